@@ -1,134 +1,92 @@
-use std::path::Path;
+pub(crate) mod sweep;
+
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Instant;
 
-use alloy::primitives::Address;
-use anyhow::Result;
+use alloy::primitives::utils::format_units;
+use alloy::primitives::{address, Address, I256};
+use anyhow::{anyhow, Result};
+use clap::Parser;
 use revm::primitives::U256;
 use shared::utils::get_env;
-use simulator::evm::EVM;
-use simulator::traits::{SimulatorContract, UniswapV3PoolContract};
-use tracing::info;
-
-#[derive(Debug, Clone)]
-struct Optimized {
-    pub optimized_in: u128,
-    pub optimized_out: u128,
-}
-
-async fn simulate(
-    rpc_https_url: &str,
-    target_block_number: u64,
+use simulator::evm::{EVM, MAINNET_WETH};
+use simulator::optimize::{
+    optimize_arbitrage_sweep, optimize_unimodal, simulate_arbitrage, Optimized, Tolerance,
+};
+use simulator::traits::{ERC4626VaultContract, UniswapV3PoolContract};
+use tracing::{info, warn};
+
+use crate::sweep::write_sweep;
+
+const DEFAULT_POOL: Address = address!("DeBead39628F93905dfc3E88003af40bf11189b0");
+
+/// Simulates the LST arbitrage against a Uniswap V3 pool at a past block.
+///
+/// Every option falls back to its env var, then to the block and pool of the
+/// original example.
+#[derive(Debug, Parser)]
+struct Args {
+    /// Block to fork, the arbitrage runs on top of it.
+    #[arg(long, env = "TARGET_BLOCK", default_value_t = 18732930)]
+    block: u64,
+
+    /// Uniswap V3 pool trading the LST against WETH.
+    #[arg(long, env = "TARGET_POOL", default_value_t = DEFAULT_POOL)]
+    pool: Address,
+
+    #[arg(long, env = "WETH_ADDRESS", default_value_t = MAINNET_WETH)]
     weth: Address,
-    target_uniswap_v3_pool: Address,
-    zfo: bool,
-    amount_in: u128,
-) -> Result<u128> {
-    let owner = Address::random();
-
-    let mut evm = EVM::new(
-        &rpc_https_url,
-        None,
-        None,
-        target_block_number,
-        weth,
-        owner,
-        U256::from(10_u64.pow(18)), // 1 ETH
-    )
-    .await;
-
-    let balance_before = evm.get_token_balance(weth, evm.simulator()).unwrap().0;
-
-    // Perform flashswap arbitrage.
-    evm.flashswap_lst_arbitrage(target_uniswap_v3_pool, zfo, U256::from(amount_in))?;
-
-    let balance_after = evm.get_token_balance(weth, evm.simulator()).unwrap().0;
-
-    let profit = balance_after.saturating_sub(balance_before);
-
-    match profit.try_into() {
-        Ok(profit_u64) => Ok(profit_u64),
-        Err(_) => {
-            info!("Profit too large for u128, returning 0");
-            Ok(0)
-        }
-    }
-}
-
-// Quadratic search for optimal amount_in.
-async fn optimize_arbitrage(
-    rpc_https_url: &str,
-    target_block_number: u64,
-    weth: Address,
-    target_uniswap_v3_pool: Address,
-    zfo: bool,
-) -> Result<Optimized> {
-    let intervals = 10;
-    let tolerance = 10_u128.pow(15); // 0.001 ETH
-    let ceiling = 10_u128.pow(18) * 1000; // 1000 ETH
-
-    let mut min_amount_in = 0; // 0 ETH
-    let mut max_amount_in = ceiling;
-    let mut optimized_in = 0;
-    let mut max_profit = 0;
 
-    while max_amount_in - min_amount_in > tolerance {
-        let step = (max_amount_in - min_amount_in) / intervals;
-        if step == 0 {
-            break;
-        }
-
-        let mut best_local_profit = 0;
-        let mut best_local_amount_in = min_amount_in;
-
-        for i in 0..=intervals {
-            let amount_in = std::cmp::min(min_amount_in + i * step, ceiling);
-
-            let s = Instant::now();
-            let profit = simulate(
-                rpc_https_url,
-                target_block_number,
-                weth,
-                target_uniswap_v3_pool,
-                zfo,
-                amount_in,
-            )
-            .await
-            .unwrap_or(0);
-            let took = s.elapsed().as_millis();
-            info!("amount_in={amount_in}, profit={profit}, took={took}ms");
-
-            if profit > best_local_profit {
-                best_local_profit = profit;
-                best_local_amount_in = amount_in;
-            }
+    /// Amount in, in wei, to simulate once instead of searching for the most
+    /// profitable one.
+    #[arg(long)]
+    amount: Option<u128>,
 
-            if profit > max_profit {
-                max_profit = profit;
-                optimized_in = amount_in;
-            }
-
-            if amount_in == ceiling {
-                break;
-            }
-        }
+    /// Tip per gas, in wei, paid on top of the basefee. Only counts in the
+    /// gas cost the profit is netted against.
+    #[arg(long, env = "PRIORITY_FEE", default_value_t = 0)]
+    priority_fee: u128,
 
-        if best_local_amount_in == min_amount_in {
-            min_amount_in = best_local_amount_in;
-            max_amount_in = std::cmp::min(best_local_amount_in + step, ceiling);
-        } else if best_local_amount_in == max_amount_in {
-            min_amount_in = max_amount_in.saturating_sub(step);
-            // NB: Intentionally leave max_amount_in unchanged.
-        } else {
-            min_amount_in = best_local_amount_in.saturating_sub(step);
-            max_amount_in = std::cmp::min(best_local_amount_in + step, ceiling);
-        }
-    }
+    /// Writes every amount evaluated by the search to this CSV file, to check
+    /// it converged on the max.
+    #[arg(long, env = "SWEEP_OUTPUT")]
+    output: Option<PathBuf>,
+}
 
-    let optimized_in: u128 = optimized_in.try_into().unwrap_or(0);
-    let optimized_out: u128 = max_profit.try_into().unwrap_or(0);
+// Searches both directions of the vault path: deposit and sell the shares on
+// the pool, or buy the shares on the pool and redeem them. Only quotes, so the
+// fork is left untouched.
+fn optimize_vault_arbitrage(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    vault: Address,
+) -> Vec<(bool, Optimized)> {
+    let tolerance = 10_u128.pow(15); // 0.001 ETH
+    let ceiling = 10_u128.pow(18) * 1000; // 1000 ETH
 
-    Ok(Optimized { optimized_in, optimized_out })
+    [true, false]
+        .into_iter()
+        .map(|deposit| {
+            let optimized = optimize_unimodal(
+                |amount_in| {
+                    let profit = evm
+                        .vault_arbitrage(pool, vault, deposit, U256::from(amount_in))
+                        .map(|quote| quote.profit.max(I256::ZERO).into_raw())
+                        .unwrap_or_else(|e| {
+                            info!("amount_in={amount_in}, vault quote failed. error={e:?}");
+                            U256::ZERO
+                        });
+                    let profit = profit.try_into().unwrap_or(0);
+                    info!("deposit={deposit}, amount_in={amount_in}, profit={profit}");
+                    profit
+                },
+                0,
+                ceiling,
+                tolerance,
+            );
+            (deposit, optimized)
+        })
+        .collect()
 }
 
 #[tokio::main]
@@ -136,9 +94,12 @@ async fn main() -> Result<()> {
     // Load environment variables.
     dotenv::dotenv().ok();
 
+    // Parsed after the env is loaded, options fall back to it.
+    let args = Args::parse();
+
     // Setup tracing.
     let log_dir = Path::new("logs");
-    let _guard = shared::logging::setup_tracing(Some(&log_dir), Some("lst-mev.log"));
+    let guard = shared::logging::setup_tracing(Some(&log_dir), Some("lst-mev.log"));
 
     info!("Starting LST MEV simulation");
 
@@ -150,42 +111,114 @@ async fn main() -> Result<()> {
         default_panic(panic_info);
     }));
 
-    let rpc_https_url = get_env("RPC_HTTPS_URL");
-    info!("RPC HTTPS URL: {}", rpc_https_url);
-
-    let target_block_number = 18732930;
+    let target_block_number = args.block;
     info!("Target block number: {}", target_block_number);
 
-    let weth = Address::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+    let target_uniswap_v3_pool = args.pool;
+    info!("Target pool: {}", target_uniswap_v3_pool);
 
-    let target_uniswap_v3_pool =
-        Address::from_str("0xDeBead39628F93905dfc3E88003af40bf11189b0").unwrap();
+    let mut evm = EVM::from_env_with_weth(target_block_number, args.weth).await?;
+    let weth = evm.weth();
 
-    let owner = Address::random();
+    // Keep what was fetched from the node when interrupted, if `RPC_CACHE_DIR`
+    // is set, see `EVM::from_env`.
+    let backend = evm.backend().clone();
+    shared::shutdown::on_ctrl_c(move || {
+        backend.flush_cache();
+        drop(guard);
+    });
 
-    let mut evm = EVM::new(
-        &rpc_https_url,
-        None,
-        None,
-        target_block_number,
-        weth,
-        owner,
-        U256::from(10_u64.pow(18)), // 1 ETH
-    )
-    .await;
+    let zfo = evm.zero_for_one(target_uniswap_v3_pool, weth)?;
 
-    let token0 = evm.token0(target_uniswap_v3_pool).unwrap();
-    let zfo = token0 == weth;
+    // 0.1% of the amount in, never finer than 0.001 ETH.
+    let tolerance = Tolerance::relative(10_u128.pow(15), 1_000);
+    let ceiling = 10_u128.pow(18) * 1000; // 1000 ETH
 
-    let optimized =
-        optimize_arbitrage(&rpc_https_url, target_block_number, weth, target_uniswap_v3_pool, zfo)
-            .await
-            .unwrap();
+    // The profit is read from WETH balances, a token taxing transfers makes it
+    // look larger than what can actually be taken out.
+    let (token0, token1) = evm.pool_tokens(target_uniswap_v3_pool)?;
+    let lst = if zfo { token1 } else { token0 };
+    let fee_on_transfer = evm.is_fee_on_transfer(lst).unwrap_or_else(|e| {
+        warn!("Failed to check fee on transfer, assuming none. token={}, error={:?}", lst, e);
+        false
+    });
+
+    if fee_on_transfer {
+        warn!("Skipping pool, {} is a fee-on-transfer token", lst);
+    } else if let Some(amount_in) = args.amount {
+        let result = simulate_arbitrage(
+            &mut evm,
+            target_uniswap_v3_pool,
+            zfo,
+            amount_in,
+            args.priority_fee,
+        )?;
+        info!("Simulated: {:?}", result);
+
+        let decimals = evm.token_decimals(weth)?;
+        let symbol = evm.token_symbol(weth)?;
+        info!("Amount in: {} {}", format_units(result.amount_in, decimals)?, symbol);
+        info!("Net profit: {} {}", format_units(result.net_profit, decimals)?, symbol);
+    } else {
+        let (result, sweep) = optimize_arbitrage_sweep(
+            &mut evm,
+            target_uniswap_v3_pool,
+            zfo,
+            0,
+            ceiling,
+            tolerance,
+            args.priority_fee,
+        );
+
+        if let Some(output) = &args.output {
+            match write_sweep(output, target_block_number, &sweep) {
+                Ok(()) => info!("Wrote {} evaluated amounts to {}", sweep.len(), output.display()),
+                Err(e) => warn!("Failed to write sweep to {}. error={:?}", output.display(), e),
+            }
+        }
+
+        match result {
+            Some(result) => {
+                info!("Optimized: {:?}", result);
+
+                let decimals = evm.token_decimals(weth)?;
+                let symbol = evm.token_symbol(weth)?;
+                info!(
+                    "Optimized amount in: {} {}",
+                    format_units(result.amount_in, decimals)?,
+                    symbol
+                );
+                info!(
+                    "Optimized gas cost: {} {}",
+                    format_units(result.gas_cost_wei, decimals)?,
+                    symbol
+                );
+                info!(
+                    "Optimized net profit: {} {}",
+                    format_units(result.net_profit, decimals)?,
+                    symbol
+                );
+            }
+            None => info!("No amount in is profitable after gas"),
+        }
+    }
+
+    // Second topology: an ERC4626 vault against a pool trading its shares.
+    if let Ok(vault) = std::env::var("TARGET_VAULT") {
+        let vault = Address::from_str(&vault)
+            .map_err(|e| anyhow!("invalid TARGET_VAULT={vault}. error={e:?}"))?;
+        let vault_pool = get_env("TARGET_VAULT_POOL");
+        let vault_pool = Address::from_str(&vault_pool)
+            .map_err(|e| anyhow!("invalid TARGET_VAULT_POOL={vault_pool}. error={e:?}"))?;
+        info!("Target vault: {}, pool: {}", vault, vault_pool);
 
-    info!("Optimized: {:?}", optimized);
+        // Reuse the warm fork, without what the first search committed.
+        evm.reset()?;
 
-    info!("Optimized amount in: {}", optimized.optimized_in);
-    info!("Optimized profit: {}", optimized.optimized_out);
+        for (deposit, optimized) in optimize_vault_arbitrage(&mut evm, vault_pool, vault) {
+            info!("Vault optimized: deposit={}, {:?}", deposit, optimized);
+        }
+    }
 
     Ok(())
 }
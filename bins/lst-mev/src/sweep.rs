@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use anyhow::Result;
+use csv::Writer;
+use simulator::optimize::SweepPoint;
+
+/// Writes every amount evaluated by the optimizer as CSV, in evaluation
+/// order. Failed simulations have empty profit columns.
+pub(crate) fn write_sweep(path: &Path, block_number: u64, sweep: &[SweepPoint]) -> Result<()> {
+    let mut writer = Writer::from_path(path)?;
+
+    writer.write_record([
+        "block_number",
+        "amount_in",
+        "gross_profit",
+        "gas_used",
+        "gas_cost_wei",
+        "net_profit",
+        "took_ms",
+    ])?;
+
+    for point in sweep {
+        let [gross_profit, gas_used, gas_cost_wei, net_profit] = match &point.result {
+            Some(result) => [
+                result.gross_profit.to_string(),
+                result.gas_used.to_string(),
+                result.gas_cost_wei.to_string(),
+                result.net_profit.to_string(),
+            ],
+            None => Default::default(),
+        };
+
+        writer.write_record([
+            block_number.to_string(),
+            point.amount_in.to_string(),
+            gross_profit,
+            gas_used,
+            gas_cost_wei,
+            net_profit,
+            point.took_ms.to_string(),
+        ])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use simulator::optimize::ArbResult;
+
+    use super::*;
+
+    #[test]
+    fn sweep_rows_follow_the_header() {
+        let path = std::env::temp_dir().join(format!("{}-sweep.csv", std::process::id()));
+        let sweep = [
+            SweepPoint {
+                amount_in: 1_000,
+                result: Some(ArbResult {
+                    amount_in: 1_000,
+                    gross_profit: 50,
+                    gas_used: 21_000,
+                    gas_cost_wei: 60,
+                    net_profit: -10,
+                    block_number: 18732930,
+                }),
+                took_ms: 7,
+            },
+            SweepPoint { amount_in: 2_000, result: None, took_ms: 3 },
+        ];
+
+        write_sweep(&path, 18732930, &sweep).unwrap();
+        let written = fs::read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<String> = written.unwrap().lines().map(String::from).collect();
+        assert_eq!(
+            lines,
+            [
+                "block_number,amount_in,gross_profit,gas_used,gas_cost_wei,net_profit,took_ms",
+                "18732930,1000,50,21000,60,-10,7",
+                "18732930,2000,,,,,3",
+            ]
+        );
+    }
+}
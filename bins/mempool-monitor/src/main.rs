@@ -1,23 +1,40 @@
 pub(crate) mod pool;
 pub(crate) mod utils;
 
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use alloy::network::Ethereum;
+use alloy::primitives::{Address, Log, LogData, TxHash, U256};
 use alloy::providers::ext::DebugApi;
 use alloy::providers::Provider;
-use alloy::sol_types::SolEvent;
+use alloy::transports::Transport;
 use alloy_rpc_types::transaction::TransactionRequest;
 use alloy_rpc_types_eth::BlockNumberOrTag;
 use alloy_rpc_types_trace::geth::{
     CallConfig, CallFrame, CallLogFrame, GethDebugTracingCallOptions, GethTrace,
 };
 use anyhow::Result;
-use futures_util::StreamExt;
-use shared::utils::{get_env, get_ws_provider};
+use futures_util::stream::{self, StreamExt};
+use shared::utils::{get_env, get_env_or, get_ws_provider};
 use simulator::abi;
-use tracing::info;
+use simulator::events::{decode_known_event, DecodedEvent};
+use simulator::evm::MAINNET_WETH;
+use simulator::path::PathFinder;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{info, warn};
 
-use crate::utils::load_pools;
+use crate::pool::Pool;
+use crate::utils::{load_pools, reconnect, save_pool_cache, watch_new_pools};
+
+/// Pending txs between two logs of the drop rate.
+const DROP_RATE_INTERVAL: u64 = 1000;
+
+/// How long the shutdown save waits for a writer to release the pools.
+const SHUTDOWN_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
 
 fn collect_logs(frame: &CallFrame) -> Vec<CallLogFrame> {
     std::iter::once(frame)
@@ -30,6 +47,177 @@ fn collect_logs(frame: &CallFrame) -> Vec<CallLogFrame> {
         .collect()
 }
 
+/// Logs emitted anywhere in the call tree, in emission order. Logs missing
+/// their address, topics or data are skipped, logs without topics are kept.
+fn frame_logs(frame: &CallFrame) -> Vec<Log> {
+    collect_logs(frame)
+        .iter()
+        .filter_map(|log| {
+            Some(Log {
+                address: log.address?,
+                data: LogData::new(log.topics.clone()?, log.data.clone()?)?,
+            })
+        })
+        .collect()
+}
+
+/// Refreshes the live state of a known pool touched by a pending swap.
+async fn refresh_pool<P, T>(pools: &RwLock<HashMap<Address, Pool>>, provider: &P, address: Address)
+where
+    P: Provider<T>,
+    T: Transport + Clone,
+{
+    // The refresh is an RPC round trip, the lock is only held to copy the
+    // pool out and to write it back.
+    let Some(mut pool) = pools.read().await.get(&address).cloned() else {
+        return;
+    };
+
+    if let Err(e) = pool.refresh(provider).await {
+        info!("Failed to refresh pool {address}: {e}");
+        return;
+    }
+    info!("Pool state: fee={}bps, {:?}", pool.fee_bps(), pool);
+
+    // Skipped if the pool was dropped meanwhile, e.g. reorged out.
+    if let Some(cached) = pools.write().await.get_mut(&address) {
+        *cached = pool;
+    }
+}
+
+/// Copies the pools out for the shutdown save, `None` if a writer holds the
+/// lock for longer than `timeout`.
+fn snapshot_pools(pools: &RwLock<HashMap<Address, Pool>>, timeout: Duration) -> Option<Vec<Pool>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Ok(pools) = pools.try_read() {
+            return Some(pools.values().cloned().collect());
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Applies a V3 mint or burn to the cached pool, see
+/// `Pool::apply_liquidity_delta`.
+async fn apply_liquidity_event(
+    pools: &RwLock<HashMap<Address, Pool>>,
+    address: Address,
+    tick_lower: i32,
+    tick_upper: i32,
+    delta: i128,
+) {
+    if let Some(pool) = pools.write().await.get_mut(&address) {
+        pool.apply_liquidity_delta(tick_lower, tick_upper, delta);
+        info!("Pool liquidity: {:?}", pool.liquidity);
+    }
+}
+
+/// Traces a pending transaction on top of the latest block and returns the
+/// logs it would emit, empty if it is gone or the trace fails.
+async fn trace_pending_tx<P, T>(provider: &P, tx_hash: TxHash) -> Vec<Log>
+where
+    P: Provider<T> + DebugApi<Ethereum, T>,
+    T: Transport + Clone,
+{
+    let Ok(Some(tx)) = provider.get_transaction_by_hash(tx_hash).await else {
+        return vec![];
+    };
+    println!("\nTx hash: {}", tx_hash);
+
+    let trace_tx = TransactionRequest::from_transaction(tx);
+
+    let mut config = GethDebugTracingCallOptions::default();
+
+    let mut call_config = CallConfig::default();
+    call_config = call_config.with_log();
+
+    config.tracing_options.tracer =
+        Some(alloy_rpc_types_trace::geth::GethDebugTracerType::BuiltInTracer(
+            alloy_rpc_types_trace::geth::GethDebugBuiltInTracerType::CallTracer,
+        ));
+
+    config.tracing_options.tracer_config = serde_json::to_value(call_config).unwrap().into();
+
+    let Ok(GethTrace::CallTracer(frame)) = provider
+        .debug_trace_call(trace_tx, BlockNumberOrTag::Latest.into(), config)
+        .await
+    else {
+        return vec![];
+    };
+
+    frame_logs(&frame)
+}
+
+/// Updates the cached pools from the logs of one pending transaction, in the
+/// order they were emitted.
+async fn handle_logs<P, T>(pools: &RwLock<HashMap<Address, Pool>>, provider: &P, logs: Vec<Log>)
+where
+    P: Provider<T>,
+    T: Transport + Clone,
+{
+    for log in logs.iter() {
+        match decode_known_event(log) {
+            Some(DecodedEvent::Transfer(event)) => {
+                info!("Transfer: {:?}", event);
+            }
+            Some(DecodedEvent::CrocSwap(event)) => {
+                info!("Croc: {:?}", event);
+            }
+            Some(DecodedEvent::V2Swap(event)) => {
+                info!("V2: {:?}", event);
+
+                refresh_pool(pools, provider, log.address).await;
+            }
+            Some(DecodedEvent::V3Swap(event)) => {
+                info!("V3: {:?}", event);
+
+                refresh_pool(pools, provider, log.address).await;
+            }
+            // Liquidity events are applied to the cached state, which then
+            // reflects the pending transaction.
+            Some(DecodedEvent::V2Sync(event)) => {
+                info!("V2 sync: {:?}", event);
+
+                if let Some(pool) = pools.write().await.get_mut(&log.address) {
+                    pool.apply_sync(U256::from(event.reserve0), U256::from(event.reserve1));
+                }
+            }
+            Some(DecodedEvent::V3Mint(event)) => {
+                info!("V3 mint: {:?}", event);
+
+                apply_liquidity_event(
+                    pools,
+                    log.address,
+                    event.tickLower.as_i32(),
+                    event.tickUpper.as_i32(),
+                    event.amount as i128,
+                )
+                .await;
+            }
+            Some(DecodedEvent::V3Burn(event)) => {
+                info!("V3 burn: {:?}", event);
+
+                apply_liquidity_event(
+                    pools,
+                    log.address,
+                    event.tickLower.as_i32(),
+                    event.tickUpper.as_i32(),
+                    -(event.amount as i128),
+                )
+                .await;
+            }
+            Some(DecodedEvent::CrocWarmCmd(event)) => {
+                info!("Croc liquidity: {:?}", event);
+            }
+            None => {}
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load environment variables.
@@ -37,7 +225,7 @@ async fn main() -> Result<()> {
 
     // Setup tracing.
     let log_dir = Path::new("logs");
-    let _guard = shared::logging::setup_tracing(Some(&log_dir), Some("mempool-monitor.log"));
+    let guard = shared::logging::setup_tracing(Some(&log_dir), Some("mempool-monitor.log"));
 
     info!("Starting mempool monitor");
 
@@ -52,91 +240,178 @@ async fn main() -> Result<()> {
     let rpc_wss_url = get_env("RPC_WSS_URL");
     info!("RPC WSS URL: {}", rpc_wss_url);
 
-    let provider = get_ws_provider(&rpc_wss_url).await;
+    let mut provider = get_ws_provider(&rpc_wss_url).await;
 
     // Load all Uniswap V2, V3 pools.
-    let pools = load_pools(&rpc_wss_url, 0).await.unwrap();
-    info!("Loaded {} pools", pools.len());
+    let mut pools = load_pools(&rpc_wss_url, 0).await.unwrap();
+    let pairs: HashSet<_> = pools.iter().map(Pool::pair_key).collect();
+    info!("Loaded {} pools across {} token pairs", pools.len(), pairs.len());
+
+    if let Err(e) = Pool::batch_refresh(&provider, &mut pools).await {
+        warn!("Failed to batch refresh pool state, refreshing pools one by one: {e}");
 
-    let sub = provider.subscribe_pending_transactions().await?;
-    let mut stream = sub.into_stream();
+        let concurrency: usize = get_env_or("REFRESH_CONCURRENCY", "16").parse()?;
+        let summary = Pool::refresh_all(&provider, &mut pools, concurrency).await;
+        info!("Refreshed {} pools, {} failed", summary.succeeded, summary.failed);
+    }
 
-    while let Some(tx_hash) = stream.next().await {
-        if let Ok(Some(tx)) = provider.get_transaction_by_hash(tx_hash).await {
-            println!("\nTx hash: {}", tx_hash);
+    // Off-chain screening only, the paths still need an EVM simulation.
+    let finder = PathFinder::new(MAINNET_WETH, pools.iter().filter_map(Pool::path_pool));
+    let ceiling = 10_u128.pow(18) * 100; // 100 ETH
+    let tolerance = 10_u128.pow(15); // 0.001 ETH
+    let paths = finder.find(3, ceiling, tolerance);
+    info!("Found {} profitable WETH cycles", paths.len());
+    for path in paths.iter().take(5) {
+        info!("Arb path: {:?}", path);
+    }
 
-            let trace_tx = TransactionRequest::from_transaction(tx);
+    let pools: HashMap<Address, Pool> = pools.into_iter().map(|pool| (pool.id, pool)).collect();
+    let pools = Arc::new(RwLock::new(pools));
 
-            let mut config = GethDebugTracingCallOptions::default();
+    // Pools created from now on are added by the watcher.
+    tokio::spawn(watch_new_pools(rpc_wss_url.clone(), pools.clone()));
 
-            let mut call_config = CallConfig::default();
-            call_config = call_config.with_log();
+    let shutdown_pools = pools.clone();
+    shared::shutdown::on_ctrl_c(move || {
+        match snapshot_pools(&shutdown_pools, SHUTDOWN_LOCK_TIMEOUT) {
+            Some(pools) => match save_pool_cache(&pools) {
+                Ok(()) => info!("Saved {} pools", pools.len()),
+                Err(e) => warn!("Failed to save pools: {e}"),
+            },
+            None => warn!("Pools still locked after {SHUTDOWN_LOCK_TIMEOUT:?}, not saving them"),
+        }
+        drop(guard);
+    });
+
+    let concurrency: usize = get_env_or("TRACE_CONCURRENCY", "16").parse()?;
+    let max_backlog: usize = get_env_or("MAX_PENDING_BACKLOG", "1024").parse()?;
+    info!("Tracing {concurrency} pending txs at a time, backlog of {max_backlog}");
+
+    // Pools are kept across reconnects, only the subscription is recreated.
+    loop {
+        match provider.subscribe_pending_transactions().await {
+            Ok(sub) => {
+                let mut stream = sub.into_stream();
+
+                // Hashes are queued for the tracers, the newest are dropped
+                // while the queue is full so the monitor stays near the tip.
+                let (queue, mut backlog) = mpsc::channel(max_backlog);
+                let received = AtomicU64::new(0);
+                let dropped = AtomicU64::new(0);
+
+                let producer = async {
+                    while let Some(tx_hash) = stream.next().await {
+                        let received = received.fetch_add(1, Ordering::Relaxed) + 1;
+                        if queue.try_send(tx_hash).is_err() {
+                            dropped.fetch_add(1, Ordering::Relaxed);
+                        }
 
-            config.tracing_options.tracer =
-                Some(alloy_rpc_types_trace::geth::GethDebugTracerType::BuiltInTracer(
-                    alloy_rpc_types_trace::geth::GethDebugBuiltInTracerType::CallTracer,
-                ));
+                        if received % DROP_RATE_INTERVAL == 0 {
+                            let dropped = dropped.load(Ordering::Relaxed);
+                            info!(
+                                "Pending txs: received={received}, dropped={dropped} ({:.2}%)",
+                                dropped as f64 / received as f64 * 100.0
+                            );
+                        }
+                    }
+                    drop(queue);
+                };
 
-            config.tracing_options.tracer_config =
-                serde_json::to_value(call_config).unwrap().into();
+                let consumer = stream::poll_fn(|cx| backlog.poll_recv(cx))
+                    .map(|tx_hash| trace_pending_tx(&provider, tx_hash))
+                    .buffer_unordered(concurrency)
+                    .for_each(|logs| handle_logs(&pools, &provider, logs));
 
-            if let Ok(trace) = provider
-                .debug_trace_call(trace_tx, BlockNumberOrTag::Latest.into(), config)
-                .await
-            {
-                if let GethTrace::CallTracer(frame) = trace {
-                    let logs = collect_logs(&frame);
+                tokio::join!(producer, consumer);
 
-                    for log in logs.iter() {
-                        if let Some(topics) = &log.topics {
-                            let topic = topics[0];
+                warn!("Pending transaction stream ended");
+            }
+            Err(e) => warn!("Failed to subscribe to pending transactions: {e}"),
+        }
 
-                            let alloy_log = alloy_primitives::Log {
-                                address: log.address.unwrap(),
-                                data: alloy_primitives::LogData::new(
-                                    log.topics.clone().unwrap(),
-                                    log.data.clone().unwrap(),
-                                )
-                                .unwrap(),
-                            };
+        provider = reconnect(&rpc_wss_url).await;
+    }
+}
 
-                            match topic {
-                                abi::IERC20::Transfer::SIGNATURE_HASH => {
-                                    let transfer_log =
-                                        abi::IERC20::Transfer::decode_log(&alloy_log, false);
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, Bytes, B256};
+    use alloy_sol_types::SolEvent;
+    use simulator::abi::IERC20;
 
-                                    info!("Transfer: {:?}", transfer_log);
-                                }
+    use super::*;
 
-                                abi::CrocSwapDex::CrocSwap::SIGNATURE_HASH => {
-                                    let swap_log =
-                                        abi::CrocSwapDex::CrocSwap::decode_log(&alloy_log, false);
+    fn log_frame(address: Address, topics: Vec<B256>, data: Bytes) -> CallLogFrame {
+        CallLogFrame {
+            address: Some(address),
+            topics: Some(topics),
+            data: Some(data),
+            ..Default::default()
+        }
+    }
 
-                                    info!("Croc: {:?}", swap_log);
-                                }
+    /// An anonymous log in the top call and a `Transfer` in a nested call.
+    fn frame() -> CallFrame {
+        let token = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let from = address!("1111111111111111111111111111111111111111");
+        let to = address!("2222222222222222222222222222222222222222");
+
+        let anonymous = log_frame(token, vec![], Bytes::from(vec![1; 32]));
+        let transfer = log_frame(
+            token,
+            vec![IERC20::Transfer::SIGNATURE_HASH, from.into_word(), to.into_word()],
+            U256::from(1000).to_be_bytes_vec().into(),
+        );
+
+        CallFrame {
+            logs: vec![anonymous],
+            calls: vec![CallFrame { logs: vec![transfer], ..Default::default() }],
+            ..Default::default()
+        }
+    }
 
-                                abi::IUniswapV2Pair::Swap::SIGNATURE_HASH => {
-                                    let swap_log =
-                                        abi::IUniswapV2Pair::Swap::decode_log(&alloy_log, false);
+    #[test]
+    fn collect_logs_walks_nested_calls() {
+        let logs = collect_logs(&frame());
 
-                                    info!("V2: {:?}", swap_log);
-                                }
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].topics.as_deref(), Some(&[][..]));
+        assert_eq!(logs[1].topics.as_ref().map(Vec::len), Some(3));
+    }
 
-                                abi::IUniswapV3Pool::Swap::SIGNATURE_HASH => {
-                                    let swap_log =
-                                        abi::IUniswapV3Pool::Swap::decode_log(&alloy_log, false);
+    #[test]
+    fn logs_without_topics_are_not_decoded() {
+        let logs = frame_logs(&frame());
+        assert_eq!(logs.len(), 2);
 
-                                    info!("V3: {:?}", swap_log);
-                                }
+        let decoded = logs.iter().map(decode_known_event).collect::<Vec<_>>();
 
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-            }
+        assert!(decoded[0].is_none());
+        match &decoded[1] {
+            Some(DecodedEvent::Transfer(event)) => assert_eq!(event.value, U256::from(1000)),
+            other => panic!("expected a transfer, got {other:?}"),
         }
     }
 
-    Ok(())
+    #[test]
+    fn snapshot_waits_for_a_writer() {
+        let pools = Arc::new(RwLock::new(HashMap::new()));
+        let writer = pools.clone();
+        let guard = std::thread::spawn(move || {
+            let _guard = writer.blocking_write();
+            std::thread::sleep(Duration::from_millis(50));
+        });
+
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(snapshot_pools(&pools, Duration::from_secs(5)).map(|p| p.len()), Some(0));
+        guard.join().unwrap();
+    }
+
+    #[test]
+    fn snapshot_gives_up_on_a_stuck_writer() {
+        let pools = RwLock::new(HashMap::new());
+        let _guard = pools.try_write().unwrap();
+
+        assert!(snapshot_pools(&pools, Duration::from_millis(50)).is_none());
+    }
 }
@@ -1,15 +1,36 @@
-use alloy::primitives::Address;
+use alloy::primitives::{Address, Bytes, B256, U256};
 use alloy::rpc::types::Log;
-use alloy::sol_types::SolEvent;
-use anyhow::Result;
+use alloy::sol_types::{SolCall, SolEvent};
+use alloy::transports::Transport;
+use alloy_provider::Provider;
+use anyhow::{anyhow, bail, Result};
+use futures_util::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
+use shared::utils::multicall;
+use simulator::path::{PathPool, PoolState};
+use tracing::{info, warn};
 
 use crate::abi;
 
+/// Uniswap V2 charges a flat 0.3%, in the same units as V3 fee tiers.
+const UNISWAP_V2_FEE: u64 = 3000;
+
+/// Curve fees have 10 decimals, 4000000 = 0.04%.
+const CURVE_FEE_DENOMINATOR: u64 = 10_000;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Venue {
     UniswapV2,
     UniswapV3,
+    Curve,
+    Balancer,
+}
+
+/// Outcome of `Pool::refresh_all`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RefreshSummary {
+    pub succeeded: usize,
+    pub failed: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,9 +38,333 @@ pub struct Pool {
     pub id: Address,
     pub token0: Address,
     pub token1: Address,
+    /// Swap fee in hundredths of a bip (3000 = 0.3%) for every venue. V3 pools
+    /// store the fee tier exactly as emitted (100, 500, 3000 or 10000).
     pub fee: u64,
+    /// Only set for V3 pools.
+    #[serde(default)]
+    pub tick_spacing: Option<i32>,
     pub venue: Venue,
     pub block: u64,
+    /// Hash of `block`, to tell whether the pool was reorged out. Missing
+    /// from pools cached before it was recorded.
+    #[serde(default)]
+    pub block_hash: Option<B256>,
+    /// Every coin of Curve and Balancer pools, which can hold more than two.
+    /// `token0` and `token1` are the first two. Empty for Uniswap pools, use
+    /// `tokens()`. Left out when empty so Uniswap pools still fit in a CSV.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<Address>,
+    /// Only set for Balancer pools, the id the Vault knows them by.
+    #[serde(default)]
+    pub balancer_pool_id: Option<B256>,
+    /// Live state, filled by `refresh`. V2 only.
+    #[serde(default)]
+    pub reserve0: Option<U256>,
+    #[serde(default)]
+    pub reserve1: Option<U256>,
+    /// Live state, filled by `refresh`. V3 only.
+    #[serde(default)]
+    pub sqrt_price_x96: Option<U256>,
+    #[serde(default)]
+    pub liquidity: Option<u128>,
+    #[serde(default)]
+    pub tick: Option<i32>,
+}
+
+impl Pool {
+    /// Token pair sorted by address, the same for every venue and fee tier.
+    pub fn pair_key(&self) -> (Address, Address) {
+        if self.token0 < self.token1 {
+            (self.token0, self.token1)
+        } else {
+            (self.token1, self.token0)
+        }
+    }
+
+    /// Every token of the pool.
+    pub fn tokens(&self) -> Vec<Address> {
+        if self.tokens.is_empty() {
+            vec![self.token0, self.token1]
+        } else {
+            self.tokens.clone()
+        }
+    }
+
+    /// Sets the coins of a pool, `token0` and `token1` being the first two.
+    pub fn set_tokens(&mut self, tokens: Vec<Address>) {
+        self.token0 = tokens.first().copied().unwrap_or_default();
+        self.token1 = tokens.get(1).copied().unwrap_or_default();
+        self.tokens = tokens;
+    }
+
+    /// Curve factory events don't carry the pool address, reads it from the
+    /// factory that emitted `log` at the block of the event. Metapools also
+    /// read their coins, the event only has the base pool.
+    pub async fn resolve_curve<P, T>(&mut self, provider: &P, log: &Log) -> Result<()>
+    where
+        P: Provider<T>,
+        T: Transport + Clone,
+    {
+        let block = log
+            .block_number
+            .ok_or_else(|| anyhow!("log has no block number"))?;
+        let factory = abi::ICurveFactory::new(log.address(), provider);
+
+        if self.tokens.len() == 1 {
+            // `token1` holds the base pool until the metapool is found, the
+            // factory indexes metapools by their coin and the base pool coins.
+            let base_pool = abi::ICurveV2Pool::new(self.token1, provider);
+            let base_coin = base_pool
+                .coins(U256::ZERO)
+                .block(block.into())
+                .call()
+                .await?
+                ._0;
+            self.id = factory
+                .find_pool_for_coins(self.token0, base_coin)
+                .block(block.into())
+                .call()
+                .await?
+                ._0;
+
+            let pool = abi::ICurveV2Pool::new(self.id, provider);
+            let lp_token = pool
+                .coins(U256::from(1))
+                .block(block.into())
+                .call()
+                .await?
+                ._0;
+            self.set_tokens(vec![self.token0, lp_token]);
+        } else {
+            self.id = factory
+                .find_pool_for_coins(self.token0, self.token1)
+                .block(block.into())
+                .call()
+                .await?
+                ._0;
+        }
+
+        if self.id == Address::ZERO {
+            bail!("no Curve pool for coins {:?} at block {block}", self.tokens);
+        }
+
+        Ok(())
+    }
+
+    /// Input of the path search, `None` until the live state is loaded.
+    pub fn path_pool(&self) -> Option<PathPool> {
+        let state = match self.venue {
+            Venue::UniswapV2 => {
+                PoolState::V2 { reserve0: self.reserve0?, reserve1: self.reserve1? }
+            }
+            Venue::UniswapV3 => {
+                PoolState::V3 { sqrt_price_x96: self.sqrt_price_x96?, liquidity: self.liquidity? }
+            }
+            // No off-chain quoting for these yet.
+            Venue::Curve | Venue::Balancer => return None,
+        };
+
+        Some(PathPool {
+            address: self.id,
+            token0: self.token0,
+            token1: self.token1,
+            fee: self.fee.try_into().ok()?,
+            state,
+        })
+    }
+
+    /// Swap fee in basis points (30.0 = 0.3%).
+    pub fn fee_bps(&self) -> f64 {
+        self.fee as f64 / 100.0
+    }
+
+    /// Reads the current reserves (V2) or `slot0` and liquidity (V3) of the
+    /// pool at the latest block. Curve and Balancer pools have no live state
+    /// yet.
+    pub async fn refresh<P, T>(&mut self, provider: &P) -> Result<()>
+    where
+        P: Provider<T>,
+        T: Transport + Clone,
+    {
+        match self.venue {
+            Venue::UniswapV2 => {
+                let pair = abi::IUniswapV2Pair::new(self.id, provider);
+                let reserves = pair.getReserves().call().await?;
+
+                self.reserve0 = Some(U256::from(reserves.reserve0));
+                self.reserve1 = Some(U256::from(reserves.reserve1));
+            }
+            Venue::UniswapV3 => {
+                let pool = abi::IUniswapV3Pool::new(self.id, provider);
+                let slot0 = pool.slot0().call().await?;
+                let liquidity = pool.liquidity().call().await?._0;
+
+                self.sqrt_price_x96 = Some(U256::from(slot0.sqrtPriceX96));
+                self.liquidity = Some(liquidity);
+                self.tick = Some(slot0.tick.try_into()?);
+            }
+            Venue::Curve | Venue::Balancer => {}
+        }
+
+        Ok(())
+    }
+
+    /// Sets the reserves from a V2 `Sync` event.
+    pub fn apply_sync(&mut self, reserve0: U256, reserve1: U256) {
+        self.reserve0 = Some(reserve0);
+        self.reserve1 = Some(reserve1);
+    }
+
+    /// Applies a V3 `Mint` (positive `delta`) or `Burn` (negative) to the
+    /// active liquidity. Only positions around the current tick count, the
+    /// pool must have been refreshed first.
+    pub fn apply_liquidity_delta(&mut self, tick_lower: i32, tick_upper: i32, delta: i128) {
+        let (Some(tick), Some(liquidity)) = (self.tick, self.liquidity) else {
+            return;
+        };
+
+        if tick_lower <= tick && tick < tick_upper {
+            self.liquidity = liquidity.checked_add_signed(delta);
+        }
+    }
+
+    /// Same as `refresh` for many pools at once, batched through Multicall3.
+    ///
+    /// A pool whose calls fail keeps its previous state.
+    pub async fn batch_refresh<P, T>(provider: &P, pools: &mut [Pool]) -> Result<()>
+    where
+        P: Provider<T>,
+        T: Transport + Clone,
+    {
+        let calls: Vec<(Address, Bytes)> = pools
+            .iter()
+            .flat_map(|pool| match pool.venue {
+                Venue::UniswapV2 => {
+                    vec![(pool.id, abi::IUniswapV2Pair::getReservesCall {}.abi_encode().into())]
+                }
+                Venue::UniswapV3 => vec![
+                    (pool.id, abi::IUniswapV3Pool::slot0Call {}.abi_encode().into()),
+                    (pool.id, abi::IUniswapV3Pool::liquidityCall {}.abi_encode().into()),
+                ],
+                Venue::Curve | Venue::Balancer => vec![],
+            })
+            .collect();
+
+        let results = multicall(provider, &calls).await?;
+        let mut results = results.iter();
+
+        for pool in pools.iter_mut() {
+            let refreshed = match pool.venue {
+                Venue::UniswapV2 => {
+                    let data = results.next().cloned().unwrap_or_default();
+                    pool.apply_reserves(&data)
+                }
+                Venue::UniswapV3 => {
+                    let slot0 = results.next().cloned().unwrap_or_default();
+                    let liquidity = results.next().cloned().unwrap_or_default();
+                    pool.apply_slot0(&slot0, &liquidity)
+                }
+                Venue::Curve | Venue::Balancer => Ok(()),
+            };
+
+            if let Err(e) = refreshed {
+                info!("Failed to refresh pool {}: {e}", pool.id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Same as `refresh` for many pools, at most `concurrency` at a time. A
+    /// pool that fails keeps its previous state and is counted as failed.
+    /// Curve and Balancer pools are skipped.
+    ///
+    /// Slower than `batch_refresh` but one failing call can't fail the whole
+    /// set, and the cap keeps the provider under its rate limit.
+    pub async fn refresh_all<P, T>(
+        provider: &P,
+        pools: &mut [Pool],
+        concurrency: usize,
+    ) -> RefreshSummary
+    where
+        P: Provider<T>,
+        T: Transport + Clone,
+    {
+        let summary = stream::iter(
+            pools
+                .iter_mut()
+                .filter(|pool| matches!(pool.venue, Venue::UniswapV2 | Venue::UniswapV3)),
+        )
+        .map(|pool| async move {
+            let result = pool.refresh(provider).await;
+            (pool.id, result)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .fold(RefreshSummary::default(), |mut summary, (id, result)| async move {
+            match result {
+                Ok(()) => summary.succeeded += 1,
+                Err(e) => {
+                    info!("Failed to refresh pool {id}: {e}");
+                    summary.failed += 1;
+                }
+            }
+            summary
+        })
+        .await;
+
+        if summary.failed > 0 {
+            warn!(
+                "Failed to refresh {} of {} pools",
+                summary.failed,
+                summary.succeeded + summary.failed
+            );
+        }
+
+        summary
+    }
+
+    fn apply_reserves(&mut self, data: &Bytes) -> Result<()> {
+        let reserves = abi::IUniswapV2Pair::getReservesCall::abi_decode_returns(data, false)?;
+
+        self.reserve0 = Some(U256::from(reserves.reserve0));
+        self.reserve1 = Some(U256::from(reserves.reserve1));
+
+        Ok(())
+    }
+
+    fn apply_slot0(&mut self, slot0: &Bytes, liquidity: &Bytes) -> Result<()> {
+        let slot0 = abi::IUniswapV3Pool::slot0Call::abi_decode_returns(slot0, false)?;
+        let liquidity =
+            abi::IUniswapV3Pool::liquidityCall::abi_decode_returns(liquidity, false)?._0;
+
+        self.sqrt_price_x96 = Some(U256::from(slot0.sqrtPriceX96));
+        self.liquidity = Some(liquidity);
+        self.tick = Some(slot0.tick.try_into()?);
+
+        Ok(())
+    }
+
+    /// Pool decoded from `log` before its address or tokens are known.
+    fn unresolved(venue: Venue, log: &Log) -> Self {
+        Pool {
+            id: Address::ZERO,
+            token0: Address::ZERO,
+            token1: Address::ZERO,
+            fee: 0,
+            tick_spacing: None,
+            venue,
+            block: log.block_number.unwrap_or(0),
+            block_hash: log.block_hash,
+            tokens: vec![],
+            balancer_pool_id: None,
+            reserve0: None,
+            reserve1: None,
+            sqrt_price_x96: None,
+            liquidity: None,
+            tick: None,
+        }
+    }
 }
 
 impl TryFrom<&Log> for Pool {
@@ -35,9 +380,18 @@ impl TryFrom<&Log> for Pool {
                     id: pair_log.data.pair,
                     token0: pair_log.data.token0,
                     token1: pair_log.data.token1,
-                    fee: 3000, // uniswap v2 style (0.3%)
+                    fee: UNISWAP_V2_FEE,
+                    tick_spacing: None,
                     venue: Venue::UniswapV2,
                     block: log.block_number.unwrap_or(0),
+                    block_hash: log.block_hash,
+                    tokens: vec![],
+                    balancer_pool_id: None,
+                    reserve0: None,
+                    reserve1: None,
+                    sqrt_price_x96: None,
+                    liquidity: None,
+                    tick: None,
                 })
             }
             abi::IUniswapV3Factory::PoolCreated::SIGNATURE_HASH => {
@@ -47,11 +401,58 @@ impl TryFrom<&Log> for Pool {
                     token0: pool_log.data.token0,
                     token1: pool_log.data.token1,
                     fee: pool_log.data.fee.try_into()?,
+                    tick_spacing: Some(pool_log.data.tickSpacing.try_into()?),
                     venue: Venue::UniswapV3,
                     block: log.block_number.unwrap_or(0),
+                    block_hash: log.block_hash,
+                    tokens: vec![],
+                    balancer_pool_id: None,
+                    reserve0: None,
+                    reserve1: None,
+                    sqrt_price_x96: None,
+                    liquidity: None,
+                    tick: None,
                 })
             }
-            _ => anyhow::bail!("Unknown event signature: {topic}"),
+            // The pool address is filled in by `resolve_curve`.
+            abi::ICurveFactory::PlainPoolDeployed::SIGNATURE_HASH => {
+                let pool_log =
+                    abi::ICurveFactory::PlainPoolDeployed::decode_log(&log.inner, false)?;
+                let coins: Vec<Address> = pool_log
+                    .data
+                    .coins
+                    .into_iter()
+                    .filter(|coin| !coin.is_zero())
+                    .collect();
+
+                let mut pool = Pool::unresolved(Venue::Curve, log);
+                pool.fee = (pool_log.data.fee / U256::from(CURVE_FEE_DENOMINATOR)).try_into()?;
+                pool.set_tokens(coins);
+                Ok(pool)
+            }
+            // `tokens` only holds the coin and `token1` the base pool until
+            // `resolve_curve` reads the base pool LP token.
+            abi::ICurveFactory::MetaPoolDeployed::SIGNATURE_HASH => {
+                let pool_log = abi::ICurveFactory::MetaPoolDeployed::decode_log(&log.inner, false)?;
+
+                let mut pool = Pool::unresolved(Venue::Curve, log);
+                pool.fee = (pool_log.data.fee / U256::from(CURVE_FEE_DENOMINATOR)).try_into()?;
+                pool.token0 = pool_log.data.coin;
+                pool.token1 = pool_log.data.base_pool;
+                pool.tokens = vec![pool_log.data.coin];
+                Ok(pool)
+            }
+            // Tokens come with the `TokensRegistered` event that follows, and
+            // the fee isn't known until the pool is read.
+            abi::IBalancerVault::PoolRegistered::SIGNATURE_HASH => {
+                let pool_log = abi::IBalancerVault::PoolRegistered::decode_log(&log.inner, false)?;
+
+                let mut pool = Pool::unresolved(Venue::Balancer, log);
+                pool.id = pool_log.data.poolAddress;
+                pool.balancer_pool_id = Some(pool_log.data.poolId);
+                Ok(pool)
+            }
+            _ => bail!("Unknown event signature: {topic}"),
         }
     }
 }
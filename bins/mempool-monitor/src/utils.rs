@@ -1,17 +1,102 @@
-use std::fs;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use alloy::primitives::{Address, B256};
+use alloy::providers::RootProvider;
+use alloy::pubsub::PubSubFrontend;
+use alloy::rpc::types::{BlockTransactionsKind, Filter, Log};
 use alloy::sol_types::SolEvent;
+use alloy::transports::Transport;
 use alloy_provider::Provider;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use csv::{Reader, Writer};
-use shared::utils::{get_block_range, get_logs, get_ws_provider};
-use tracing::info;
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use shared::utils::{get_block_range, get_logs, get_ws_provider, try_get_ws_provider};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
 
 use crate::abi;
-use crate::pool::Pool;
+use crate::pool::{Pool, Venue};
+
+const CACHE_DIR: &str = "cache";
+const POOLS_CACHE_FILE: &str = "pools.jsonl";
+
+/// Events creating a pool. `TokensRegistered` follows `PoolRegistered` and
+/// carries the Balancer pool tokens.
+const POOL_EVENTS: [&str; 6] = [
+    abi::IUniswapV2Factory::PairCreated::SIGNATURE,
+    abi::IUniswapV3Factory::PoolCreated::SIGNATURE,
+    abi::ICurveFactory::PlainPoolDeployed::SIGNATURE,
+    abi::ICurveFactory::MetaPoolDeployed::SIGNATURE,
+    abi::IBalancerVault::PoolRegistered::SIGNATURE,
+    abi::IBalancerVault::TokensRegistered::SIGNATURE,
+];
+
+/// Decodes the pools created in `logs`, reading what the events leave out:
+/// Curve pool addresses, and Balancer tokens registered in another batch.
+async fn pools_from_logs<P, T>(provider: &P, logs: &[Log]) -> Vec<Pool>
+where
+    P: Provider<T>,
+    T: Transport + Clone,
+{
+    let mut balancer_tokens: HashMap<B256, Vec<Address>> = HashMap::new();
+    for log in logs {
+        if let Ok(tokens_log) = abi::IBalancerVault::TokensRegistered::decode_log(&log.inner, true)
+        {
+            balancer_tokens.insert(tokens_log.data.poolId, tokens_log.data.tokens);
+        }
+    }
+
+    let mut pools = Vec::new();
+    for log in logs {
+        if log.topic0() == Some(&abi::IBalancerVault::TokensRegistered::SIGNATURE_HASH) {
+            continue;
+        }
+
+        let mut pool = match Pool::try_from(log) {
+            Ok(pool) => pool,
+            Err(e) => {
+                info!("Failed to parse pool from log: {e}");
+                continue;
+            }
+        };
+
+        let resolved = match (&pool.venue, pool.balancer_pool_id) {
+            (Venue::Curve, _) => pool.resolve_curve(provider, log).await,
+            (Venue::Balancer, Some(pool_id)) => match balancer_tokens.remove(&pool_id) {
+                Some(tokens) => {
+                    pool.set_tokens(tokens);
+                    Ok(())
+                }
+                None => {
+                    let vault = abi::IBalancerVault::new(log.address(), provider);
+                    vault
+                        .getPoolTokens(pool_id)
+                        .call()
+                        .await
+                        .map(|pool_tokens| {
+                            pool.set_tokens(pool_tokens.tokens);
+                        })
+                        .map_err(Into::into)
+                }
+            },
+            _ => Ok(()),
+        };
+
+        match resolved {
+            Ok(()) => pools.push(pool),
+            Err(e) => info!("Failed to resolve {:?} pool from log: {e}", pool.venue),
+        }
+    }
+
+    pools
+}
 
 fn save_to_csv(pools: &[Pool], path: &Path) -> Result<()> {
     let mut writer = Writer::from_path(path)?;
@@ -36,79 +121,549 @@ fn load_from_csv(path: &Path) -> Result<Vec<Pool>> {
     Ok(pools)
 }
 
+/// Version written with every JSON Lines record. Bump it when `Pool` changes in
+/// a way `#[serde(default)]` can't absorb, and add a step to `migrate_pool`.
+const POOLS_SCHEMA_VERSION: u64 = 1;
+
+#[derive(Serialize)]
+struct PoolRecord<'a> {
+    version: u64,
+    pool: &'a Pool,
+}
+
+/// Upgrades a JSON Lines record written by any older schema version.
+fn migrate_pool(mut record: Value) -> Result<Pool> {
+    let version = record
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("pool record has no version"))?;
+
+    if version > POOLS_SCHEMA_VERSION {
+        bail!("pool record version {version} is newer than {POOLS_SCHEMA_VERSION}");
+    }
+
+    let pool = record
+        .get_mut("pool")
+        .map(Value::take)
+        .ok_or_else(|| anyhow!("pool record has no pool"))?;
+
+    // Version 1 is the first versioned schema, later migrations go here as
+    // `if version < N { ... }` steps applied to `pool` in order.
+    Ok(serde_json::from_value(pool)?)
+}
+
+fn save_to_jsonl(pools: &[Pool], path: &Path) -> Result<()> {
+    write_jsonl(pools, File::create(path)?)
+}
+
+/// Appends pools to a JSON Lines file, creating it if needed.
+fn append_to_jsonl(pools: &[Pool], path: &Path) -> Result<()> {
+    write_jsonl(pools, OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn write_jsonl(pools: &[Pool], file: File) -> Result<()> {
+    let mut writer = BufWriter::new(file);
+
+    for pool in pools {
+        let record = PoolRecord { version: POOLS_SCHEMA_VERSION, pool };
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn load_from_jsonl(path: &Path) -> Result<Vec<Pool>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut pools = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        pools.push(migrate_pool(serde_json::from_str(&line)?)?);
+    }
+
+    Ok(pools)
+}
+
+/// Drops the pools already in `seen` and adds the others to it. Overlapping
+/// rescans and re-emitting factories report a pool again.
+fn unseen_pools(pools: Vec<Pool>, seen: &mut HashSet<Address>) -> Vec<Pool> {
+    pools
+        .into_iter()
+        .filter(|pool| seen.insert(pool.id))
+        .collect()
+}
+
+/// Saves pools as JSON Lines for `.jsonl` paths and as CSV otherwise.
+pub(crate) fn save_pools(pools: &[Pool], path: &Path) -> Result<()> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("jsonl") => save_to_jsonl(pools, path),
+        _ => save_to_csv(pools, path),
+    }
+}
+
+/// Rewrites the pool cache with `pools`, e.g. on shutdown.
+pub(crate) fn save_pool_cache(pools: &[Pool]) -> Result<()> {
+    let cache_dir = Path::new(CACHE_DIR);
+    fs::create_dir_all(cache_dir)?;
+    save_pools(pools, &cache_dir.join(POOLS_CACHE_FILE))
+}
+
+/// Loads pools from JSON Lines for `.jsonl` paths and from CSV otherwise.
+pub(crate) fn load_pools_from(path: &Path) -> Result<Vec<Pool>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("jsonl") => load_from_jsonl(path),
+        _ => load_from_csv(path),
+    }
+}
+
+/// Highest block fully scanned for pools and its hash, kept apart from the
+/// pools since a range can end without creating any. Checkpoints written
+/// before hashes were recorded only hold the number.
+fn load_checkpoint(path: &Path) -> Result<Option<(u64, Option<B256>)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let checkpoint = fs::read_to_string(path)?;
+    let mut parts = checkpoint.split_whitespace();
+    let block = parts
+        .next()
+        .ok_or_else(|| anyhow!("empty checkpoint {:?}", path))?
+        .parse()?;
+    let hash = parts.next().map(str::parse).transpose()?;
+
+    Ok(Some((block, hash)))
+}
+
+fn save_checkpoint(block: u64, hash: Option<B256>, path: &Path) -> Result<()> {
+    let checkpoint = match hash {
+        Some(hash) => format!("{block} {hash}"),
+        None => block.to_string(),
+    };
+
+    // Write then rename, so a crash never leaves a truncated checkpoint.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, checkpoint)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Blocks this deep under the tip are assumed final, only the cached blocks
+/// above are checked for reorgs.
+const REORG_DEPTH: u64 = 64;
+
+async fn block_hash<P, T>(provider: &P, number: u64) -> Result<B256>
+where
+    P: Provider<T>,
+    T: Transport + Clone,
+{
+    provider
+        .get_block_by_number(number.into(), BlockTransactionsKind::Hashes)
+        .await?
+        .map(|block| block.header.hash)
+        .ok_or_else(|| anyhow!("block {number} not found"))
+}
+
+/// Compares the hashes of the recent cached blocks, the checkpoint and the
+/// blocks pools were created in, with the canonical chain. Returns the block
+/// to rescan from if one diverged: right after the last block that still
+/// matches, or `REORG_DEPTH` under the tip.
+async fn find_reorg<P, T>(
+    provider: &P,
+    pools: &[Pool],
+    checkpoint: Option<(u64, Option<B256>)>,
+    tip: u64,
+) -> Result<Option<u64>>
+where
+    P: Provider<T>,
+    T: Transport + Clone,
+{
+    let confirmed = tip.saturating_sub(REORG_DEPTH);
+
+    let mut cached: BTreeMap<u64, B256> = pools
+        .iter()
+        .filter(|pool| pool.block > confirmed)
+        .filter_map(|pool| Some((pool.block, pool.block_hash?)))
+        .collect();
+    if let Some((block, Some(hash))) = checkpoint {
+        if block > confirmed {
+            cached.insert(block, hash);
+        }
+    }
+
+    let mut last_match = confirmed;
+    for (number, hash) in cached {
+        if block_hash(provider, number).await? != hash {
+            return Ok(Some(last_match + 1));
+        }
+        last_match = number;
+    }
+
+    Ok(None)
+}
+
 pub(crate) async fn load_pools(wss_url: &str, from_block: u64) -> Result<Vec<Pool>> {
     let provider = Arc::new(get_ws_provider(wss_url).await);
     info!("connected to provider");
 
-    let cache_dir = Path::new("cache");
+    let cache_dir = Path::new(CACHE_DIR);
     if !cache_dir.exists() {
         fs::create_dir_all(cache_dir)?;
         info!("Created cache directory at {:?}", cache_dir);
     }
 
-    let pools_cache_path = cache_dir.join("pools.csv");
-    let pools = if pools_cache_path.exists() { load_from_csv(&pools_cache_path)? } else { vec![] };
+    // Older runs cached pools as CSV, read it once and rewrite it as JSON Lines.
+    let pools_cache_path = cache_dir.join(POOLS_CACHE_FILE);
+    let legacy_cache_path = cache_dir.join("pools.csv");
+    let checkpoint_path = cache_dir.join("pools.checkpoint");
+    let mut pools = if pools_cache_path.exists() {
+        load_pools_from(&pools_cache_path)?
+    } else if legacy_cache_path.exists() {
+        info!("Migrating {:?} to {:?}", legacy_cache_path, pools_cache_path);
+        let pools = load_pools_from(&legacy_cache_path)?;
+        save_pools(&pools, &pools_cache_path)?;
+        pools
+    } else {
+        vec![]
+    };
 
-    let start_block = pools
-        .iter()
-        .map(|pool| pool.block)
-        .max()
-        .map_or(from_block, |block| block + 1);
+    // A crash between appending a chunk and saving the checkpoint rescans the
+    // chunk, drop the pools it appended twice.
+    let mut seen = HashSet::new();
+    pools.retain(|pool| seen.insert(pool.id));
+
+    // Caches written before checkpoints existed resume after the last pool.
+    let checkpoint = load_checkpoint(&checkpoint_path)?;
+    let mut start_block = match checkpoint {
+        Some((block, _)) => block + 1,
+        None => pools
+            .iter()
+            .map(|pool| pool.block)
+            .max()
+            .map_or(from_block, |block| block + 1),
+    };
 
     let end_block = provider.get_block_number().await?;
 
+    // Pools created in reorged blocks may not exist anymore, drop everything
+    // found from the fork on and scan it again.
+    if let Some(rescan_from) = find_reorg(provider.as_ref(), &pools, checkpoint, end_block).await? {
+        warn!("Reorg detected, rescanning from block {rescan_from}");
+        pools.retain(|pool| pool.block < rescan_from);
+        seen = pools.iter().map(|pool| pool.id).collect();
+        save_pools(&pools, &pools_cache_path)?;
+        start_block = start_block.min(rescan_from);
+    }
+
     if start_block >= end_block {
         info!("No new blocks to scan");
         return Ok(pools);
     }
 
     info!("Scanning blocks {start_block} to {end_block}");
-    let mut pools = pools;
-    let events = [
-        abi::IUniswapV2Factory::PairCreated::SIGNATURE,
-        abi::IUniswapV3Factory::PoolCreated::SIGNATURE,
-    ];
 
-    // Process blocks in chunks
+    // Process blocks in chunks. `get_logs` splits chunks the provider rejects,
+    // a chunk that still fails stops the scan so no pools are skipped. Every
+    // chunk is saved as soon as it is scanned, so an interrupted scan resumes
+    // at the first unscanned block.
     const CHUNK_SIZE: u64 = 10_000;
     for (chunk_start, chunk_end) in get_block_range(start_block, end_block, CHUNK_SIZE) {
         let timer = Instant::now();
 
-        match get_logs(provider.clone(), chunk_start, chunk_end, None, &events).await {
-            Ok(logs) => {
-                info!("Processing blocks {chunk_start}-{chunk_end}: found {} logs", logs.len());
-
-                let new_pools: Vec<_> = logs
-                    .iter()
-                    .filter_map(|log| {
-                        Pool::try_from(log)
-                            .map_err(|e| info!("Failed to parse pool from log: {e}"))
-                            .ok()
-                    })
-                    .collect();
-
-                if !new_pools.is_empty() {
-                    info!(
-                        "Added {} new pools in {}ms",
-                        new_pools.len(),
-                        timer.elapsed().as_millis()
-                    );
-                    pools.extend(new_pools);
-                }
+        let logs = get_logs(provider.clone(), chunk_start, chunk_end, None, &POOL_EVENTS)
+            .await
+            .map_err(|e| {
+                info!("Failed to fetch logs for blocks {chunk_start}-{chunk_end}: {e}");
+                e.context(format!("failed to fetch logs for blocks {chunk_start}-{chunk_end}"))
+            })?;
+
+        info!("Processing blocks {chunk_start}-{chunk_end}: found {} logs", logs.len());
+
+        let new_pools = unseen_pools(pools_from_logs(provider.as_ref(), &logs).await, &mut seen);
+
+        if !new_pools.is_empty() {
+            append_to_jsonl(&new_pools, &pools_cache_path)?;
+            info!("Added {} new pools in {}ms", new_pools.len(), timer.elapsed().as_millis());
+            pools.extend(new_pools);
+        }
+
+        let chunk_end_hash = block_hash(provider.as_ref(), chunk_end)
+            .await
+            .map_err(|e| warn!("Failed to fetch hash of block {chunk_end}: {e}"))
+            .ok();
+        save_checkpoint(chunk_end, chunk_end_hash, &checkpoint_path)?;
+    }
+
+    info!("Saved {} pools to {:?}", pools.len(), pools_cache_path);
+
+    Ok(pools)
+}
+
+/// Reconnects to the WS endpoint, doubling the delay between failed attempts.
+pub(crate) async fn reconnect(wss_url: &str) -> RootProvider<PubSubFrontend> {
+    const MAX_DELAY: Duration = Duration::from_secs(60);
+
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+
+    loop {
+        info!("Reconnecting to WS provider, attempt {attempt}");
+
+        match try_get_ws_provider(wss_url).await {
+            Ok(provider) => {
+                info!("Reconnected to WS provider");
+                return provider;
             }
             Err(e) => {
-                info!("Failed to fetch logs for blocks {chunk_start}-{chunk_end}: {e}");
-                continue;
+                warn!("Failed to reconnect: {e}, retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_DELAY);
+                attempt += 1;
             }
         }
     }
+}
 
-    // Save results
-    if let Err(e) = save_to_csv(&pools, &pools_cache_path) {
-        info!("Failed to save pools to cache: {e}");
-    } else {
-        info!("Saved {} pools to {:?}", pools.len(), pools_cache_path);
+/// Keeps `pools` up to date with the pools created after startup, appending
+/// them to the cache. Resubscribes when the WS connection drops.
+///
+/// Pools created between the end of `load_pools` and the subscription are
+/// missed, the next startup scan picks them up.
+pub(crate) async fn watch_new_pools(wss_url: String, pools: Arc<RwLock<HashMap<Address, Pool>>>) {
+    let filter = Filter::new().events(POOL_EVENTS);
+    let pools_cache_path = Path::new(CACHE_DIR).join(POOLS_CACHE_FILE);
+
+    let mut provider = match try_get_ws_provider(&wss_url).await {
+        Ok(provider) => provider,
+        Err(e) => {
+            warn!("Failed to connect pool watcher: {e}");
+            reconnect(&wss_url).await
+        }
+    };
+
+    loop {
+        match provider.subscribe_logs(&filter).await {
+            Ok(sub) => {
+                let mut stream = sub.into_stream();
+
+                while let Some(log) = stream.next().await {
+                    if log.removed {
+                        continue;
+                    }
+
+                    // Balancer tokens are read from the Vault when the pool
+                    // registers, the `TokensRegistered` log adds nothing.
+                    for pool in pools_from_logs(&provider, std::slice::from_ref(&log)).await {
+                        let mut pools = pools.write().await;
+                        if pools.contains_key(&pool.id) {
+                            continue;
+                        }
+
+                        if let Err(e) =
+                            append_to_jsonl(std::slice::from_ref(&pool), &pools_cache_path)
+                        {
+                            warn!("Failed to cache new pool {}: {e}", pool.id);
+                        }
+                        info!("New pool: {:?}", pool);
+                        pools.insert(pool.id, pool);
+                    }
+                }
+
+                warn!("Pool creation stream ended");
+            }
+            Err(e) => warn!("Failed to subscribe to pool creations: {e}"),
+        }
+
+        provider = reconnect(&wss_url).await;
     }
+}
 
-    Ok(pools)
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::aliases::{I24, U24};
+    use alloy::primitives::{address, U256};
+    use alloy::providers::ProviderBuilder;
+    use serde_json::json;
+
+    use super::*;
+
+    fn v2_pool() -> Pool {
+        Pool {
+            id: address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"),
+            token0: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            token1: address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+            fee: 3000,
+            tick_spacing: None,
+            venue: Venue::UniswapV2,
+            block: 10_008_355,
+            block_hash: Some(B256::repeat_byte(0xaa)),
+            tokens: Vec::new(),
+            balancer_pool_id: None,
+            reserve0: Some(U256::from(50_000_000_000_000u64)),
+            reserve1: Some(U256::from(20_000u64) * U256::from(10u64).pow(U256::from(18))),
+            sqrt_price_x96: None,
+            liquidity: None,
+            tick: None,
+        }
+    }
+
+    fn v3_pool() -> Pool {
+        Pool {
+            id: address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640"),
+            token0: address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+            token1: address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+            fee: 500,
+            tick_spacing: Some(10),
+            venue: Venue::UniswapV3,
+            block: 12_376_729,
+            block_hash: None,
+            tokens: Vec::new(),
+            balancer_pool_id: None,
+            reserve0: None,
+            reserve1: None,
+            sqrt_price_x96: Some(U256::from(1u64) << 96),
+            liquidity: Some(123_456_789),
+            tick: Some(-201_000),
+        }
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("{}-{name}", std::process::id()))
+    }
+
+    fn to_values(pools: &[Pool]) -> Vec<Value> {
+        pools
+            .iter()
+            .map(|pool| serde_json::to_value(pool).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn jsonl_roundtrips_v2_and_v3_pools() {
+        let path = temp_path("pools.jsonl");
+        let pools = vec![v2_pool(), v3_pool()];
+
+        save_pools(&pools, &path).unwrap();
+        let loaded = load_pools_from(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(to_values(&loaded), to_values(&pools));
+        assert!(matches!(loaded[0].venue, Venue::UniswapV2));
+        assert!(matches!(loaded[1].venue, Venue::UniswapV3));
+        assert_eq!(loaded[1].tick, Some(-201_000));
+    }
+
+    #[test]
+    fn appended_pools_are_loaded_after_saved_ones() {
+        let path = temp_path("appended.jsonl");
+
+        save_pools(&[v2_pool()], &path).unwrap();
+        append_to_jsonl(&[v3_pool()], &path).unwrap();
+        let loaded = load_pools_from(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(to_values(&loaded.unwrap()), to_values(&[v2_pool(), v3_pool()]));
+    }
+
+    #[test]
+    fn records_are_versioned() {
+        let path = temp_path("versioned.jsonl");
+
+        save_pools(&[v3_pool()], &path).unwrap();
+        let contents = fs::read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+        let record: Value = serde_json::from_str(contents.unwrap().trim()).unwrap();
+
+        assert_eq!(record["version"], json!(POOLS_SCHEMA_VERSION));
+        assert_eq!(record["pool"]["venue"], json!("UniswapV3"));
+    }
+
+    #[test]
+    fn migration_fills_fields_missing_from_old_records() {
+        let mut pool = serde_json::to_value(v2_pool()).unwrap();
+        let fields = pool.as_object_mut().unwrap();
+        for field in ["block_hash", "reserve0", "reserve1", "tick_spacing"] {
+            fields.remove(field);
+        }
+
+        let migrated = migrate_pool(json!({ "version": 1, "pool": pool })).unwrap();
+
+        assert_eq!(migrated.id, v2_pool().id);
+        assert_eq!(migrated.block_hash, None);
+        assert_eq!(migrated.reserve0, None);
+    }
+
+    #[test]
+    fn migration_rejects_unknown_versions() {
+        let pool = serde_json::to_value(v2_pool()).unwrap();
+
+        assert!(migrate_pool(json!({ "pool": pool })).is_err());
+        assert!(migrate_pool(json!({ "version": POOLS_SCHEMA_VERSION + 1, "pool": pool })).is_err());
+    }
+
+    /// Creation logs of `v2_pool` and `v3_pool`, the V2 one emitted twice.
+    fn creation_logs() -> Vec<Log> {
+        let factory = address!("5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f");
+        let (v2, v3) = (v2_pool(), v3_pool());
+        let pair_created = abi::IUniswapV2Factory::PairCreated {
+            token0: v2.token0,
+            token1: v2.token1,
+            pair: v2.id,
+            _3: U256::from(1),
+        }
+        .encode_log_data();
+        let pool_created = abi::IUniswapV3Factory::PoolCreated {
+            token0: v3.token0,
+            token1: v3.token1,
+            fee: U24::from(v3.fee),
+            tickSpacing: I24::try_from(10).unwrap(),
+            pool: v3.id,
+        }
+        .encode_log_data();
+
+        [pair_created.clone(), pool_created, pair_created]
+            .into_iter()
+            .map(|data| Log {
+                inner: alloy::primitives::Log { address: factory, data },
+                block_number: Some(v2.block),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scanning_a_range_twice_keeps_the_pool_count() {
+        // Uniswap pools are decoded from their logs, nothing is requested.
+        let provider = ProviderBuilder::new().on_http("http://127.0.0.1:1".parse().unwrap());
+        let logs = creation_logs();
+        let mut seen = HashSet::new();
+        let mut pools = Vec::new();
+
+        for _ in 0..2 {
+            let found = pools_from_logs(&provider, &logs).await;
+            assert_eq!(found.len(), 3);
+            pools.extend(unseen_pools(found, &mut seen));
+        }
+
+        let ids: Vec<_> = pools.iter().map(|pool| pool.id).collect();
+        assert_eq!(ids, vec![v2_pool().id, v3_pool().id]);
+    }
+
+    #[test]
+    fn pair_key_is_the_same_for_both_token_orders() {
+        let pool = v3_pool();
+        let mut flipped = v2_pool();
+        flipped.token0 = pool.token1;
+        flipped.token1 = pool.token0;
+
+        assert_eq!(flipped.pair_key(), pool.pair_key());
+        assert!(pool.pair_key().0 < pool.pair_key().1);
+    }
 }
@@ -1,34 +1,48 @@
 //! Smart caching and deduplication of requests when using a forking provider.
 
 use std::collections::VecDeque;
-use std::fmt;
 use std::future::IntoFuture;
 use std::marker::PhantomData;
 use std::path::Path;
 use std::pin::Pin;
-use std::sync::mpsc::{channel as oneshot_channel, Sender as OneshotSender};
+use std::sync::mpsc::{
+    channel as oneshot_channel, Receiver as OneshotReceiver, RecvTimeoutError,
+    Sender as OneshotSender,
+};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{fmt, mem};
 
 use alloy_primitives::{keccak256, Address, Bytes, B256, U256};
-use alloy_provider::network::{AnyNetwork, AnyRpcBlock, AnyRpcTransaction, AnyTxEnvelope};
+use alloy_provider::network::{
+    AnyNetwork, AnyRpcBlock, AnyRpcHeader, AnyRpcTransaction, AnyTransactionReceipt, AnyTxEnvelope,
+};
 use alloy_provider::Provider;
-use alloy_rpc_types::{BlockId, Transaction};
+use alloy_rpc_types::{BlockId, Log, Transaction};
 use alloy_serde::WithOtherFields;
-use alloy_transport::Transport;
+use alloy_transport::layers::{RateLimitRetryPolicy, RetryPolicy};
+use alloy_transport::{Transport, TransportError, TransportErrorKind};
 use eyre::WrapErr;
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use futures::stream::Stream;
 use futures::task::{Context, Poll};
 use futures::{Future, FutureExt};
 use reth::primitives::Bytecode as RethBytecode;
+use reth_provider::{BlockHashReader, StateProvider, StateProviderBox};
 use revm::db::DatabaseRef;
 use revm::primitives::map::hash_map::Entry;
 use revm::primitives::map::{AddressHashMap, HashMap};
 use revm::primitives::{AccountInfo, Bytecode, KECCAK_EMPTY};
 
-use crate::cache::{BlockchainDb, FlushJsonBlockCacheDB, MemDb, StorageInfo};
-use crate::error::{DatabaseError, DatabaseResult};
-use crate::types::DBFactory;
+use crate::cache::{
+    diff_storage, BlockchainDb, FlushJsonBlockCacheDB, MemDb, MemDbFootprint, StorageData,
+    StorageInfo,
+};
+use crate::error::{
+    is_non_archive_node_message, DatabaseError, DatabaseResult, RequestKind, RequestTimedOut,
+};
+use crate::metrics::{BackendCounters, BackendMetrics};
+use crate::types::{dump_plain_storage, DBFactory};
 
 /// Logged when an error is indicative that the user is trying to fork from a
 /// non-archive node.
@@ -37,6 +51,183 @@ It looks like you're trying to fork from an older block with a non-archive node
                                             supported. Please try to change your RPC url to an \
                                             archive node if the issue persists.";
 
+/// Time `SharedBackend::is_alive` waits for the handler to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A blocking `SharedBackend` call logs a warning every time it has waited
+/// this long for the handler.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Retry policy for provider requests made by the `BackendHandler`.
+///
+/// Only transient errors (rate limits, 5xx, null responses) are retried. The
+/// delay doubles on every attempt, plus up to `jitter` so concurrent requests
+/// don't retry in lockstep. A backoff requested by the provider takes
+/// precedence.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryConfig {
+    /// Total attempts per request, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub jitter: Duration,
+}
+
+impl RetryConfig {
+    /// Fails on the first error, the default.
+    pub const fn disabled() -> Self {
+        Self { max_attempts: 1, base_delay: Duration::ZERO, jitter: Duration::ZERO }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+
+        // Not worth a proper RNG, the clock is enough to spread retries out.
+        let jitter_ms = self.jitter.as_millis() as u64;
+        let jitter = if jitter_ms == 0 {
+            Duration::ZERO
+        } else {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.subsec_nanos() as u64)
+                .unwrap_or_default();
+            Duration::from_millis(nanos % jitter_ms)
+        };
+
+        backoff + jitter
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+fn is_retryable(err: &TransportError) -> bool {
+    // Pruned state won't show up on a later attempt.
+    RateLimitRetryPolicy::default().should_retry(err)
+        && !is_non_archive_node_message(&err.to_string())
+}
+
+/// Runs the request built by `make_request` until it succeeds, fails with a
+/// non-retryable error or runs out of attempts.
+///
+/// Each attempt is aborted with an error after `timeout`, so a hung provider
+/// can't block the listeners forever. Timeouts are not retried, the handler
+/// reports them as `DatabaseError::Timeout`.
+async fn with_retry<T, F, Fut>(
+    config: RetryConfig,
+    timeout: Option<Duration>,
+    mut make_request: F,
+) -> Result<T, TransportError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, TransportError>>,
+{
+    let start = Instant::now();
+    let mut attempt = 1;
+    loop {
+        let res = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, make_request())
+                .await
+                .unwrap_or_else(|_| {
+                    Err(TransportErrorKind::custom(RequestTimedOut(start.elapsed())))
+                }),
+            None => make_request().await,
+        };
+
+        match res {
+            Err(err) if attempt < config.max_attempts && is_retryable(&err) => {
+                let delay = RateLimitRetryPolicy::default()
+                    .backoff_hint(&err)
+                    .unwrap_or_else(|| config.delay(attempt));
+                warn!(
+                    target: "backendhandler",
+                    %err,
+                    attempt,
+                    ?delay,
+                    "provider request failed, retrying"
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Reads of the local reth state made by storage requests, a seam so the
+/// tests can stand in for the local database.
+trait LocalState: Send + Sync + 'static {
+    /// Value of the slot, `None` if it was never written.
+    fn read_slot(&self, address: Address, idx: U256) -> eyre::Result<Option<U256>>;
+
+    fn has_account(&self, address: Address) -> eyre::Result<bool>;
+}
+
+impl LocalState for StateProviderBox {
+    fn read_slot(&self, address: Address, idx: U256) -> eyre::Result<Option<U256>> {
+        Ok(StateProvider::storage(self.as_ref(), address, idx.into())?)
+    }
+
+    fn has_account(&self, address: Address) -> eyre::Result<bool> {
+        Ok(self.account_balance(&address)?.is_some())
+    }
+}
+
+/// Fetches a slot from the provider at `block_id`.
+async fn provider_storage<T, P>(
+    provider: P,
+    block_id: BlockId,
+    retry: RetryConfig,
+    timeout: Option<Duration>,
+    address: Address,
+    idx: U256,
+) -> eyre::Result<U256>
+where
+    T: Transport + Clone,
+    P: Provider<T, AnyNetwork>,
+{
+    with_retry(retry, timeout, || {
+        provider
+            .get_storage_at(address, idx)
+            .block_id(block_id)
+            .into_future()
+    })
+    .await
+    .map_err(Into::into)
+}
+
+/// Reads a slot from the local state.
+///
+/// A slot that was never written is zero, but only if its account is in the
+/// local state. A missing account means the local database hasn't caught up
+/// with the block, so the slot is fetched with `fallback` instead of being
+/// answered as zero. Read errors are returned, never turned into zero.
+async fn local_storage<L, F>(
+    local: &L,
+    address: Address,
+    idx: U256,
+    fallback: F,
+) -> eyre::Result<U256>
+where
+    L: LocalState,
+    F: Future<Output = eyre::Result<U256>>,
+{
+    if let Some(value) = local.read_slot(address, idx)? {
+        return Ok(value);
+    }
+
+    if local.has_account(address)? {
+        return Ok(U256::ZERO);
+    }
+
+    trace!(target: "backendhandler", %address, %idx, "account missing from local db, using provider");
+    fallback.await
+}
+
 // Various future/request type aliases
 
 type AccountFuture<Err> =
@@ -46,18 +237,29 @@ type BlockHashFuture<Err> = Pin<Box<dyn Future<Output = (Result<B256, Err>, u64)
 type FullBlockFuture<Err> = Pin<
     Box<dyn Future<Output = (FullBlockSender, Result<Option<AnyRpcBlock>, Err>, BlockId)> + Send>,
 >;
+type HeaderFuture<Err> = Pin<
+    Box<dyn Future<Output = (HeaderSender, Result<Option<AnyRpcHeader>, Err>, BlockId)> + Send>,
+>;
 type TransactionFuture<Err> =
     Pin<Box<dyn Future<Output = (TransactionSender, Result<AnyRpcTransaction, Err>, B256)> + Send>>;
+type ReceiptFuture<Err> =
+    Pin<Box<dyn Future<Output = (Result<Option<AnyTransactionReceipt>, Err>, B256)> + Send>>;
 
 type AccountInfoSender = OneshotSender<DatabaseResult<AccountInfo>>;
 type StorageSender = OneshotSender<DatabaseResult<U256>>;
 type BlockHashSender = OneshotSender<DatabaseResult<B256>>;
+type CodeSender = OneshotSender<DatabaseResult<Bytecode>>;
 type FullBlockSender = OneshotSender<DatabaseResult<AnyRpcBlock>>;
+type HeaderSender = OneshotSender<DatabaseResult<AnyRpcHeader>>;
 type TransactionSender = OneshotSender<DatabaseResult<AnyRpcTransaction>>;
+type ReceiptSender = OneshotSender<DatabaseResult<AnyTransactionReceipt>>;
+type DrainSender = OneshotSender<()>;
+type PingSender = OneshotSender<()>;
+type StorageDumpSender = OneshotSender<Result<StorageDump, eyre::Report>>;
 
 type AddressData = AddressHashMap<AccountInfo>;
-type StorageData = AddressHashMap<StorageInfo>;
 type BlockHashData = HashMap<U256, B256>;
+type StorageDump = HashMap<U256, U256>;
 
 struct AnyRequestFuture<T, Err> {
     sender: OneshotSender<Result<T, Err>>,
@@ -100,13 +302,23 @@ where
     }
 }
 
+/// Account or storage slot fetched by the handler, evictable since it can be
+/// fetched again
+#[derive(Clone, Copy, Debug)]
+enum FetchedEntry {
+    Account(Address),
+    Storage(Address, U256),
+}
+
 /// Request variants that are executed by the provider
 enum ProviderRequest<Err> {
     Account(AccountFuture<Err>),
     Storage(StorageFuture<Err>),
     BlockHash(BlockHashFuture<Err>),
     FullBlock(FullBlockFuture<Err>),
+    Header(HeaderFuture<Err>),
     Transaction(TransactionFuture<Err>),
+    Receipt(ReceiptFuture<Err>),
     AnyRequest(Box<dyn WrappedAnyRequest>),
 }
 
@@ -119,12 +331,33 @@ enum BackendRequest {
     Storage(Address, U256, StorageSender),
     /// Fetch a block hash
     BlockHash(u64, BlockHashSender),
+    /// Fetch the code of an already fetched account by its hash
+    CodeByHash(B256, CodeSender),
     /// Fetch an entire block with transactions
     FullBlock(BlockId, FullBlockSender),
+    /// Fetch a block header, without the transactions
+    Header(BlockId, HeaderSender),
     /// Fetch a transaction
     Transaction(B256, TransactionSender),
+    /// Fetch a transaction receipt
+    Receipt(B256, ReceiptSender),
     /// Sets the pinned block to fetch data from
     SetPinnedBlock(BlockId),
+    /// Sets the retry policy for provider requests
+    SetRetryConfig(RetryConfig),
+    /// Sets the timeout of a single provider request
+    SetRequestTimeout(Option<Duration>),
+    /// Enables or disables serving state lookups ahead of block and tx fetches
+    SetPriorityLanes(bool),
+    /// Sets the maximum number of provider requests in flight
+    SetMaxInFlight(Option<usize>),
+    /// Sets the maximum number of fetched accounts and slots kept in memory
+    SetMaxCacheEntries(Option<usize>),
+    /// Enables or disables dropping the fetched state when the pinned block
+    /// changes
+    SetPruneOnRepin(bool),
+    /// Drop the fetched accounts and storage
+    ClearState,
 
     /// Update Address data
     UpdateAddress(AddressData),
@@ -132,8 +365,39 @@ enum BackendRequest {
     UpdateStorage(StorageData),
     /// Update Block Hashes
     UpdateBlockHash(BlockHashData),
+    /// Update an account and its storage together
+    SeedAccount(Address, AccountInfo, StorageInfo),
     /// Any other request
     AnyRequest(Box<dyn WrappedAnyRequest>),
+    /// Stop accepting requests, finish the in-flight ones and flush the cache
+    DrainAndFlush(DrainSender),
+    /// Read all storage slots of an account from the local DB
+    DumpStorage(Address, StorageDumpSender),
+    /// Liveness probe, answered as soon as the handler polls it
+    Ping(PingSender),
+}
+
+impl BackendRequest {
+    /// State lookups the EVM blocks on while executing.
+    fn is_high_priority(&self) -> bool {
+        matches!(
+            self,
+            Self::Basic(..) | Self::Storage(..) | Self::BlockHash(..) | Self::CodeByHash(..)
+        )
+    }
+
+    /// Bulk fetches that can wait behind state lookups.
+    fn is_low_priority(&self) -> bool {
+        matches!(
+            self,
+            Self::FullBlock(..)
+                | Self::Header(..)
+                | Self::Transaction(..)
+                | Self::Receipt(..)
+                | Self::AnyRequest(..)
+                | Self::DumpStorage(..)
+        )
+    }
 }
 
 /// Handles an internal provider and listens for requests.
@@ -155,13 +419,43 @@ pub struct BackendHandler<T, P> {
     storage_requests: HashMap<(Address, U256), Vec<StorageSender>>,
     /// Listeners that wait for a `get_block` response
     block_requests: HashMap<u64, Vec<BlockHashSender>>,
+    /// Listeners that wait for a `get_transaction_receipt` response
+    receipt_requests: HashMap<B256, Vec<ReceiptSender>>,
+    /// Code of all fetched accounts, keyed by code hash
+    contracts: HashMap<B256, Bytecode>,
     /// Incoming commands.
     incoming: UnboundedReceiver<BackendRequest>,
-    /// unprocessed queued requests
-    queued_requests: VecDeque<BackendRequest>,
+    /// unprocessed queued state lookups, served first
+    high_priority_requests: VecDeque<BackendRequest>,
+    /// unprocessed queued block, transaction and other requests
+    low_priority_requests: VecDeque<BackendRequest>,
+    /// Whether requests are split into the two lanes, otherwise all of them
+    /// are served in arrival order
+    priority_lanes: bool,
+    /// Cap on `pending_requests`, further requests wait in the queues.
+    /// Unbounded if `None`
+    max_in_flight: Option<usize>,
+    /// Cap on the cached accounts and slots, the oldest fetched ones are
+    /// evicted first. Unbounded if `None`
+    max_cache_entries: Option<usize>,
+    /// Fetched accounts and slots, oldest first. Only tracked with a cap
+    fetched: VecDeque<FetchedEntry>,
+    /// Whether an entry was fetched since the cap was last enforced, so the
+    /// cache isn't counted on every poll
+    fetched_since_limit_check: bool,
+    /// Whether the fetched state is dropped when the pinned block changes
+    prune_on_repin: bool,
     /// The block to fetch data from.
     // This is an `Option` so that we can have less code churn in the functions below
     block_id: Option<BlockId>,
+    /// Retry policy for provider requests
+    retry: RetryConfig,
+    /// Timeout of a single provider request, unbounded if `None`
+    request_timeout: Option<Duration>,
+    /// Request counters, shared with the `SharedBackend`s
+    counters: Arc<BackendCounters>,
+    /// Listeners waiting for the handler to drain and flush before it exits
+    drain_listeners: Vec<DrainSender>,
 }
 
 impl<T, P> BackendHandler<T, P>
@@ -175,6 +469,7 @@ where
         db: BlockchainDb,
         rx: UnboundedReceiver<BackendRequest>,
         block_id: Option<BlockId>,
+        counters: Arc<BackendCounters>,
     ) -> Self {
         Self {
             provider,
@@ -184,13 +479,151 @@ where
             account_requests: Default::default(),
             storage_requests: Default::default(),
             block_requests: Default::default(),
-            queued_requests: Default::default(),
+            receipt_requests: Default::default(),
+            contracts: Default::default(),
+            high_priority_requests: Default::default(),
+            low_priority_requests: Default::default(),
+            priority_lanes: true,
+            max_in_flight: None,
+            max_cache_entries: None,
+            fetched: Default::default(),
+            fetched_since_limit_check: false,
+            prune_on_repin: false,
             incoming: rx,
             block_id,
+            retry: Default::default(),
+            request_timeout: None,
+            counters,
+            drain_listeners: Default::default(),
             transport: PhantomData,
         }
     }
 
+    /// Queues an incoming request on its lane.
+    ///
+    /// Settings and cache updates are not queued: everything received before
+    /// them is dispatched first, even past `max_in_flight`, and they are
+    /// applied right away, so lanes never reorder a request around e.g. a
+    /// pinned block change.
+    fn queue_request(&mut self, req: BackendRequest) {
+        if req.is_high_priority() || (!self.priority_lanes && req.is_low_priority()) {
+            self.high_priority_requests.push_back(req);
+        } else if req.is_low_priority() {
+            self.low_priority_requests.push_back(req);
+        } else {
+            self.drain_queued_requests();
+            self.on_request(req);
+        }
+    }
+
+    /// Drops the cached accounts and storage, block hashes and headers are
+    /// kept.
+    fn clear_state(&mut self) {
+        self.db.db().clear_state();
+        self.fetched.clear();
+    }
+
+    /// Remembers a fetched entry for eviction, if the cache is capped.
+    fn record_fetched(&mut self, entry: FetchedEntry) {
+        if self.max_cache_entries.is_some() {
+            self.fetched.push_back(entry);
+            self.fetched_since_limit_check = true;
+        }
+    }
+
+    /// Evicts the oldest fetched accounts and slots until the cache holds at
+    /// most `max_cache_entries` of them. Entries inserted with the
+    /// `insert_or_update_*` and `seed_account` calls or loaded from the JSON
+    /// cache are never evicted.
+    fn enforce_cache_limit(&mut self) {
+        let Some(max_entries) = self.max_cache_entries else {
+            return;
+        };
+        // Nothing evictable.
+        if self.fetched.is_empty() {
+            return;
+        }
+        let db = self.db.db();
+
+        let mut entries = db.state_entries();
+        let mut evicted = 0;
+        while entries > max_entries {
+            let Some(entry) = self.fetched.pop_front() else {
+                break;
+            };
+            // Entries already cleared, e.g. by a repin, are skipped.
+            let removed = match entry {
+                FetchedEntry::Account(address) => db.accounts.write().remove(&address).is_some(),
+                FetchedEntry::Storage(address, slot) => {
+                    let mut storage = db.storage.write();
+                    let removed = storage
+                        .get_mut(&address)
+                        .is_some_and(|slots| slots.remove(&slot).is_some());
+                    if storage.get(&address).is_some_and(|slots| slots.is_empty()) {
+                        storage.remove(&address);
+                    }
+                    removed
+                }
+            };
+            if removed {
+                entries -= 1;
+                evicted += 1;
+            }
+        }
+
+        // Drop the entries that were cleared behind our back so the queue
+        // stays bounded.
+        if self.fetched.len() > max_entries.saturating_mul(2) {
+            let accounts = db.accounts.read();
+            let storage = db.storage.read();
+            self.fetched.retain(|entry| match entry {
+                FetchedEntry::Account(address) => accounts.contains_key(address),
+                FetchedEntry::Storage(address, slot) => storage
+                    .get(address)
+                    .is_some_and(|slots| slots.contains_key(slot)),
+            });
+        }
+
+        if evicted > 0 {
+            trace!(target: "backendhandler", evicted, entries, "evicted fetched state");
+        }
+    }
+
+    /// Dispatches all queued requests, state lookups first.
+    fn drain_queued_requests(&mut self) {
+        while let Some(req) = self.high_priority_requests.pop_front() {
+            self.on_request(req)
+        }
+        while let Some(req) = self.low_priority_requests.pop_front() {
+            self.on_request(req)
+        }
+    }
+
+    /// Dispatches queued requests, state lookups first, until `max_in_flight`
+    /// provider requests are pending. Requests answered from the cache or
+    /// joining an in-flight fetch don't take a slot.
+    fn dispatch_queued_requests(&mut self) {
+        while !self.at_capacity() {
+            let Some(req) = self
+                .high_priority_requests
+                .pop_front()
+                .or_else(|| self.low_priority_requests.pop_front())
+            else {
+                break;
+            };
+            self.on_request(req)
+        }
+    }
+
+    fn at_capacity(&self) -> bool {
+        self.max_in_flight
+            .is_some_and(|max_in_flight| self.pending_requests.len() >= max_in_flight)
+    }
+
+    fn has_queued_requests(&self) -> bool {
+        !self.high_priority_requests.is_empty() || !self.low_priority_requests.is_empty()
+    }
+
     /// handle the request in queue in the future.
     ///
     /// We always check:
@@ -205,8 +638,10 @@ where
                 trace!(target: "backendhandler", "received request basic address={:?}", addr);
                 let acc = self.db.accounts().read().get(&addr).cloned();
                 if let Some(basic) = acc {
+                    BackendCounters::incr(&self.counters.account_hits);
                     let _ = sender.send(Ok(basic));
                 } else {
+                    BackendCounters::incr(&self.counters.account_misses);
                     self.request_account(addr, sender);
                 }
             }
@@ -218,17 +653,39 @@ where
                     .get(&U256::from(number))
                     .cloned();
                 if let Some(hash) = hash {
+                    BackendCounters::incr(&self.counters.block_hash_hits);
                     let _ = sender.send(Ok(hash));
                 } else {
+                    BackendCounters::incr(&self.counters.block_hash_misses);
                     self.request_hash(number, sender);
                 }
             }
+            BackendRequest::CodeByHash(hash, sender) => {
+                let _ = sender.send(self.code_by_hash(hash));
+            }
             BackendRequest::FullBlock(number, sender) => {
+                BackendCounters::incr(&self.counters.full_block_fetches);
                 self.request_full_block(number, sender);
             }
+            BackendRequest::Header(block_id, sender) => {
+                // Tags move with the chain, only numbers are served from the cache.
+                let header = block_id
+                    .as_u64()
+                    .and_then(|number| self.db.block_headers().read().get(&number).cloned());
+                if let Some(header) = header {
+                    let _ = sender.send(Ok(header));
+                } else {
+                    self.request_header(block_id, sender);
+                }
+            }
             BackendRequest::Transaction(tx, sender) => {
+                BackendCounters::incr(&self.counters.transaction_fetches);
                 self.request_transaction(tx, sender);
             }
+            BackendRequest::Receipt(tx, sender) => {
+                BackendCounters::incr(&self.counters.receipt_fetches);
+                self.request_receipt(tx, sender);
+            }
             BackendRequest::Storage(addr, idx, sender) => {
                 // account is already stored in the cache
                 let value = self
@@ -238,15 +695,49 @@ where
                     .get(&addr)
                     .and_then(|acc| acc.get(&idx).copied());
                 if let Some(value) = value {
+                    BackendCounters::incr(&self.counters.storage_hits);
                     let _ = sender.send(Ok(value));
                 } else {
+                    BackendCounters::incr(&self.counters.storage_misses);
                     // account present but not storage -> fetch storage
                     self.request_account_storage(addr, idx, sender);
                 }
             }
             BackendRequest::SetPinnedBlock(block_id) => {
+                // Storage is block sensitive, the old block's state is stale. The
+                // first pin keeps the state loaded from the JSON cache.
+                if self.prune_on_repin && self.block_id.is_some_and(|current| current != block_id) {
+                    trace!(target: "backendhandler", ?block_id, "pruning state of the previous block");
+                    self.clear_state();
+                }
                 self.block_id = Some(block_id);
             }
+            BackendRequest::SetRetryConfig(retry) => {
+                self.retry = retry;
+            }
+            BackendRequest::SetRequestTimeout(timeout) => {
+                self.request_timeout = timeout;
+            }
+            BackendRequest::SetPriorityLanes(enabled) => {
+                self.priority_lanes = enabled;
+            }
+            BackendRequest::SetMaxInFlight(max_in_flight) => {
+                // A cap of zero would never dispatch anything.
+                self.max_in_flight = max_in_flight.map(|max_in_flight| max_in_flight.max(1));
+            }
+            BackendRequest::SetMaxCacheEntries(max_entries) => {
+                self.max_cache_entries = max_entries;
+                if max_entries.is_none() {
+                    self.fetched.clear();
+                }
+                self.enforce_cache_limit();
+            }
+            BackendRequest::SetPruneOnRepin(enabled) => {
+                self.prune_on_repin = enabled;
+            }
+            BackendRequest::ClearState => {
+                self.clear_state();
+            }
             BackendRequest::UpdateAddress(address_data) => {
                 for (address, data) in address_data {
                     self.db.accounts().write().insert(address, data);
@@ -257,6 +748,9 @@ where
                     self.db.storage().write().insert(address, data);
                 }
             }
+            BackendRequest::SeedAccount(address, info, storage) => {
+                self.db.db().do_seed_account(address, info, storage);
+            }
             BackendRequest::UpdateBlockHash(block_hash_data) => {
                 for (block, hash) in block_hash_data {
                     self.db.block_hashes().write().insert(block, hash);
@@ -265,6 +759,99 @@ where
             BackendRequest::AnyRequest(fut) => {
                 self.pending_requests.push(ProviderRequest::AnyRequest(fut));
             }
+            BackendRequest::DrainAndFlush(sender) => {
+                trace!(target: "backendhandler", "draining requests before shutdown");
+                // messages already buffered in the channel are still delivered, new sends fail
+                self.incoming.close();
+                self.drain_listeners.push(sender);
+            }
+            BackendRequest::Ping(sender) => {
+                let _ = sender.send(());
+            }
+            BackendRequest::DumpStorage(address, sender) => match self.file_db_factory.clone() {
+                Some(file_db_factory) => {
+                    let block_number = self.dump_block_number();
+                    let future = Box::pin(async move {
+                        dump_plain_storage(&file_db_factory, address, block_number?)
+                    });
+                    self.pending_requests
+                        .push(ProviderRequest::AnyRequest(Box::new(AnyRequestFuture {
+                            sender,
+                            future,
+                        })));
+                }
+                None => {
+                    let _ = sender.send(Err(eyre::eyre!("storage dumps require a local database")));
+                }
+            },
+        }
+    }
+
+    /// looks up code by hash among the accounts fetched so far
+    fn code_by_hash(&mut self, hash: B256) -> DatabaseResult<Bytecode> {
+        if hash == KECCAK_EMPTY {
+            return Ok(Bytecode::default());
+        }
+
+        if let Some(code) = self.contracts.get(&hash) {
+            return Ok(code.clone());
+        }
+
+        // accounts loaded from the json cache never went through the provider
+        let code = self
+            .db
+            .accounts()
+            .read()
+            .values()
+            .find(|acc| acc.code_hash == hash)
+            .and_then(|acc| acc.code.clone());
+
+        match code {
+            Some(code) => {
+                self.contracts.insert(hash, code.clone());
+                Ok(code)
+            }
+            None => Err(DatabaseError::MissingCode(hash)),
+        }
+    }
+
+    /// Returns the local DB factory and the pinned block number to read it at.
+    ///
+    /// The local DB is only read at a concrete block number. If the pinned
+    /// block is unset, a tag like `latest` or a hash, requests go through the
+    /// provider instead, which resolves the block id itself.
+    fn local_db(&self) -> Option<(&DBFactory, u64)> {
+        let file_db_factory = self.file_db_factory.as_ref()?;
+
+        match self.block_id.and_then(|block_id| block_id.as_u64()) {
+            Some(block_number) => Some((file_db_factory, block_number)),
+            None => {
+                trace!(
+                    target: "backendhandler",
+                    block_id = ?self.block_id,
+                    "pinned block is not a number, skipping local db"
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns the block number a storage dump is checked against, `None` for
+    /// the tip.
+    ///
+    /// The plain state only holds the tip, so an unset or `latest` block needs
+    /// no check, and a tag or hash other than `latest` can't be checked.
+    fn dump_block_number(&self) -> eyre::Result<Option<u64>> {
+        match self.block_id {
+            Some(block_id) if !block_id.is_latest() => {
+                block_id.as_u64().map(Some).ok_or_else(|| {
+                    eyre::eyre!(
+                        "storage dumps need a block number or latest. block_id={:?}",
+                        block_id
+                    )
+                })
+            }
+            _ => Ok(None),
         }
     }
 
@@ -278,42 +865,34 @@ where
                 trace!(target: "backendhandler", %address, %idx, "preparing storage request");
                 entry.insert(vec![listener]);
 
-                let mut use_provider = false;
-
-                if let Some(file_db_factory) = &self.file_db_factory {
-                    let block_number = self.block_id.unwrap().as_u64().unwrap();
-                    match file_db_factory.history_by_block_number(block_number) {
-                        Ok(state_provider) => {
-                            let fut = Box::pin(async move {
-                                let storage = state_provider
-                                    .storage(address, idx.into())
-                                    .map_err(Into::into)
-                                    .and_then(|res| Ok(res.unwrap_or(U256::ZERO)));
-                                (storage, address, idx)
-                            });
-                            self.pending_requests.push(ProviderRequest::Storage(fut));
-                        }
-                        Err(_) => {
-                            use_provider = true;
-                        }
-                    }
-                } else {
-                    use_provider = true;
-                }
+                let provider = self.provider.clone();
+                let block_id = self.block_id.unwrap_or_default();
+                let (retry, timeout) = (self.retry, self.request_timeout);
+                let remote =
+                    provider_storage::<T, _>(provider, block_id, retry, timeout, address, idx);
+
+                let local = self.local_db().and_then(|(file_db_factory, block_number)| {
+                    file_db_factory
+                        .history_by_block_number(block_number)
+                        .inspect_err(|err| {
+                            warn!(
+                                target: "backendhandler",
+                                %block_number,
+                                ?err,
+                                "failed to open local state, falling back to provider"
+                            );
+                        })
+                        .ok()
+                });
 
-                if use_provider {
-                    let provider = self.provider.clone();
-                    let block_id = self.block_id.unwrap_or_default();
-                    let fut = Box::pin(async move {
-                        let storage = provider
-                            .get_storage_at(address, idx)
-                            .block_id(block_id)
-                            .await
-                            .map_err(Into::into);
+                let fut: StorageFuture<eyre::Report> = match local {
+                    Some(state_provider) => Box::pin(async move {
+                        let storage = local_storage(&state_provider, address, idx, remote).await;
                         (storage, address, idx)
-                    });
-                    self.pending_requests.push(ProviderRequest::Storage(fut));
-                }
+                    }),
+                    None => Box::pin(async move { (remote.await, address, idx) }),
+                };
+                self.pending_requests.push(ProviderRequest::Storage(fut));
             }
         }
     }
@@ -322,8 +901,7 @@ where
     fn get_account_req(&self, address: Address) -> ProviderRequest<eyre::Report> {
         trace!(target: "backendhandler", "preparing account request, address={:?}", address);
 
-        if let Some(file_db_factory) = &self.file_db_factory {
-            let block_number = self.block_id.unwrap().as_u64().unwrap();
+        if let Some((file_db_factory, block_number)) = self.local_db() {
             match file_db_factory.history_by_block_number(block_number) {
                 Ok(state_provider) => {
                     let fut = Box::pin(async move {
@@ -361,26 +939,38 @@ where
                     });
                     return ProviderRequest::Account(fut);
                 }
-                Err(_) => {}
+                Err(err) => {
+                    warn!(
+                        target: "backendhandler",
+                        %block_number,
+                        ?err,
+                        "failed to open local state, falling back to provider"
+                    );
+                }
             }
         }
 
         let provider = self.provider.clone();
         let block_id = self.block_id.unwrap_or_default();
+        let (retry, timeout) = (self.retry, self.request_timeout);
         let fut = Box::pin(async move {
-            let balance = provider
-                .get_balance(address)
-                .block_id(block_id)
-                .into_future();
-            let nonce = provider
-                .get_transaction_count(address)
-                .block_id(block_id)
-                .into_future();
-            let code = provider
-                .get_code_at(address)
-                .block_id(block_id)
-                .into_future();
-            let resp = tokio::try_join!(balance, nonce, code).map_err(Into::into);
+            let resp = with_retry(retry, timeout, || {
+                let balance = provider
+                    .get_balance(address)
+                    .block_id(block_id)
+                    .into_future();
+                let nonce = provider
+                    .get_transaction_count(address)
+                    .block_id(block_id)
+                    .into_future();
+                let code = provider
+                    .get_code_at(address)
+                    .block_id(block_id)
+                    .into_future();
+                async move { tokio::try_join!(balance, nonce, code) }
+            })
+            .await
+            .map_err(Into::into);
             (resp, address)
         });
         ProviderRequest::Account(fut)
@@ -402,9 +992,9 @@ where
     /// process a request for an entire block
     fn request_full_block(&mut self, number: BlockId, sender: FullBlockSender) {
         let provider = self.provider.clone();
+        let (retry, timeout) = (self.retry, self.request_timeout);
         let fut = Box::pin(async move {
-            let block = provider
-                .get_block(number, true.into())
+            let block = with_retry(retry, timeout, || provider.get_block(number, true.into()))
                 .await
                 .wrap_err("could not fetch block {number:?}");
             (sender, block, number)
@@ -413,17 +1003,36 @@ where
         self.pending_requests.push(ProviderRequest::FullBlock(fut));
     }
 
+    /// process a request for a block header, fetching the block with
+    /// transaction hashes only
+    fn request_header(&mut self, number: BlockId, sender: HeaderSender) {
+        let provider = self.provider.clone();
+        let (retry, timeout) = (self.retry, self.request_timeout);
+        let fut = Box::pin(async move {
+            let header = with_retry(retry, timeout, || {
+                provider.get_block(number, alloy_rpc_types::BlockTransactionsKind::Hashes)
+            })
+            .await
+            .wrap_err_with(|| format!("could not fetch block header {number:?}"))
+            .map(|block| block.map(|block| block.header.clone()));
+            (sender, header, number)
+        });
+
+        self.pending_requests.push(ProviderRequest::Header(fut));
+    }
+
     /// process a request for a transactions
     fn request_transaction(&mut self, tx: B256, sender: TransactionSender) {
         let provider = self.provider.clone();
+        let (retry, timeout) = (self.retry, self.request_timeout);
         let fut = Box::pin(async move {
-            let block = provider
-                .get_transaction_by_hash(tx)
-                .await
-                .wrap_err_with(|| format!("could not get transaction {tx}"))
-                .and_then(|maybe| {
-                    maybe.ok_or_else(|| eyre::eyre!("could not get transaction {tx}"))
-                });
+            let block =
+                with_retry(retry, timeout, || provider.get_transaction_by_hash(tx).into_future())
+                    .await
+                    .wrap_err_with(|| format!("could not get transaction {tx}"))
+                    .and_then(|maybe| {
+                        maybe.ok_or_else(|| eyre::eyre!("could not get transaction {tx}"))
+                    });
             (sender, block, tx)
         });
 
@@ -431,6 +1040,33 @@ where
             .push(ProviderRequest::Transaction(fut));
     }
 
+    /// process a request for a transaction receipt, joining the one in flight
+    /// for the same hash
+    fn request_receipt(&mut self, tx: B256, listener: ReceiptSender) {
+        match self.receipt_requests.entry(tx) {
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().push(listener);
+            }
+            Entry::Vacant(entry) => {
+                trace!(target: "backendhandler", %tx, "preparing receipt request");
+                entry.insert(vec![listener]);
+
+                let provider = self.provider.clone();
+                let (retry, timeout) = (self.retry, self.request_timeout);
+                let fut = Box::pin(async move {
+                    let receipt = with_retry(retry, timeout, || {
+                        provider.get_transaction_receipt(tx).into_future()
+                    })
+                    .await
+                    .wrap_err_with(|| format!("could not get receipt {tx}"));
+                    (receipt, tx)
+                });
+
+                self.pending_requests.push(ProviderRequest::Receipt(fut));
+            }
+        }
+    }
+
     /// process a request for a block hash
     fn request_hash(&mut self, number: u64, listener: BlockHashSender) {
         match self.block_requests.entry(number) {
@@ -440,15 +1076,41 @@ where
             Entry::Vacant(entry) => {
                 trace!(target: "backendhandler", number, "preparing block hash request");
                 entry.insert(vec![listener]);
+
+                // Canonical hashes don't depend on the pinned block, any block the
+                // local db has synced can be read from it.
+                if let Some(file_db_factory) = self.file_db_factory.as_ref() {
+                    match file_db_factory.block_hash(number) {
+                        Ok(Some(block_hash)) => {
+                            let fut = Box::pin(async move { (Ok(block_hash), number) });
+                            self.pending_requests.push(ProviderRequest::BlockHash(fut));
+                            return;
+                        }
+                        Ok(None) => {
+                            trace!(target: "backendhandler", number, "block hash not in local db, falling back to provider");
+                        }
+                        Err(err) => {
+                            warn!(
+                                target: "backendhandler",
+                                number,
+                                ?err,
+                                "failed to read block hash from local db, falling back to provider"
+                            );
+                        }
+                    }
+                }
+
                 let provider = self.provider.clone();
+                let (retry, timeout) = (self.retry, self.request_timeout);
                 let fut = Box::pin(async move {
-                    let block = provider
-                        .get_block_by_number(
+                    let block = with_retry(retry, timeout, || {
+                        provider.get_block_by_number(
                             number.into(),
                             alloy_rpc_types::BlockTransactionsKind::Hashes,
                         )
-                        .await
-                        .wrap_err("failed to get block");
+                    })
+                    .await
+                    .wrap_err("failed to get block");
 
                     let block_hash = match block {
                         Ok(Some(block)) => Ok(block.header.hash),
@@ -481,20 +1143,22 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let pin = self.get_mut();
         loop {
-            // Drain queued requests first.
-            while let Some(req) = pin.queued_requests.pop_front() {
-                pin.on_request(req)
-            }
+            // Dispatch queued requests first, as far as the in-flight cap allows.
+            pin.dispatch_queued_requests();
 
             // receive new requests to delegate to the underlying provider
             loop {
                 match Pin::new(&mut pin.incoming).poll_next(cx) {
                     Poll::Ready(Some(req)) => {
-                        pin.queued_requests.push_back(req);
+                        pin.queue_request(req);
                     }
                     Poll::Ready(None) => {
-                        trace!(target: "backendhandler", "last sender dropped, ready to drop (&flush cache)");
-                        return Poll::Ready(());
+                        if pin.drain_listeners.is_empty() {
+                            trace!(target: "backendhandler", "last sender dropped, ready to drop (&flush cache)");
+                            return Poll::Ready(());
+                        }
+                        // draining, keep polling the requests that are still in progress
+                        break;
                     }
                     Poll::Pending => break,
                 }
@@ -513,9 +1177,10 @@ where
                                     let err = Arc::new(err);
                                     if let Some(listeners) = pin.account_requests.remove(&addr) {
                                         listeners.into_iter().for_each(|l| {
-                                            let _ = l.send(Err(DatabaseError::GetAccount(
-                                                addr,
+                                            let _ = l.send(Err(DatabaseError::from_request(
+                                                RequestKind::Account,
                                                 Arc::clone(&err),
+                                                |err| DatabaseError::GetAccount(addr, err),
                                             )));
                                         })
                                     }
@@ -537,7 +1202,12 @@ where
                                 code: Some(Bytecode::new_raw(code)),
                                 code_hash,
                             };
+                            if code_hash != KECCAK_EMPTY {
+                                pin.contracts
+                                    .insert(code_hash, acc.code.clone().unwrap_or_default());
+                            }
                             pin.db.accounts().write().insert(addr, acc.clone());
+                            pin.record_fetched(FetchedEntry::Account(addr));
 
                             // notify all listeners
                             if let Some(listeners) = pin.account_requests.remove(&addr) {
@@ -559,10 +1229,10 @@ where
                                         pin.storage_requests.remove(&(addr, idx))
                                     {
                                         listeners.into_iter().for_each(|l| {
-                                            let _ = l.send(Err(DatabaseError::GetStorage(
-                                                addr,
-                                                idx,
+                                            let _ = l.send(Err(DatabaseError::from_request(
+                                                RequestKind::Storage,
                                                 Arc::clone(&err),
+                                                |err| DatabaseError::GetStorage(addr, idx, err),
                                             )));
                                         })
                                     }
@@ -577,6 +1247,7 @@ where
                                 .entry(addr)
                                 .or_default()
                                 .insert(idx, value);
+                            pin.record_fetched(FetchedEntry::Storage(addr, idx));
 
                             // notify all listeners
                             if let Some(listeners) = pin.storage_requests.remove(&(addr, idx)) {
@@ -596,9 +1267,10 @@ where
                                     // notify all listeners
                                     if let Some(listeners) = pin.block_requests.remove(&number) {
                                         listeners.into_iter().for_each(|l| {
-                                            let _ = l.send(Err(DatabaseError::GetBlockHash(
-                                                number,
+                                            let _ = l.send(Err(DatabaseError::from_request(
+                                                RequestKind::BlockHash,
                                                 Arc::clone(&err),
+                                                |err| DatabaseError::GetBlockHash(number, err),
                                             )));
                                         })
                                     }
@@ -628,7 +1300,35 @@ where
                                 Ok(None) => Err(DatabaseError::BlockNotFound(number)),
                                 Err(err) => {
                                     let err = Arc::new(err);
-                                    Err(DatabaseError::GetFullBlock(number, err))
+                                    Err(DatabaseError::from_request(
+                                        RequestKind::FullBlock,
+                                        err,
+                                        |err| DatabaseError::GetFullBlock(number, err),
+                                    ))
+                                }
+                            };
+                            let _ = sender.send(msg);
+                            continue;
+                        }
+                    }
+                    ProviderRequest::Header(fut) => {
+                        if let Poll::Ready((sender, resp, number)) = fut.poll_unpin(cx) {
+                            let msg = match resp {
+                                Ok(Some(header)) => {
+                                    pin.db
+                                        .block_headers()
+                                        .write()
+                                        .insert(header.number, header.clone());
+                                    Ok(header)
+                                }
+                                Ok(None) => Err(DatabaseError::BlockNotFound(number)),
+                                Err(err) => {
+                                    let err = Arc::new(err);
+                                    Err(DatabaseError::from_request(
+                                        RequestKind::BlockHeader,
+                                        err,
+                                        |err| DatabaseError::GetBlockHeader(number, err),
+                                    ))
                                 }
                             };
                             let _ = sender.send(msg);
@@ -641,13 +1341,39 @@ where
                                 Ok(tx) => Ok(tx),
                                 Err(err) => {
                                     let err = Arc::new(err);
-                                    Err(DatabaseError::GetTransaction(tx_hash, err))
+                                    Err(DatabaseError::from_request(
+                                        RequestKind::Transaction,
+                                        err,
+                                        |err| DatabaseError::GetTransaction(tx_hash, err),
+                                    ))
                                 }
                             };
                             let _ = sender.send(msg);
                             continue;
                         }
                     }
+                    ProviderRequest::Receipt(fut) => {
+                        if let Poll::Ready((resp, tx_hash)) = fut.poll_unpin(cx) {
+                            let resp = resp.map_err(Arc::new);
+
+                            // notify all listeners
+                            if let Some(listeners) = pin.receipt_requests.remove(&tx_hash) {
+                                listeners.into_iter().for_each(|l| {
+                                    let msg = match &resp {
+                                        Ok(Some(receipt)) => Ok(receipt.clone()),
+                                        Ok(None) => Err(DatabaseError::ReceiptNotFound(tx_hash)),
+                                        Err(err) => Err(DatabaseError::from_request(
+                                            RequestKind::Receipt,
+                                            Arc::clone(err),
+                                            |err| DatabaseError::GetReceipt(tx_hash, err),
+                                        )),
+                                    };
+                                    let _ = l.send(msg);
+                                })
+                            }
+                            continue;
+                        }
+                    }
                     ProviderRequest::AnyRequest(fut) => {
                         if fut.poll_inner(cx).is_ready() {
                             continue;
@@ -658,15 +1384,64 @@ where
                 pin.pending_requests.push(request);
             }
 
+            if mem::take(&mut pin.fetched_since_limit_check) {
+                pin.enforce_cache_limit();
+            }
+
+            // All in-flight requests are done, flush and notify the drain listeners.
+            if !pin.drain_listeners.is_empty()
+                && pin.pending_requests.is_empty()
+                && !pin.has_queued_requests()
+            {
+                pin.db.cache().flush();
+                trace!(target: "backendhandler", "drained and flushed cache");
+                for listener in pin.drain_listeners.drain(..) {
+                    let _ = listener.send(());
+                }
+                return Poll::Ready(());
+            }
+
             // If no new requests have been queued, break to
             // be polled again later.
-            if pin.queued_requests.is_empty() {
+            //
+            // At capacity, every pending request was just polled and wakes the
+            // handler when it completes, freeing a slot.
+            if !pin.has_queued_requests() || pin.at_capacity() {
                 return Poll::Pending;
             }
         }
     }
 }
 
+/// Waits for the handler's response, warning every `WATCHDOG_INTERVAL` so a
+/// stuck handler doesn't hang silently. Fails if the handler dropped the
+/// request, e.g. because it panicked, or once `timeout` has passed.
+fn recv_response<T>(
+    rx: &OneshotReceiver<T>,
+    kind: RequestKind,
+    timeout: Option<Duration>,
+) -> DatabaseResult<T> {
+    let start = Instant::now();
+    loop {
+        let wait = match timeout {
+            Some(timeout) => timeout
+                .saturating_sub(start.elapsed())
+                .min(WATCHDOG_INTERVAL),
+            None => WATCHDOG_INTERVAL,
+        };
+        match rx.recv_timeout(wait) {
+            Ok(response) => return Ok(response),
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(timeout) = timeout.filter(|timeout| start.elapsed() >= *timeout) {
+                    return Err(DatabaseError::Timeout(kind, timeout));
+                }
+                warn!(target: "backendhandler", "still waiting for the backend handler after {:?}", start.elapsed());
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(DatabaseError::HandlerClosed),
+        }
+    }
+}
+
 /// Mode for the `SharedBackend` how to block in the non-async [`DatabaseRef`]
 /// when interacting with [`BackendHandler`].
 #[derive(Default, Clone, Debug, PartialEq)]
@@ -681,6 +1456,11 @@ pub enum BlockingMode {
     /// This can be used if blocking on the call site is allowed, e.g. on a
     /// tokio blocking task.
     Block,
+    /// Blocks in place like `BlockInPlace`, but gives up waiting for a
+    /// response after the duration with [`DatabaseError::Timeout`].
+    ///
+    /// The request stays with the handler, a late response is dropped.
+    Timeout(Duration),
 }
 
 impl BlockingMode {
@@ -690,10 +1470,18 @@ impl BlockingMode {
         F: FnOnce() -> R,
     {
         match self {
-            Self::BlockInPlace => tokio::task::block_in_place(f),
+            Self::BlockInPlace | Self::Timeout(_) => tokio::task::block_in_place(f),
             Self::Block => f(),
         }
     }
+
+    /// How long to wait for a response, unbounded if `None`
+    pub const fn response_timeout(&self) -> Option<Duration> {
+        match self {
+            Self::Timeout(timeout) => Some(*timeout),
+            Self::BlockInPlace | Self::Block => None,
+        }
+    }
 }
 
 /// A cloneable backend type that shares access to the backend data with all its
@@ -742,6 +1530,9 @@ pub struct SharedBackend {
 
     /// The mode for the `SharedBackend` to block in place or not
     blocking_mode: BlockingMode,
+
+    /// Request counters updated by the `BackendHandler`
+    counters: Arc<BackendCounters>,
 }
 
 impl SharedBackend {
@@ -815,17 +1606,80 @@ impl SharedBackend {
     {
         let (backend, backend_rx) = unbounded();
         let cache = Arc::new(FlushJsonBlockCacheDB(Arc::clone(db.cache())));
-        let handler = BackendHandler::new(provider, file_db_factory, db, backend_rx, pin_block);
-        (Self { backend, cache, blocking_mode: Default::default() }, handler)
+        let counters = Arc::new(BackendCounters::default());
+        let handler = BackendHandler::new(
+            provider,
+            file_db_factory,
+            db,
+            backend_rx,
+            pin_block,
+            Arc::clone(&counters),
+        );
+        (Self { backend, cache, blocking_mode: Default::default(), counters }, handler)
     }
 
     /// Returns a new `SharedBackend` and the `BackendHandler` with a specific
     /// blocking mode
     pub fn with_blocking_mode(&self, mode: BlockingMode) -> Self {
-        Self { backend: self.backend.clone(), cache: self.cache.clone(), blocking_mode: mode }
+        Self {
+            backend: self.backend.clone(),
+            cache: self.cache.clone(),
+            blocking_mode: mode,
+            counters: self.counters.clone(),
+        }
+    }
+
+    /// Returns a snapshot of the request counters of the `BackendHandler`.
+    ///
+    /// Counters are shared by all clones and accumulate from the moment the
+    /// backend was created.
+    pub fn metrics(&self) -> BackendMetrics {
+        self.counters.snapshot()
+    }
+
+    /// Returns the number of cached entries and an estimate of their size
+    pub fn cache_footprint(&self) -> MemDbFootprint {
+        self.cache.0.db().footprint()
+    }
+
+    /// Caps the fetched accounts and storage slots kept in memory, evicting the
+    /// oldest fetched ones first. Evicted entries are fetched again when read.
+    /// `None` removes the cap.
+    pub fn set_max_cache_entries(&self, max_entries: Option<usize>) -> eyre::Result<()> {
+        let req = BackendRequest::SetMaxCacheEntries(max_entries);
+        self.backend
+            .unbounded_send(req)
+            .map_err(|e| eyre::eyre!("{:?}", e))
+    }
+
+    /// Drops the fetched accounts and storage whenever the pinned block
+    /// changes, since they belong to the old block. Off by default, so
+    /// [`Self::set_pinned_block`] alone keeps serving the old block's values
+    /// for everything already fetched.
+    pub fn set_prune_on_repin(&self, enabled: bool) -> eyre::Result<()> {
+        let req = BackendRequest::SetPruneOnRepin(enabled);
+        self.backend
+            .unbounded_send(req)
+            .map_err(|e| eyre::eyre!("{:?}", e))
+    }
+
+    /// Drops the cached accounts and storage, e.g. after
+    /// [`Self::set_pinned_block`], so they are fetched again at the new block.
+    /// Block hashes and headers are kept.
+    ///
+    /// The request is ordered with the other requests of this backend, but a
+    /// fetch of another clone still in flight may insert its old value after
+    /// the clear.
+    pub fn clear_cache(&self) -> eyre::Result<()> {
+        self.backend
+            .unbounded_send(BackendRequest::ClearState)
+            .map_err(|e| eyre::eyre!("{:?}", e))
     }
 
-    /// Updates the pinned block to fetch data from
+    /// Updates the pinned block to fetch data from.
+    ///
+    /// Already cached accounts and storage are still served as they are, see
+    /// [`Self::set_prune_on_repin`] and [`Self::clear_cache`].
     pub fn set_pinned_block(&self, block: impl Into<BlockId>) -> eyre::Result<()> {
         let req = BackendRequest::SetPinnedBlock(block.into());
         self.backend
@@ -833,13 +1687,90 @@ impl SharedBackend {
             .map_err(|e| eyre::eyre!("{:?}", e))
     }
 
+    /// Sets the retry policy for provider requests, see [`RetryConfig`].
+    pub fn set_retry_config(&self, retry: RetryConfig) -> eyre::Result<()> {
+        let req = BackendRequest::SetRetryConfig(retry);
+        self.backend
+            .unbounded_send(req)
+            .map_err(|e| eyre::eyre!("{:?}", e))
+    }
+
+    /// Fails provider requests that take longer than `timeout` instead of
+    /// waiting on them forever. Blocking calls then return a `DatabaseError`.
+    /// `None` removes the limit.
+    pub fn set_request_timeout(&self, timeout: Option<Duration>) -> eyre::Result<()> {
+        let req = BackendRequest::SetRequestTimeout(timeout);
+        self.backend
+            .unbounded_send(req)
+            .map_err(|e| eyre::eyre!("{:?}", e))
+    }
+
+    /// Serves account, storage and block hash lookups before full block,
+    /// header and transaction fetches queued alongside them. Enabled by
+    /// default, disabling it serves requests in arrival order.
+    pub fn set_priority_lanes(&self, enabled: bool) -> eyre::Result<()> {
+        let req = BackendRequest::SetPriorityLanes(enabled);
+        self.backend
+            .unbounded_send(req)
+            .map_err(|e| eyre::eyre!("{:?}", e))
+    }
+
+    /// Whether the `BackendHandler` is running and answers a ping within
+    /// `PING_TIMEOUT`. Queued requests are answered first, so a busy handler
+    /// can be reported as not alive.
+    pub fn is_alive(&self) -> bool {
+        self.blocking_mode.run(|| {
+            let (sender, rx) = oneshot_channel();
+            self.send_request(BackendRequest::Ping(sender)).is_ok()
+                && rx.recv_timeout(PING_TIMEOUT).is_ok()
+        })
+    }
+
+    /// Waits for the response to a `kind` request, bounded by the blocking
+    /// mode's timeout.
+    fn recv<T>(&self, rx: &OneshotReceiver<T>, kind: RequestKind) -> DatabaseResult<T> {
+        recv_response(rx, kind, self.blocking_mode.response_timeout())
+    }
+
+    /// Sends `req` to the handler, failing right away if it has exited.
+    fn send_request(&self, req: BackendRequest) -> DatabaseResult<()> {
+        if self.backend.is_closed() {
+            return Err(DatabaseError::HandlerClosed);
+        }
+        self.backend
+            .unbounded_send(req)
+            .map_err(|_| DatabaseError::HandlerClosed)
+    }
+
+    /// Limits the provider requests the handler runs at once, excess requests
+    /// wait until one completes. Keeps a cold fork from tripping the rate
+    /// limit of an endpoint. `None` removes the limit.
+    pub fn set_max_in_flight(&self, max_in_flight: Option<usize>) -> eyre::Result<()> {
+        let req = BackendRequest::SetMaxInFlight(max_in_flight);
+        self.backend
+            .unbounded_send(req)
+            .map_err(|e| eyre::eyre!("{:?}", e))
+    }
+
     /// Returns the full block for the given block identifier
     pub fn get_full_block(&self, block: impl Into<BlockId>) -> DatabaseResult<AnyRpcBlock> {
         self.blocking_mode.run(|| {
             let (sender, rx) = oneshot_channel();
             let req = BackendRequest::FullBlock(block.into(), sender);
-            self.backend.unbounded_send(req)?;
-            rx.recv()?
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::FullBlock)?
+        })
+    }
+
+    /// Returns the header of the given block, without fetching its
+    /// transactions. Headers fetched by number are cached in the
+    /// `BlockchainDb`.
+    pub fn get_block_header(&self, block: impl Into<BlockId>) -> DatabaseResult<AnyRpcHeader> {
+        self.blocking_mode.run(|| {
+            let (sender, rx) = oneshot_channel();
+            let req = BackendRequest::Header(block.into(), sender);
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::BlockHeader)?
         })
     }
 
@@ -851,8 +1782,64 @@ impl SharedBackend {
         self.blocking_mode.run(|| {
             let (sender, rx) = oneshot_channel();
             let req = BackendRequest::Transaction(tx, sender);
-            self.backend.unbounded_send(req)?;
-            rx.recv()?
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::Transaction)?
+        })
+    }
+
+    /// Returns the receipt of a mined transaction, with its logs and the gas it
+    /// actually used
+    pub fn get_transaction_receipt(&self, tx: B256) -> DatabaseResult<AnyTransactionReceipt> {
+        self.blocking_mode.run(|| {
+            let (sender, rx) = oneshot_channel();
+            let req = BackendRequest::Receipt(tx, sender);
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::Receipt)?
+        })
+    }
+
+    /// Returns the logs emitted by a mined transaction, in order
+    pub fn get_transaction_logs(&self, tx: B256) -> DatabaseResult<Vec<Log>> {
+        self.get_transaction_receipt(tx)
+            .map(|receipt| receipt.inner.inner.logs().to_vec())
+    }
+
+    /// Fetches the account info of all `addresses` concurrently.
+    ///
+    /// All requests are queued before waiting on any of them, so the handler
+    /// fetches them in parallel instead of one round trip at a time. Accounts
+    /// already in the cache or already being fetched are not requested again.
+    pub fn prefetch_accounts(&self, addresses: &[Address]) -> DatabaseResult<()> {
+        self.blocking_mode.run(|| {
+            let mut receivers = Vec::with_capacity(addresses.len());
+            for address in addresses {
+                let (sender, rx) = oneshot_channel();
+                self.send_request(BackendRequest::Basic(*address, sender))?;
+                receivers.push(rx);
+            }
+
+            for rx in receivers {
+                self.recv(&rx, RequestKind::Account)??;
+            }
+            Ok(())
+        })
+    }
+
+    /// Fetches all `(address, index)` storage slots concurrently, see
+    /// [`Self::prefetch_accounts`].
+    pub fn prefetch_storage(&self, slots: &[(Address, U256)]) -> DatabaseResult<()> {
+        self.blocking_mode.run(|| {
+            let mut receivers = Vec::with_capacity(slots.len());
+            for (address, index) in slots {
+                let (sender, rx) = oneshot_channel();
+                self.send_request(BackendRequest::Storage(*address, *index, sender))?;
+                receivers.push(rx);
+            }
+
+            for rx in receivers {
+                self.recv(&rx, RequestKind::Storage)??;
+            }
+            Ok(())
         })
     }
 
@@ -860,8 +1847,8 @@ impl SharedBackend {
         self.blocking_mode.run(|| {
             let (sender, rx) = oneshot_channel();
             let req = BackendRequest::Basic(address, sender);
-            self.backend.unbounded_send(req)?;
-            rx.recv()?.map(Some)
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::Account)?.map(Some)
         })
     }
 
@@ -869,8 +1856,17 @@ impl SharedBackend {
         self.blocking_mode.run(|| {
             let (sender, rx) = oneshot_channel();
             let req = BackendRequest::Storage(address, index, sender);
-            self.backend.unbounded_send(req)?;
-            rx.recv()?
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::Storage)?
+        })
+    }
+
+    fn do_get_code_by_hash(&self, hash: B256) -> DatabaseResult<Bytecode> {
+        self.blocking_mode.run(|| {
+            let (sender, rx) = oneshot_channel();
+            let req = BackendRequest::CodeByHash(hash, sender);
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::Code)?
         })
     }
 
@@ -878,8 +1874,8 @@ impl SharedBackend {
         self.blocking_mode.run(|| {
             let (sender, rx) = oneshot_channel();
             let req = BackendRequest::BlockHash(number, sender);
-            self.backend.unbounded_send(req)?;
-            rx.recv()?
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::BlockHash)?
         })
     }
 
@@ -907,6 +1903,19 @@ impl SharedBackend {
         }
     }
 
+    /// Inserts or updates an account and merges `storage` into its slots in a
+    /// single message, so no reader sees a half-seeded account
+    pub fn seed_account(&self, address: Address, info: AccountInfo, storage: StorageInfo) {
+        let req = BackendRequest::SeedAccount(address, info, storage);
+        let err = self.backend.unbounded_send(req);
+        match err {
+            Ok(_) => (),
+            Err(e) => {
+                error!(target: "sharedbackend", "Failed to send seed account request: {:?}", e)
+            }
+        }
+    }
+
     /// Inserts or updates data for multiple block hashes
     pub fn insert_or_update_block_hashes(&self, block_hash_data: BlockHashData) {
         let req = BackendRequest::UpdateBlockHash(block_hash_data);
@@ -931,12 +1940,28 @@ impl SharedBackend {
                 sender,
                 future: Box::pin(fut),
             }));
-            self.backend.unbounded_send(req)?;
-            rx.recv()?
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::Any)?
                 .map_err(|err| DatabaseError::AnyRequest(Arc::new(err)))
         })
     }
 
+    /// Returns all storage slots of `address`, read from the local reth DB.
+    ///
+    /// This reads the plain state table, which only holds the tip of the
+    /// local node, so it fails unless the pinned block is `latest` or the tip's
+    /// number. Also fails if the backend was created without a
+    /// `file_db_factory`, as RPC can't enumerate storage.
+    pub fn dump_account_storage(&self, address: Address) -> DatabaseResult<StorageDump> {
+        self.blocking_mode.run(|| {
+            let (sender, rx) = oneshot_channel();
+            let req = BackendRequest::DumpStorage(address, sender);
+            self.send_request(req)?;
+            self.recv(&rx, RequestKind::DumpStorage)?
+                .map_err(|err| DatabaseError::DumpStorage(address, Arc::new(err)))
+        })
+    }
+
     /// Flushes the DB to disk if caching is enabled
     pub fn flush_cache(&self) {
         self.cache.0.flush();
@@ -947,6 +1972,23 @@ impl SharedBackend {
         self.cache.0.flush_to(cache_path);
     }
 
+    /// Shuts the `BackendHandler` down cleanly.
+    ///
+    /// The handler stops accepting new requests, but every request sent before
+    /// this call (by any `SharedBackend` clone) is still answered. Once all of
+    /// them have completed the cache is flushed and the handler exits. Requests
+    /// sent by remaining clones afterwards fail with
+    /// [`DatabaseError::HandlerClosed`].
+    pub fn drain_and_flush(self) -> DatabaseResult<()> {
+        self.blocking_mode.run(|| {
+            let (sender, rx) = oneshot_channel();
+            let req = BackendRequest::DrainAndFlush(sender);
+            self.backend.unbounded_send(req)?;
+            rx.recv()?;
+            Ok(())
+        })
+    }
+
     /// Returns the DB
     pub fn data(&self) -> Arc<MemDb> {
         self.cache.0.db().clone()
@@ -967,6 +2009,13 @@ impl SharedBackend {
         self.cache.0.db().storage.read().clone()
     }
 
+    /// Returns the DB storage slots changed since `baseline`, see
+    /// [BlockchainDb::storage_since]
+    pub fn storage_since(&self, baseline: &StorageData) -> StorageData {
+        let storage = self.cache.0.db().storage.read();
+        diff_storage(&storage, baseline)
+    }
+
     /// Returns the DB storage length
     pub fn storage_len(&self) -> usize {
         self.cache.0.db().storage.read().len()
@@ -998,7 +2047,11 @@ impl DatabaseRef for SharedBackend {
     }
 
     fn code_by_hash_ref(&self, hash: B256) -> Result<Bytecode, Self::Error> {
-        Err(DatabaseError::MissingCode(hash))
+        trace!(target: "sharedbackend", %hash, "request code by hash");
+        self.do_get_code_by_hash(hash).map_err(|err| {
+            error!(target: "sharedbackend", %err, %hash, "Failed to send/recv `code_by_hash`");
+            err
+        })
     }
 
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
@@ -1023,3 +2076,625 @@ impl DatabaseRef for SharedBackend {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap as StdHashMap;
+    use std::sync::Mutex;
+
+    use alloy_json_rpc::{
+        ErrorPayload, RequestPacket, Response, ResponsePacket, ResponsePayload, SerializedRequest,
+    };
+    use alloy_primitives::Bloom;
+    use alloy_provider::RootProvider;
+    use alloy_rpc_client::RpcClient;
+    use alloy_transport::TransportFut;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::cache::BlockchainDbMeta;
+
+    type MockResult = Result<Value, ErrorPayload>;
+
+    #[derive(Default)]
+    struct MockState {
+        /// Queued results per method, the last one is repeated.
+        results: StdHashMap<String, VecDeque<MockResult>>,
+        /// Params of every call, per method.
+        calls: StdHashMap<String, Vec<Value>>,
+    }
+
+    /// JSON-RPC transport answering every method from queued results, and
+    /// counting the calls it gets.
+    #[derive(Clone, Default)]
+    struct MockTransport {
+        state: Arc<Mutex<MockState>>,
+        /// Time taken by every response.
+        delay: Duration,
+    }
+
+    impl MockTransport {
+        fn with_delay(mut self, delay: Duration) -> Self {
+            self.delay = delay;
+            self
+        }
+
+        fn push(&self, method: &str, result: MockResult) {
+            let mut state = self.state.lock().unwrap();
+            state
+                .results
+                .entry(method.to_string())
+                .or_default()
+                .push_back(result);
+        }
+
+        fn calls(&self, method: &str) -> usize {
+            self.params(method).len()
+        }
+
+        fn params(&self, method: &str) -> Vec<Value> {
+            let state = self.state.lock().unwrap();
+            state.calls.get(method).cloned().unwrap_or_default()
+        }
+
+        fn respond(&self, req: &SerializedRequest) -> Response {
+            let params = req
+                .params()
+                .map(|params| serde_json::from_str(params.get()).unwrap())
+                .unwrap_or_default();
+
+            let mut state = self.state.lock().unwrap();
+            state
+                .calls
+                .entry(req.method().to_string())
+                .or_default()
+                .push(params);
+
+            let result = match state.results.get_mut(req.method()) {
+                Some(results) if results.len() > 1 => results.pop_front(),
+                Some(results) => results.front().cloned(),
+                None => None,
+            };
+            let payload = match result {
+                Some(Ok(value)) => {
+                    ResponsePayload::Success(serde_json::value::to_raw_value(&value).unwrap())
+                }
+                Some(Err(err)) => ResponsePayload::Failure(err),
+                None => ResponsePayload::Failure(ErrorPayload::method_not_found()),
+            };
+
+            Response { id: req.id().clone(), payload }
+        }
+    }
+
+    impl tower::Service<RequestPacket> for MockTransport {
+        type Response = ResponsePacket;
+        type Error = TransportError;
+        type Future = TransportFut<'static>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: RequestPacket) -> Self::Future {
+            let res = match &req {
+                RequestPacket::Single(req) => ResponsePacket::Single(self.respond(req)),
+                RequestPacket::Batch(reqs) => {
+                    ResponsePacket::Batch(reqs.iter().map(|req| self.respond(req)).collect())
+                }
+            };
+            let delay = self.delay;
+
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                Ok(res)
+            })
+        }
+    }
+
+    fn mock_provider(transport: MockTransport) -> RootProvider<MockTransport, AnyNetwork> {
+        RootProvider::new(RpcClient::new(transport, true))
+    }
+
+    /// Spawns a backend answered by `transport`, pinned to `block`.
+    async fn spawn_mock_backend(
+        transport: &MockTransport,
+        file_db_factory: Option<DBFactory>,
+        block: BlockId,
+    ) -> SharedBackend {
+        let meta = BlockchainDbMeta {
+            cfg_env: Default::default(),
+            block_env: Default::default(),
+            hosts: Default::default(),
+        };
+        let db = BlockchainDb::new(meta, None);
+
+        SharedBackend::spawn_backend(
+            mock_provider(transport.clone()),
+            file_db_factory,
+            db,
+            Some(block),
+        )
+        .await
+    }
+
+    /// Local state with the given slots and accounts, failing every read if
+    /// `fail` is set.
+    #[derive(Default)]
+    struct MockLocalState {
+        slots: StdHashMap<(Address, U256), U256>,
+        accounts: Vec<Address>,
+        fail: bool,
+    }
+
+    impl LocalState for MockLocalState {
+        fn read_slot(&self, address: Address, idx: U256) -> eyre::Result<Option<U256>> {
+            if self.fail {
+                eyre::bail!("local read failed");
+            }
+            Ok(self.slots.get(&(address, idx)).copied())
+        }
+
+        fn has_account(&self, address: Address) -> eyre::Result<bool> {
+            if self.fail {
+                eyre::bail!("local read failed");
+            }
+            Ok(self.accounts.contains(&address))
+        }
+    }
+
+    async fn read_storage(local: &MockLocalState, transport: &MockTransport) -> eyre::Result<U256> {
+        let remote = provider_storage::<MockTransport, _>(
+            mock_provider(transport.clone()),
+            BlockId::number(1),
+            RetryConfig::disabled(),
+            None,
+            ADDRESS,
+            SLOT,
+        );
+        local_storage(local, ADDRESS, SLOT, remote).await
+    }
+
+    const ADDRESS: Address = Address::repeat_byte(0x11);
+    const SLOT: U256 = U256::from_limbs([7, 0, 0, 0]);
+
+    fn remote_slot() -> MockTransport {
+        let transport = MockTransport::default();
+        transport.push("eth_getStorageAt", Ok(json!("0x2a")));
+        transport
+    }
+
+    #[tokio::test]
+    async fn local_slot_is_served_without_the_provider() {
+        let transport = remote_slot();
+        let local = MockLocalState {
+            slots: StdHashMap::from([((ADDRESS, SLOT), U256::from(5))]),
+            accounts: vec![ADDRESS],
+            ..Default::default()
+        };
+
+        assert_eq!(read_storage(&local, &transport).await.unwrap(), U256::from(5));
+        assert_eq!(transport.calls("eth_getStorageAt"), 0);
+    }
+
+    #[tokio::test]
+    async fn unwritten_slot_of_a_local_account_is_zero() {
+        let transport = remote_slot();
+        let local = MockLocalState { accounts: vec![ADDRESS], ..Default::default() };
+
+        assert_eq!(read_storage(&local, &transport).await.unwrap(), U256::ZERO);
+        assert_eq!(transport.calls("eth_getStorageAt"), 0);
+    }
+
+    /// Used to be answered as zero from the local db.
+    #[tokio::test]
+    async fn slot_of_an_account_missing_locally_is_fetched() {
+        let transport = remote_slot();
+        let local = MockLocalState::default();
+
+        assert_eq!(read_storage(&local, &transport).await.unwrap(), U256::from(42));
+        assert_eq!(transport.calls("eth_getStorageAt"), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_local_read_is_an_error() {
+        let transport = remote_slot();
+        let local = MockLocalState { fail: true, ..Default::default() };
+
+        assert!(read_storage(&local, &transport).await.is_err());
+        assert_eq!(transport.calls("eth_getStorageAt"), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dump_account_storage_needs_a_local_db() {
+        let backend = spawn_mock_backend(&MockTransport::default(), None, BlockId::number(1)).await;
+
+        let err = backend.dump_account_storage(ADDRESS).unwrap_err();
+        assert!(matches!(err, DatabaseError::DumpStorage(address, _) if address == ADDRESS));
+    }
+
+    /// Reads WETH from the local reth db at `RETH_DB_PATH` and
+    /// `RETH_STATIC_PATH`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RETH_DB_PATH and RETH_STATIC_PATH"]
+    async fn dump_account_storage_reads_the_local_db() {
+        let db_path = std::env::var("RETH_DB_PATH").expect("missing env. key=RETH_DB_PATH");
+        let static_path =
+            std::env::var("RETH_STATIC_PATH").expect("missing env. key=RETH_STATIC_PATH");
+        let file_db_factory = crate::types::get_db_factory(&db_path, &static_path);
+
+        let transport = MockTransport::default();
+        let backend =
+            spawn_mock_backend(&transport, Some(file_db_factory), BlockId::latest()).await;
+
+        let weth = alloy_primitives::address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let storage = backend.dump_account_storage(weth).unwrap();
+
+        // WETH9 stores `decimals` in slot 2.
+        assert_eq!(storage.get(&U256::from(2)), Some(&U256::from(18)));
+        // Holders' balances are in there too.
+        assert!(storage.len() > 3);
+        assert_eq!(transport.calls("eth_getStorageAt"), 0);
+    }
+
+    /// The plain state is at the tip, so a dump pinned to block 1 must fail
+    /// rather than return the tip's storage.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RETH_DB_PATH and RETH_STATIC_PATH"]
+    async fn dump_account_storage_rejects_a_block_below_the_tip() {
+        let db_path = std::env::var("RETH_DB_PATH").expect("missing env. key=RETH_DB_PATH");
+        let static_path =
+            std::env::var("RETH_STATIC_PATH").expect("missing env. key=RETH_STATIC_PATH");
+        let file_db_factory = crate::types::get_db_factory(&db_path, &static_path);
+
+        let backend = spawn_mock_backend(
+            &MockTransport::default(),
+            Some(file_db_factory),
+            BlockId::number(1),
+        )
+        .await;
+
+        let weth = alloy_primitives::address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+        let err = backend.dump_account_storage(weth).unwrap_err();
+        assert!(matches!(err, DatabaseError::DumpStorage(address, _) if address == weth));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn storage_at_a_tag_is_fetched_at_the_tag() {
+        let transport = remote_slot();
+        let backend = spawn_mock_backend(&transport, None, BlockId::latest()).await;
+
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+
+        let params = transport.params("eth_getStorageAt");
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0][2], json!("latest"));
+    }
+
+    /// A tag used to panic the handler when a local db was set.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RETH_DB_PATH and RETH_STATIC_PATH"]
+    async fn tag_with_a_local_db_uses_the_provider() {
+        let db_path = std::env::var("RETH_DB_PATH").expect("missing env. key=RETH_DB_PATH");
+        let static_path =
+            std::env::var("RETH_STATIC_PATH").expect("missing env. key=RETH_STATIC_PATH");
+        let file_db_factory = crate::types::get_db_factory(&db_path, &static_path);
+
+        let transport = remote_slot();
+        let backend =
+            spawn_mock_backend(&transport, Some(file_db_factory), BlockId::latest()).await;
+
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+        assert!(backend.is_alive());
+        assert_eq!(transport.calls("eth_getStorageAt"), 1);
+    }
+
+    async fn fetch_slot(
+        transport: &MockTransport,
+        retry: RetryConfig,
+        timeout: Option<Duration>,
+    ) -> eyre::Result<U256> {
+        provider_storage::<MockTransport, _>(
+            mock_provider(transport.clone()),
+            BlockId::number(1),
+            retry,
+            timeout,
+            ADDRESS,
+            SLOT,
+        )
+        .await
+    }
+
+    fn rpc_error(code: i64, message: &'static str) -> ErrorPayload {
+        ErrorPayload { code, message: message.into(), data: None }
+    }
+
+    const RETRY: RetryConfig =
+        RetryConfig { max_attempts: 3, base_delay: Duration::ZERO, jitter: Duration::ZERO };
+
+    #[tokio::test]
+    async fn rate_limited_request_is_retried() {
+        let transport = MockTransport::default();
+        transport.push("eth_getStorageAt", Err(rpc_error(429, "too many requests")));
+        transport.push("eth_getStorageAt", Ok(json!("0x2a")));
+
+        assert_eq!(fetch_slot(&transport, RETRY, None).await.unwrap(), U256::from(42));
+        assert_eq!(transport.calls("eth_getStorageAt"), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_max_attempts() {
+        let transport = MockTransport::default();
+        transport.push("eth_getStorageAt", Err(rpc_error(429, "too many requests")));
+
+        assert!(fetch_slot(&transport, RETRY, None).await.is_err());
+        assert_eq!(transport.calls("eth_getStorageAt"), 3);
+    }
+
+    #[tokio::test]
+    async fn requests_are_not_retried_by_default() {
+        let transport = MockTransport::default();
+        transport.push("eth_getStorageAt", Err(rpc_error(429, "too many requests")));
+        transport.push("eth_getStorageAt", Ok(json!("0x2a")));
+
+        assert!(fetch_slot(&transport, RetryConfig::default(), None)
+            .await
+            .is_err());
+        assert_eq!(transport.calls("eth_getStorageAt"), 1);
+    }
+
+    #[tokio::test]
+    async fn hard_failures_are_not_retried() {
+        for err in [rpc_error(-32602, "invalid params"), rpc_error(429, "missing trie node")] {
+            let transport = MockTransport::default();
+            transport.push("eth_getStorageAt", Err(err));
+
+            assert!(fetch_slot(&transport, RETRY, None).await.is_err());
+            assert_eq!(transport.calls("eth_getStorageAt"), 1);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn backend_retries_with_its_retry_config() {
+        let transport = MockTransport::default();
+        transport.push("eth_getStorageAt", Err(rpc_error(429, "too many requests")));
+        transport.push("eth_getStorageAt", Ok(json!("0x2a")));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+        backend.set_retry_config(RETRY).unwrap();
+
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+        assert_eq!(transport.calls("eth_getStorageAt"), 2);
+    }
+
+    #[tokio::test]
+    async fn hung_request_times_out() {
+        let transport = remote_slot().with_delay(Duration::from_secs(60));
+        let start = Instant::now();
+
+        let err = fetch_slot(&transport, RETRY, Some(Duration::from_millis(20)))
+            .await
+            .unwrap_err();
+
+        assert!(err.chain().any(|e| e.is::<RequestTimedOut>()));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // Timeouts are not retried.
+        assert_eq!(transport.calls("eth_getStorageAt"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hung_provider_fails_the_read() {
+        let transport = remote_slot().with_delay(Duration::from_secs(60));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+        backend
+            .set_request_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+
+        assert!(backend.storage_ref(ADDRESS, SLOT).is_err());
+        assert!(backend.is_alive());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn timed_out_request_is_a_timeout_error() {
+        let transport = remote_slot().with_delay(Duration::from_secs(60));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+        backend
+            .set_request_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+
+        let err = backend.storage_ref(ADDRESS, SLOT).unwrap_err();
+        assert!(matches!(err, DatabaseError::Timeout(RequestKind::Storage, _)));
+        assert!(err.is_timeout());
+        assert!(!err.is_possibly_non_archive_node_error());
+    }
+
+    #[test]
+    fn failed_request_is_not_a_timeout() {
+        let err = DatabaseError::from_request(
+            RequestKind::Storage,
+            Arc::new(eyre::eyre!("missing trie node")),
+            |err| DatabaseError::GetStorage(ADDRESS, SLOT, err),
+        );
+
+        assert!(!err.is_timeout());
+        assert!(err.is_possibly_non_archive_node_error());
+    }
+
+    /// Transport answering 42 for the first slot read and 43 for later ones.
+    fn changing_slot() -> MockTransport {
+        let transport = MockTransport::default();
+        transport.push("eth_getStorageAt", Ok(json!("0x2a")));
+        transport.push("eth_getStorageAt", Ok(json!("0x2b")));
+        transport
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn repin_serves_the_cached_slot_by_default() {
+        let transport = changing_slot();
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+        backend.set_pinned_block(2).unwrap();
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+        assert_eq!(transport.calls("eth_getStorageAt"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn repin_with_pruning_fetches_the_slot_at_the_new_block() {
+        let transport = changing_slot();
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+        backend.set_prune_on_repin(true).unwrap();
+
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+        // Pinning the same block again keeps the state.
+        backend.set_pinned_block(1).unwrap();
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+
+        backend.set_pinned_block(2).unwrap();
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(43));
+
+        let params = transport.params("eth_getStorageAt");
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0][2], json!("0x1"));
+        assert_eq!(params[1][2], json!("0x2"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cleared_cache_is_fetched_again() {
+        let transport = changing_slot();
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(42));
+        backend.clear_cache().unwrap();
+        assert_eq!(backend.storage_ref(ADDRESS, SLOT).unwrap(), U256::from(43));
+        assert_eq!(transport.calls("eth_getStorageAt"), 2);
+    }
+
+    #[test]
+    fn response_is_received_before_the_timeout() {
+        let (tx, rx) = oneshot_channel();
+        tx.send(7).unwrap();
+
+        let response = recv_response(&rx, RequestKind::Storage, Some(Duration::from_millis(20)));
+        assert_eq!(response.unwrap(), 7);
+    }
+
+    #[test]
+    fn missing_response_times_out() {
+        let (_tx, rx) = oneshot_channel::<()>();
+        let timeout = Duration::from_millis(20);
+
+        let err = recv_response(&rx, RequestKind::Account, Some(timeout)).unwrap_err();
+        assert!(matches!(err, DatabaseError::Timeout(RequestKind::Account, t) if t == timeout));
+    }
+
+    #[test]
+    fn dropped_request_means_the_handler_closed() {
+        let (tx, rx) = oneshot_channel::<()>();
+        drop(tx);
+
+        let err = recv_response(&rx, RequestKind::Storage, None).unwrap_err();
+        assert!(matches!(err, DatabaseError::HandlerClosed));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn timeout_blocking_mode_bounds_the_wait() {
+        // No request timeout, the handler would wait for the provider.
+        let transport = remote_slot().with_delay(Duration::from_secs(60));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1))
+            .await
+            .with_blocking_mode(BlockingMode::Timeout(Duration::from_millis(20)));
+        let start = Instant::now();
+
+        let err = backend.storage_ref(ADDRESS, SLOT).unwrap_err();
+        assert!(matches!(err, DatabaseError::Timeout(RequestKind::Storage, _)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    const TX: B256 = B256::repeat_byte(0xab);
+
+    fn receipt_json() -> Value {
+        json!({
+            "transactionHash": TX,
+            "transactionIndex": "0x0",
+            "blockHash": B256::repeat_byte(0xcd),
+            "blockNumber": "0x1",
+            "from": Address::repeat_byte(0x11),
+            "to": Address::repeat_byte(0x22),
+            "contractAddress": null,
+            "gasUsed": "0x5208",
+            "cumulativeGasUsed": "0x5208",
+            "effectiveGasPrice": "0x1",
+            "status": "0x1",
+            "type": "0x2",
+            "logs": [],
+            "logsBloom": Bloom::ZERO,
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_receipt_requests_share_one_fetch() {
+        let transport = MockTransport::default().with_delay(Duration::from_millis(100));
+        transport.push("eth_getTransactionReceipt", Ok(receipt_json()));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+
+        let requests: Vec<_> = (0..3)
+            .map(|_| {
+                let backend = backend.with_blocking_mode(BlockingMode::Block);
+                tokio::task::spawn_blocking(move || backend.get_transaction_receipt(TX))
+            })
+            .collect();
+        for request in requests {
+            let receipt = request.await.unwrap().unwrap();
+            assert_eq!(receipt.transaction_hash, TX);
+            assert_eq!(receipt.gas_used, 21_000);
+        }
+
+        assert_eq!(transport.calls("eth_getTransactionReceipt"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unknown_receipt_is_not_found() {
+        let transport = MockTransport::default();
+        transport.push("eth_getTransactionReceipt", Ok(Value::Null));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+
+        let err = backend.get_transaction_receipt(TX).unwrap_err();
+        assert!(matches!(err, DatabaseError::ReceiptNotFound(tx) if tx == TX));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prefetch_fetches_each_slot_once() {
+        let transport = remote_slot().with_delay(Duration::from_millis(50));
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+        let other_slot = SLOT + U256::from(1);
+
+        backend
+            .prefetch_storage(&[(ADDRESS, SLOT), (ADDRESS, SLOT), (ADDRESS, other_slot)])
+            .unwrap();
+        assert_eq!(transport.calls("eth_getStorageAt"), 2);
+
+        // Served from the cache.
+        assert_eq!(backend.storage_ref(ADDRESS, other_slot).unwrap(), U256::from(42));
+        assert_eq!(transport.calls("eth_getStorageAt"), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn capped_cache_evicts_the_oldest_fetched_slot() {
+        let transport = remote_slot();
+        let backend = spawn_mock_backend(&transport, None, BlockId::number(1)).await;
+        backend.set_max_cache_entries(Some(2)).unwrap();
+
+        let slots = [SLOT, SLOT + U256::from(1), SLOT + U256::from(2)];
+        for slot in slots {
+            backend.storage_ref(ADDRESS, slot).unwrap();
+        }
+
+        // Served from the cache, and answered after the eviction ran.
+        backend.storage_ref(ADDRESS, slots[2]).unwrap();
+        assert_eq!(transport.calls("eth_getStorageAt"), 3);
+        assert_eq!(backend.cache_footprint().state_entries(), 2);
+
+        backend.storage_ref(ADDRESS, slots[0]).unwrap();
+        assert_eq!(transport.calls("eth_getStorageAt"), 4);
+    }
+}
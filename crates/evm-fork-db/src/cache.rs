@@ -7,7 +7,7 @@ use std::sync::Arc;
 
 use alloy_consensus::BlockHeader;
 use alloy_primitives::{Address, B256, U256};
-use alloy_provider::network::TransactionResponse;
+use alloy_provider::network::{AnyRpcHeader, TransactionResponse};
 use parking_lot::RwLock;
 use revm::primitives::map::{AddressHashMap, HashMap};
 use revm::primitives::{
@@ -19,6 +19,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use url::Url;
 
 pub type StorageInfo = HashMap<U256, U256>;
+pub type StorageData = AddressHashMap<StorageInfo>;
 
 /// A shareable Block database
 #[derive(Clone, Debug)]
@@ -101,11 +102,34 @@ impl BlockchainDb {
         &self.db.storage
     }
 
+    /// Returns the storage slots that are new or hold a different value than in
+    /// `baseline`, e.g. a previous [SharedBackend::storage] clone, so only
+    /// the delta has to be persisted. Slots dropped since are not reported.
+    ///
+    /// [SharedBackend::storage]: crate::backend::SharedBackend::storage
+    pub fn storage_since(&self, baseline: &StorageData) -> StorageData {
+        diff_storage(&self.db.storage.read(), baseline)
+    }
+
+    /// Applies a diff returned by [Self::storage_since], overwriting the
+    /// slots it holds and keeping all others
+    pub fn merge_storage(&self, diff: StorageData) {
+        let mut storage = self.db.storage.write();
+        for (address, slots) in diff {
+            storage.entry(address).or_default().extend(slots);
+        }
+    }
+
     /// Returns the map that holds all the block hashes
     pub fn block_hashes(&self) -> &RwLock<HashMap<U256, B256>> {
         &self.db.block_hashes
     }
 
+    /// Returns the map that holds the fetched block headers
+    pub fn block_headers(&self) -> &RwLock<HashMap<u64, AnyRpcHeader>> {
+        &self.db.block_headers
+    }
+
     /// Returns the Env related metadata
     pub const fn meta(&self) -> &Arc<RwLock<BlockchainDbMeta>> {
         &self.meta
@@ -122,6 +146,23 @@ impl BlockchainDb {
     }
 }
 
+/// Slots of `storage` that are missing from `baseline` or hold another value
+pub(crate) fn diff_storage(storage: &StorageData, baseline: &StorageData) -> StorageData {
+    storage
+        .iter()
+        .filter_map(|(address, slots)| {
+            let base = baseline.get(address);
+            let changed: StorageInfo = slots
+                .iter()
+                .filter(|(slot, value)| base.and_then(|base| base.get(*slot)) != Some(*value))
+                .map(|(slot, value)| (*slot, *value))
+                .collect();
+
+            (!changed.is_empty()).then_some((*address, changed))
+        })
+        .collect()
+}
+
 /// relevant identifying markers in the context of [BlockchainDb]
 #[derive(Clone, Debug, Eq, Serialize, Default)]
 pub struct BlockchainDbMeta {
@@ -309,6 +350,33 @@ impl<'de> Deserialize<'de> for BlockchainDbMeta {
     }
 }
 
+/// Number of entries held by a [MemDb] and a rough estimate of their size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemDbFootprint {
+    pub accounts: usize,
+    pub storage_slots: usize,
+    pub block_hashes: usize,
+    pub block_headers: usize,
+    /// Total size of the cached contract code
+    pub code_bytes: usize,
+}
+
+impl MemDbFootprint {
+    /// Accounts and storage slots, the block sensitive entries
+    pub const fn state_entries(&self) -> usize {
+        self.accounts + self.storage_slots
+    }
+
+    /// Lower bound of the heap used by the entries, map overhead is ignored
+    pub const fn approx_bytes(&self) -> usize {
+        self.accounts * std::mem::size_of::<(Address, AccountInfo)>()
+            + self.code_bytes
+            + self.storage_slots * std::mem::size_of::<(U256, U256)>()
+            + self.block_hashes * std::mem::size_of::<(U256, B256)>()
+            + self.block_headers * std::mem::size_of::<(u64, AnyRpcHeader)>()
+    }
+}
+
 /// In Memory cache containing all fetched accounts and storage slots
 /// and their values from RPC
 #[derive(Debug, Default)]
@@ -319,6 +387,8 @@ pub struct MemDb {
     pub storage: RwLock<AddressHashMap<StorageInfo>>,
     /// All retrieved block hashes
     pub block_hashes: RwLock<HashMap<U256, B256>>,
+    /// Retrieved block headers by number, not written to the JSON cache
+    pub block_headers: RwLock<HashMap<u64, AnyRpcHeader>>,
 }
 
 impl MemDb {
@@ -327,6 +397,41 @@ impl MemDb {
         self.accounts.write().clear();
         self.storage.write().clear();
         self.block_hashes.write().clear();
+        self.block_headers.write().clear();
+    }
+
+    /// Number of cached accounts and storage slots
+    pub fn state_entries(&self) -> usize {
+        let slots: usize = self.storage.read().values().map(|slots| slots.len()).sum();
+        self.accounts.read().len() + slots
+    }
+
+    /// Counts the cached entries, see [MemDbFootprint]
+    pub fn footprint(&self) -> MemDbFootprint {
+        let (accounts, code_bytes) = {
+            let accounts = self.accounts.read();
+            let code_bytes = accounts
+                .values()
+                .filter_map(|account| account.code.as_ref())
+                .map(|code| code.len())
+                .sum();
+            (accounts.len(), code_bytes)
+        };
+
+        MemDbFootprint {
+            accounts,
+            storage_slots: self.storage.read().values().map(|slots| slots.len()).sum(),
+            block_hashes: self.block_hashes.read().len(),
+            block_headers: self.block_headers.read().len(),
+            code_bytes,
+        }
+    }
+
+    /// Clears accounts and storage but keeps the block hashes and headers,
+    /// which don't depend on the pinned block
+    pub fn clear_state(&self) {
+        self.accounts.write().clear();
+        self.storage.write().clear();
     }
 
     // Inserts the account, replacing it if it exists already
@@ -334,6 +439,21 @@ impl MemDb {
         self.accounts.write().insert(address, account);
     }
 
+    /// Inserts the account and merges `storage` into its cached slots while
+    /// holding both locks, so readers never see one without the other
+    pub fn do_seed_account(&self, address: Address, mut info: AccountInfo, storage: StorageInfo) {
+        if let Some(code) = info.code.as_ref().filter(|code| !code.is_empty()) {
+            info.code_hash = code.hash_slow();
+        } else if info.code_hash.is_zero() {
+            info.code_hash = KECCAK_EMPTY;
+        }
+
+        let mut storage_lock = self.storage.write();
+        let mut accounts_lock = self.accounts.write();
+        storage_lock.entry(address).or_default().extend(storage);
+        accounts_lock.insert(address, info);
+    }
+
     /// The implementation of [DatabaseCommit::commit()]
     pub fn do_commit(&self, changes: HashMap<Address, Account>) {
         let mut storage = self.storage.write();
@@ -382,6 +502,7 @@ impl Clone for MemDb {
             storage: RwLock::new(self.storage.read().clone()),
             accounts: RwLock::new(self.accounts.read().clone()),
             block_hashes: RwLock::new(self.block_hashes.read().clone()),
+            block_headers: RwLock::new(self.block_headers.read().clone()),
         }
     }
 }
@@ -525,6 +646,7 @@ impl<'de> Deserialize<'de> for JsonBlockCacheData {
                 accounts: RwLock::new(accounts),
                 storage: RwLock::new(storage),
                 block_hashes: RwLock::new(block_hashes),
+                block_headers: Default::default(),
             }),
         })
     }
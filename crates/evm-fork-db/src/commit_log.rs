@@ -0,0 +1,191 @@
+//! Records the writes committed to a fork, so a diverging simulation can be
+//! replayed step by step. Unlike the state witness, which holds what a
+//! simulation reads, this only holds mutations.
+
+use alloy_primitives::map::HashMap;
+use alloy_primitives::{Address, U256};
+use revm::primitives::{Account, AccountInfo};
+use serde::{Deserialize, Serialize};
+
+/// What a committed transaction changed on one account.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateChange {
+    Storage { slot: U256, old: U256, new: U256 },
+    Balance { old: U256, new: U256 },
+    Nonce { old: u64, new: u64 },
+}
+
+/// One write, `commit` is the index of the commit that made it since the log
+/// was enabled or cleared.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateWrite {
+    pub commit: usize,
+    pub address: Address,
+    pub change: StateChange,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CommitLog {
+    commits: usize,
+    writes: Vec<StateWrite>,
+}
+
+impl CommitLog {
+    /// Records the changes of one commit. `previous` returns the account as it
+    /// was before the commit, `None` if it didn't exist.
+    pub fn record(
+        &mut self,
+        changes: &HashMap<Address, Account>,
+        mut previous: impl FnMut(Address) -> Option<AccountInfo>,
+    ) {
+        let commit = self.commits;
+        self.commits += 1;
+
+        for (address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+            let address = *address;
+            let old = previous(address).unwrap_or_default();
+
+            if old.balance != account.info.balance {
+                self.writes.push(StateWrite {
+                    commit,
+                    address,
+                    change: StateChange::Balance { old: old.balance, new: account.info.balance },
+                });
+            }
+            if old.nonce != account.info.nonce {
+                self.writes.push(StateWrite {
+                    commit,
+                    address,
+                    change: StateChange::Nonce { old: old.nonce, new: account.info.nonce },
+                });
+            }
+
+            for (slot, value) in account.changed_storage_slots() {
+                self.writes.push(StateWrite {
+                    commit,
+                    address,
+                    change: StateChange::Storage {
+                        slot: *slot,
+                        old: value.original_value(),
+                        new: value.present_value(),
+                    },
+                });
+            }
+        }
+    }
+
+    /// Writes in the order they were committed.
+    pub fn writes(&self) -> &[StateWrite] {
+        &self.writes
+    }
+
+    /// Number of writes recorded.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Drops the writes after the first `len`, e.g. those of reverted commits.
+    /// The next commit is numbered right after the last write kept.
+    pub fn truncate(&mut self, len: usize) {
+        self.writes.truncate(len);
+        self.commits = self.writes.last().map_or(0, |write| write.commit + 1);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::EvmStorageSlot;
+
+    use super::*;
+
+    const ALICE: Address = Address::repeat_byte(0x11);
+    const BOB: Address = Address::repeat_byte(0x22);
+
+    fn touched(balance: u64, nonce: u64) -> Account {
+        let mut account = Account::from(AccountInfo {
+            balance: U256::from(balance),
+            nonce,
+            ..Default::default()
+        });
+        account.mark_touch();
+        account
+    }
+
+    fn balance_of(balance: u64) -> Option<AccountInfo> {
+        Some(AccountInfo { balance: U256::from(balance), ..Default::default() })
+    }
+
+    #[test]
+    fn records_the_writes_of_touched_accounts() {
+        let mut alice = touched(5, 1);
+        alice
+            .storage
+            .insert(U256::from(1), EvmStorageSlot::new_changed(U256::ZERO, U256::from(7)));
+        let untouched = Account::from(AccountInfo { balance: U256::from(9), ..Default::default() });
+        let changes = HashMap::from_iter([(ALICE, alice), (BOB, untouched)]);
+
+        let mut log = CommitLog::default();
+        log.record(&changes, |_| None);
+
+        assert_eq!(
+            log.writes(),
+            [
+                StateWrite {
+                    commit: 0,
+                    address: ALICE,
+                    change: StateChange::Balance { old: U256::ZERO, new: U256::from(5) },
+                },
+                StateWrite {
+                    commit: 0,
+                    address: ALICE,
+                    change: StateChange::Nonce { old: 0, new: 1 }
+                },
+                StateWrite {
+                    commit: 0,
+                    address: ALICE,
+                    change: StateChange::Storage {
+                        slot: U256::from(1),
+                        old: U256::ZERO,
+                        new: U256::from(7),
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn truncate_drops_later_writes() {
+        let mut log = CommitLog::default();
+        log.record(&HashMap::from_iter([(ALICE, touched(1, 0))]), |_| balance_of(0));
+        let len = log.len();
+        log.record(&HashMap::from_iter([(ALICE, touched(2, 0))]), |_| balance_of(1));
+        assert_eq!(log.len(), 2);
+
+        log.truncate(len);
+        log.record(&HashMap::from_iter([(ALICE, touched(3, 0))]), |_| balance_of(1));
+
+        let changes: Vec<_> = log
+            .writes()
+            .iter()
+            .map(|write| (write.commit, write.change.clone()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (0, StateChange::Balance { old: U256::ZERO, new: U256::from(1) }),
+                (1, StateChange::Balance { old: U256::from(1), new: U256::from(3) }),
+            ]
+        );
+    }
+}
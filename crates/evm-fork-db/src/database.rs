@@ -15,6 +15,7 @@ use tracing::{trace, warn};
 
 use crate::backend::SharedBackend;
 use crate::cache::BlockchainDb;
+use crate::commit_log::{CommitLog, StateWrite};
 use crate::error::DatabaseError;
 
 /// a [revm::Database] that's forked off another client
@@ -43,6 +44,8 @@ pub struct ForkedDatabase {
     db: BlockchainDb,
     /// Holds the state snapshots of a blockchain.
     state_snapshots: Arc<Mutex<StateSnapshots<ForkDbStateSnapshot>>>,
+    /// Writes of every commit, only recorded when enabled
+    commit_log: Arc<Mutex<Option<CommitLog>>>,
 }
 
 impl ForkedDatabase {
@@ -53,6 +56,7 @@ impl ForkedDatabase {
             backend,
             db,
             state_snapshots: Arc::new(Mutex::new(Default::default())),
+            commit_log: Default::default(),
         }
     }
 
@@ -68,6 +72,39 @@ impl ForkedDatabase {
         &self.state_snapshots
     }
 
+    /// Starts or stops recording the writes of every commit, stopping drops the
+    /// recorded ones
+    pub fn record_commits(&self, enabled: bool) {
+        *self.commit_log.lock() = enabled.then(CommitLog::default);
+    }
+
+    /// Returns the recorded writes in commit order, empty if recording is off
+    pub fn commit_log(&self) -> Vec<StateWrite> {
+        self.commit_log
+            .lock()
+            .as_ref()
+            .map(|log| log.writes().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Drops the recorded writes, recording goes on if enabled
+    pub fn clear_commit_log(&self) {
+        if let Some(log) = self.commit_log.lock().as_mut() {
+            log.clear();
+        }
+    }
+
+    /// A clone for throwaway execution, with its own commit log that is off.
+    /// Plain clones share the log, so their commits would be recorded here
+    pub fn detached(&self) -> Self {
+        Self { commit_log: Default::default(), ..self.clone() }
+    }
+
+    /// Number of recorded writes, zero if recording is off
+    fn commit_log_len(&self) -> usize {
+        self.commit_log.lock().as_ref().map_or(0, CommitLog::len)
+    }
+
     /// Reset the fork to a fresh forked state, and optionally update the fork
     /// config
     pub fn reset(
@@ -85,10 +122,34 @@ impl ForkedDatabase {
         self.inner().db().clear();
         // create a fresh `CacheDB`, effectively wiping modified state
         self.cache_db = CacheDB::new(self.backend.clone());
+        self.clear_commit_log();
         trace!(target: "backend::forkdb", "Cleared database");
         Ok(())
     }
 
+    /// Pins the fork to another block, wiping the fetched and modified state
+    /// but keeping the already fetched block hashes
+    pub fn repin(&mut self, block_number: impl Into<BlockId>) -> Result<(), String> {
+        self.backend
+            .set_pinned_block(block_number)
+            .map_err(|err| err.to_string())?;
+
+        self.inner().db().clear_state();
+        self.cache_db = CacheDB::new(self.backend.clone());
+        self.clear_commit_log();
+        trace!(target: "backend::forkdb", "Repinned database");
+        Ok(())
+    }
+
+    /// Wipes the modified state, the snapshots and the commit log, keeping the
+    /// state fetched from the remote client
+    pub fn clear_local(&mut self) {
+        self.cache_db = CacheDB::new(self.backend.clone());
+        *self.state_snapshots.lock() = Default::default();
+        self.clear_commit_log();
+        trace!(target: "backend::forkdb", "Cleared local state");
+    }
+
     /// Flushes the cache to disk if configured
     pub fn flush_cache(&self) {
         self.db.cache().flush()
@@ -106,7 +167,11 @@ impl ForkedDatabase {
             storage: db.storage.read().clone(),
             block_hashes: db.block_hashes.read().clone(),
         };
-        ForkDbStateSnapshot { local: self.cache_db.clone(), state_snapshot }
+        ForkDbStateSnapshot {
+            local: self.cache_db.clone(),
+            state_snapshot,
+            commit_log_len: self.commit_log_len(),
+        }
     }
 
     pub fn insert_state_snapshot(&self) -> U256 {
@@ -130,6 +195,7 @@ impl ForkedDatabase {
             let ForkDbStateSnapshot {
                 local,
                 state_snapshot: StateSnapshot { accounts, storage, block_hashes },
+                commit_log_len,
             } = state_snapshot;
             let db = self.inner().db();
             {
@@ -150,6 +216,11 @@ impl ForkedDatabase {
 
             self.cache_db = local;
 
+            // The reverted commits are undone, so are their writes.
+            if let Some(log) = self.commit_log.lock().as_mut() {
+                log.truncate(commit_log_len);
+            }
+
             trace!(target: "backend::forkdb", "Reverted snapshot {}", id);
             true
         } else {
@@ -204,6 +275,10 @@ impl DatabaseRef for ForkedDatabase {
 
 impl DatabaseCommit for ForkedDatabase {
     fn commit(&mut self, changes: HashMap<Address, Account>) {
+        if let Some(log) = self.commit_log.lock().as_mut() {
+            let cache_db = &self.cache_db;
+            log.record(&changes, |address| cache_db.basic_ref(address).ok().flatten());
+        }
         self.database_mut().commit(changes)
     }
 }
@@ -215,6 +290,8 @@ impl DatabaseCommit for ForkedDatabase {
 pub struct ForkDbStateSnapshot {
     pub local: CacheDB<SharedBackend>,
     pub state_snapshot: StateSnapshot,
+    /// Length of the commit log when the snapshot was taken
+    pub commit_log_len: usize,
 }
 
 impl ForkDbStateSnapshot {
@@ -279,3 +356,91 @@ impl DatabaseRef for ForkDbStateSnapshot {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloy_provider::network::AnyNetwork;
+    use alloy_provider::RootProvider;
+
+    use super::*;
+    use crate::cache::BlockchainDbMeta;
+    use crate::commit_log::StateChange;
+
+    const ALICE: Address = Address::repeat_byte(0x11);
+
+    /// Fork whose provider is never reached, the only account is local.
+    async fn local_fork() -> ForkedDatabase {
+        let provider =
+            RootProvider::<_, AnyNetwork>::new_http("http://127.0.0.1:1".parse().unwrap());
+        let meta = BlockchainDbMeta {
+            cfg_env: Default::default(),
+            block_env: Default::default(),
+            hosts: Default::default(),
+        };
+        let db = BlockchainDb::new(meta, None);
+        let backend =
+            SharedBackend::spawn_backend(provider, None, db.clone(), Some(BlockId::number(1)))
+                .await;
+
+        let mut fork = ForkedDatabase::new(backend, db);
+        fork.database_mut()
+            .insert_account_info(ALICE, AccountInfo::default());
+        fork
+    }
+
+    fn set_balance(fork: &mut ForkedDatabase, balance: u64) {
+        let mut account =
+            Account::from(AccountInfo { balance: U256::from(balance), ..Default::default() });
+        account.mark_touch();
+        fork.commit(HashMap::from_iter([(ALICE, account)]));
+    }
+
+    fn balance_change(old: u64, new: u64) -> StateChange {
+        StateChange::Balance { old: U256::from(old), new: U256::from(new) }
+    }
+
+    fn changes(fork: &ForkedDatabase) -> Vec<StateChange> {
+        fork.commit_log()
+            .into_iter()
+            .map(|write| write.change)
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn revert_rolls_the_commit_log_back() {
+        let mut fork = local_fork().await;
+        fork.record_commits(true);
+        set_balance(&mut fork, 1);
+
+        // Taking a snapshot keeps the log.
+        let id = fork.insert_state_snapshot();
+        assert_eq!(changes(&fork), [balance_change(0, 1)]);
+
+        set_balance(&mut fork, 2);
+        assert_eq!(changes(&fork), [balance_change(0, 1), balance_change(1, 2)]);
+
+        assert!(fork.revert_state_snapshot(id, RevertStateSnapshotAction::RevertRemove));
+        assert_eq!(changes(&fork), [balance_change(0, 1)]);
+
+        set_balance(&mut fork, 3);
+        assert_eq!(changes(&fork), [balance_change(0, 1), balance_change(1, 3)]);
+        assert_eq!(fork.commit_log()[1].commit, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detached_commits_are_not_recorded() {
+        let mut fork = local_fork().await;
+        fork.record_commits(true);
+        set_balance(&mut fork, 1);
+
+        let mut detached = fork.detached();
+        set_balance(&mut detached, 2);
+        assert!(detached.commit_log().is_empty());
+        assert_eq!(changes(&fork), [balance_change(0, 1)]);
+
+        // A plain clone shares the log.
+        let mut clone = fork.clone();
+        set_balance(&mut clone, 3);
+        assert_eq!(changes(&fork), [balance_change(0, 1), balance_change(1, 3)]);
+    }
+}
@@ -1,6 +1,8 @@
 use std::convert::Infallible;
+use std::fmt;
 use std::sync::mpsc::RecvError;
 use std::sync::Arc;
+use std::time::Duration;
 
 use alloy_primitives::{Address, B256, U256};
 use alloy_rpc_types::BlockId;
@@ -27,14 +29,65 @@ pub enum DatabaseError {
     GetBlockHash(u64, Arc<eyre::Error>),
     #[error("failed to get full block for {0:?}: {1}")]
     GetFullBlock(BlockId, Arc<eyre::Error>),
+    #[error("failed to get block header for {0:?}: {1}")]
+    GetBlockHeader(BlockId, Arc<eyre::Error>),
     #[error("block {0:?} does not exist")]
     BlockNotFound(BlockId),
     #[error("failed to get transaction {0}: {1}")]
     GetTransaction(B256, Arc<eyre::Error>),
+    #[error("failed to get receipt {0}: {1}")]
+    GetReceipt(B256, Arc<eyre::Error>),
+    #[error("no receipt for transaction {0}, it is unknown or pending")]
+    ReceiptNotFound(B256),
     #[error("failed to process AnyRequest: {0}")]
     AnyRequest(Arc<eyre::Error>),
+    #[error("failed to dump storage for {0}: {1}")]
+    DumpStorage(Address, Arc<eyre::Error>),
+    #[error("{0} request timed out after {1:?}")]
+    Timeout(RequestKind, Duration),
+    #[error("backend handler is not running")]
+    HandlerClosed,
 }
 
+/// Kind of provider request, used to tell which request timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    Account,
+    Storage,
+    BlockHash,
+    FullBlock,
+    BlockHeader,
+    Transaction,
+    Receipt,
+    Code,
+    DumpStorage,
+    Any,
+}
+
+impl fmt::Display for RequestKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Account => "account",
+            Self::Storage => "storage",
+            Self::BlockHash => "block hash",
+            Self::FullBlock => "full block",
+            Self::BlockHeader => "block header",
+            Self::Transaction => "transaction",
+            Self::Receipt => "receipt",
+            Self::Code => "code",
+            Self::DumpStorage => "storage dump",
+            Self::Any => "any",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Error of a provider request aborted by the request timeout, carrying the
+/// time elapsed since the first attempt.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("request timed out after {0:?}")]
+pub(crate) struct RequestTimedOut(pub(crate) Duration);
+
 impl DatabaseError {
     fn get_rpc_error(&self) -> Option<&eyre::Error> {
         match self {
@@ -42,24 +95,59 @@ impl DatabaseError {
             Self::GetStorage(_, _, err) => Some(err),
             Self::GetBlockHash(_, err) => Some(err),
             Self::GetFullBlock(_, err) => Some(err),
+            Self::GetBlockHeader(_, err) => Some(err),
             Self::GetTransaction(_, err) => Some(err),
+            Self::GetReceipt(_, err) => Some(err),
             Self::AnyRequest(err) => Some(err),
             // Enumerate explicitly to make sure errors are updated if a new one is added.
-            Self::MissingCode(_) | Self::Recv(_) | Self::Send(_) | Self::BlockNotFound(_) => None,
+            Self::MissingCode(_)
+            | Self::Recv(_)
+            | Self::Send(_)
+            | Self::BlockNotFound(_)
+            | Self::ReceiptNotFound(_)
+            | Self::DumpStorage(..)
+            | Self::Timeout(..)
+            | Self::HandlerClosed => None,
         }
     }
 
+    /// Builds the error of a failed provider request: `Timeout` if it was
+    /// aborted by the request timeout, `other(err)` otherwise.
+    pub(crate) fn from_request(
+        kind: RequestKind,
+        err: Arc<eyre::Error>,
+        other: impl FnOnce(Arc<eyre::Error>) -> Self,
+    ) -> Self {
+        match err
+            .chain()
+            .find_map(|e| e.downcast_ref::<RequestTimedOut>())
+        {
+            Some(timed_out) => Self::Timeout(kind, timed_out.0),
+            None => other(err),
+        }
+    }
+
+    /// Whether the provider request timed out, as opposed to failing.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::Timeout(..))
+    }
+
     /// Whether the error is potentially caused by the user forking from an
     /// older block in a non-archive node.
     pub fn is_possibly_non_archive_node_error(&self) -> bool {
-        static GETH_MESSAGE: &str = "missing trie node";
-
         self.get_rpc_error()
-            .map(|err| err.to_string().to_lowercase().contains(GETH_MESSAGE))
+            .map(|err| is_non_archive_node_message(&err.to_string()))
             .unwrap_or(false)
     }
 }
 
+/// Whether an RPC error message points at pruned state on a non-archive node.
+pub(crate) fn is_non_archive_node_message(message: &str) -> bool {
+    static GETH_MESSAGE: &str = "missing trie node";
+
+    message.to_lowercase().contains(GETH_MESSAGE)
+}
+
 impl<T> From<TrySendError<T>> for DatabaseError {
     fn from(value: TrySendError<T>) -> Self {
         value.into_send_error().into()
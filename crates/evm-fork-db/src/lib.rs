@@ -3,6 +3,9 @@ extern crate tracing;
 
 pub mod backend;
 pub mod cache;
+pub mod commit_log;
 pub mod database;
 pub mod error;
+pub mod metrics;
 pub mod types;
+pub mod witness;
@@ -0,0 +1,101 @@
+//! Request counters shared between the `BackendHandler` and its
+//! `SharedBackend`s, to tell cache hits apart from provider round trips.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Live counters, updated by the `BackendHandler`.
+#[derive(Debug, Default)]
+pub(crate) struct BackendCounters {
+    pub(crate) account_hits: AtomicU64,
+    pub(crate) account_misses: AtomicU64,
+    pub(crate) storage_hits: AtomicU64,
+    pub(crate) storage_misses: AtomicU64,
+    pub(crate) block_hash_hits: AtomicU64,
+    pub(crate) block_hash_misses: AtomicU64,
+    pub(crate) full_block_fetches: AtomicU64,
+    pub(crate) transaction_fetches: AtomicU64,
+    pub(crate) receipt_fetches: AtomicU64,
+}
+
+impl BackendCounters {
+    pub(crate) fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> BackendMetrics {
+        BackendMetrics {
+            account_hits: self.account_hits.load(Ordering::Relaxed),
+            account_misses: self.account_misses.load(Ordering::Relaxed),
+            storage_hits: self.storage_hits.load(Ordering::Relaxed),
+            storage_misses: self.storage_misses.load(Ordering::Relaxed),
+            block_hash_hits: self.block_hash_hits.load(Ordering::Relaxed),
+            block_hash_misses: self.block_hash_misses.load(Ordering::Relaxed),
+            full_block_fetches: self.full_block_fetches.load(Ordering::Relaxed),
+            transaction_fetches: self.transaction_fetches.load(Ordering::Relaxed),
+            receipt_fetches: self.receipt_fetches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Snapshot of the backend request counters.
+///
+/// A miss is any request not answered from the in-memory cache, it is served
+/// by the local DB or the provider. Requests joining one already in flight
+/// count as misses too, so `misses` is an upper bound on round trips.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BackendMetrics {
+    pub account_hits: u64,
+    pub account_misses: u64,
+    pub storage_hits: u64,
+    pub storage_misses: u64,
+    pub block_hash_hits: u64,
+    pub block_hash_misses: u64,
+    pub full_block_fetches: u64,
+    pub transaction_fetches: u64,
+    pub receipt_fetches: u64,
+}
+
+impl BackendMetrics {
+    pub fn account_hit_ratio(&self) -> f64 {
+        hit_ratio(self.account_hits, self.account_misses)
+    }
+
+    pub fn storage_hit_ratio(&self) -> f64 {
+        hit_ratio(self.storage_hits, self.storage_misses)
+    }
+
+    pub fn block_hash_hit_ratio(&self) -> f64 {
+        hit_ratio(self.block_hash_hits, self.block_hash_misses)
+    }
+}
+
+fn hit_ratio(hits: u64, misses: u64) -> f64 {
+    let total = hits + misses;
+    if total == 0 {
+        return 0.0;
+    }
+    hits as f64 / total as f64
+}
+
+impl fmt::Display for BackendMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "accounts: {} hits / {} misses ({:.1}%), storage: {} hits / {} misses ({:.1}%), block \
+             hashes: {} hits / {} misses ({:.1}%), full blocks: {}, transactions: {}, receipts: {}",
+            self.account_hits,
+            self.account_misses,
+            self.account_hit_ratio() * 100.0,
+            self.storage_hits,
+            self.storage_misses,
+            self.storage_hit_ratio() * 100.0,
+            self.block_hash_hits,
+            self.block_hash_misses,
+            self.block_hash_hit_ratio() * 100.0,
+            self.full_block_fetches,
+            self.transaction_fetches,
+            self.receipt_fetches,
+        )
+    }
+}
@@ -1,12 +1,16 @@
 use std::path::Path;
 use std::sync::Arc;
 
-use reth_chainspec::ChainSpecBuilder;
-use reth_db::{open_db_read_only, DatabaseEnv};
+use alloy_primitives::{Address, U256};
+use reth_chainspec::{ChainSpec, HOLESKY, MAINNET, SEPOLIA};
+use reth_db::cursor::DbDupCursorRO;
+use reth_db::transaction::DbTx;
+use reth_db::{open_db_read_only, tables, DatabaseEnv};
 use reth_node_ethereum::EthereumNode;
 use reth_node_types::NodeTypesWithDBAdapter;
 use reth_provider::providers::StaticFileProvider;
-use reth_provider::ProviderFactory;
+use reth_provider::{BlockNumReader, DBProvider, ProviderFactory};
+use revm::primitives::map::HashMap;
 
 pub type DBFactory = ProviderFactory<NodeTypesWithDBAdapter<EthereumNode, Arc<DatabaseEnv>>>;
 
@@ -16,12 +20,72 @@ pub fn get_db(db_path: &str) -> DatabaseEnv {
 }
 
 pub fn get_db_factory(db_path: &str, static_path: &str) -> DBFactory {
+    get_db_factory_with_spec(db_path, static_path, MAINNET.clone())
+}
+
+/// Same as `get_db_factory` for a database synced on another chain.
+pub fn get_db_factory_with_spec(
+    db_path: &str,
+    static_path: &str,
+    chain_spec: Arc<ChainSpec>,
+) -> DBFactory {
     let db = get_db(db_path);
-    let spec = ChainSpecBuilder::mainnet().build();
 
     ProviderFactory::<NodeTypesWithDBAdapter<EthereumNode, Arc<DatabaseEnv>>>::new(
         db.into(),
-        spec.into(),
+        chain_spec,
         StaticFileProvider::read_only(static_path, true).unwrap(),
     )
 }
+
+/// Chain spec of `mainnet`, `sepolia` or `holesky`.
+pub fn chain_spec_by_name(name: &str) -> Option<Arc<ChainSpec>> {
+    match name.to_ascii_lowercase().as_str() {
+        "mainnet" => Some(MAINNET.clone()),
+        "sepolia" => Some(SEPOLIA.clone()),
+        "holesky" => Some(HOLESKY.clone()),
+        _ => None,
+    }
+}
+
+/// Chain spec of mainnet, Sepolia or Holesky by chain id.
+pub fn chain_spec_by_id(chain_id: u64) -> Option<Arc<ChainSpec>> {
+    [MAINNET.clone(), SEPOLIA.clone(), HOLESKY.clone()]
+        .into_iter()
+        .find(|chain_spec| chain_spec.chain.id() == chain_id)
+}
+
+/// Reads every storage slot of `address` from the plain state table.
+///
+/// The plain state only holds the latest state of the node, so the result is
+/// the storage at the database tip. Fails if `block_number` is set and isn't
+/// the tip.
+pub fn dump_plain_storage(
+    db_factory: &DBFactory,
+    address: Address,
+    block_number: Option<u64>,
+) -> eyre::Result<HashMap<U256, U256>> {
+    let provider = db_factory.provider()?;
+
+    if let Some(block_number) = block_number {
+        let tip = provider.best_block_number()?;
+        if block_number != tip {
+            eyre::bail!(
+                "plain state is only readable at the tip. block_number={}, tip={}",
+                block_number,
+                tip
+            );
+        }
+    }
+    let mut cursor = provider
+        .tx_ref()
+        .cursor_dup_read::<tables::PlainStorageState>()?;
+
+    let mut storage = HashMap::default();
+    for entry in cursor.walk_dup(Some(address), None)? {
+        let (_, entry) = entry?;
+        storage.insert(U256::from_be_bytes(entry.key.0), entry.value);
+    }
+
+    Ok(storage)
+}
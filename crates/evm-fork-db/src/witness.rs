@@ -0,0 +1,140 @@
+//! Records the state a simulation reads, so it can be replayed offline.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use alloy_primitives::{Address, B256, U256};
+use parking_lot::RwLock;
+use revm::db::DatabaseRef;
+use revm::primitives::map::{AddressHashMap, B256HashMap, HashMap};
+use revm::primitives::{AccountInfo, Bytecode};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::SharedBackend;
+use crate::error::DatabaseError;
+
+/// Accounts, storage slots, code and block hashes read through a
+/// [`RecordingBackend`].
+///
+/// Implements [`DatabaseRef`] itself, so a recorded simulation can be run
+/// again without RPC. Slots that were never read are zero, accounts that were
+/// never read don't exist.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateWitness {
+    pub accounts: AddressHashMap<AccountInfo>,
+    pub storage: AddressHashMap<HashMap<U256, U256>>,
+    pub contracts: B256HashMap<Bytecode>,
+    pub block_hashes: HashMap<u64, B256>,
+}
+
+impl StateWitness {
+    /// Writes the witness as JSON.
+    pub fn save(&self, path: &Path) -> eyre::Result<()> {
+        fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+}
+
+impl DatabaseRef for StateWitness {
+    type Error = DatabaseError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self.accounts.get(&address).cloned())
+    }
+
+    fn code_by_hash_ref(&self, hash: B256) -> Result<Bytecode, Self::Error> {
+        self.contracts
+            .get(&hash)
+            .cloned()
+            .ok_or(DatabaseError::MissingCode(hash))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        Ok(self
+            .storage
+            .get(&address)
+            .and_then(|storage| storage.get(&index).copied())
+            .unwrap_or_default())
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.block_hashes.get(&number).copied().ok_or_else(|| {
+            DatabaseError::GetBlockHash(number, Arc::new(eyre::eyre!("not in state witness")))
+        })
+    }
+}
+
+/// [`SharedBackend`] wrapper that records every read into a
+/// [`StateWitness`].
+///
+/// Only the read set is kept, unlike the `BlockchainDb` cache which holds
+/// everything fetched by any user of the backend. Clones share the witness.
+#[derive(Clone, Debug)]
+pub struct RecordingBackend {
+    backend: SharedBackend,
+    witness: Arc<RwLock<StateWitness>>,
+}
+
+impl RecordingBackend {
+    pub fn new(backend: SharedBackend) -> Self {
+        Self { backend, witness: Default::default() }
+    }
+
+    /// Returns a copy of everything read so far.
+    pub fn witness(&self) -> StateWitness {
+        self.witness.read().clone()
+    }
+
+    /// Clears the recorded reads.
+    pub fn reset_witness(&self) {
+        *self.witness.write() = StateWitness::default();
+    }
+
+    pub fn backend(&self) -> &SharedBackend {
+        &self.backend
+    }
+}
+
+impl DatabaseRef for RecordingBackend {
+    type Error = DatabaseError;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let account = self.backend.basic_ref(address)?;
+        if let Some(account) = &account {
+            let mut witness = self.witness.write();
+            if let Some(code) = &account.code {
+                witness.contracts.insert(account.code_hash, code.clone());
+            }
+            witness.accounts.insert(address, account.clone());
+        }
+        Ok(account)
+    }
+
+    fn code_by_hash_ref(&self, hash: B256) -> Result<Bytecode, Self::Error> {
+        let code = self.backend.code_by_hash_ref(hash)?;
+        self.witness.write().contracts.insert(hash, code.clone());
+        Ok(code)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let value = self.backend.storage_ref(address, index)?;
+        self.witness
+            .write()
+            .storage
+            .entry(address)
+            .or_default()
+            .insert(index, value);
+        Ok(value)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        let hash = self.backend.block_hash_ref(number)?;
+        self.witness.write().block_hashes.insert(number, hash);
+        Ok(hash)
+    }
+}
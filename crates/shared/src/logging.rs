@@ -3,6 +3,7 @@ use std::path::Path;
 use const_format::formatcp;
 use tracing::level_filters::LevelFilter;
 use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
@@ -10,23 +11,82 @@ use tracing_subscriber::{EnvFilter, Layer};
 const DEFAULT_FILE_DIRECTIVE: &str = formatcp!("info,{}=debug", env!("CARGO_PKG_NAME"));
 const LOG_FILE_NAME: &str = formatcp!("{}.log", env!("CARGO_PKG_NAME"));
 
+/// How often the log file is rolled over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RotationPolicy {
+    #[default]
+    Hourly,
+    Daily,
+    /// A single file that grows forever.
+    Never,
+}
+
+impl From<RotationPolicy> for Rotation {
+    fn from(policy: RotationPolicy) -> Self {
+        match policy {
+            RotationPolicy::Hourly => Rotation::HOURLY,
+            RotationPolicy::Daily => Rotation::DAILY,
+            RotationPolicy::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Format of the logs written to stderr. The log file is always JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StdoutFormat {
+    /// One line per event.
+    #[default]
+    Full,
+    /// Multi-line, for reading by hand.
+    Pretty,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogOptions {
+    pub rotation: RotationPolicy,
+    /// Rolled files to keep, older ones are deleted. Keeps all if `None`.
+    pub max_log_files: Option<usize>,
+    pub stdout_format: StdoutFormat,
+}
+
+/// Same as [`setup_tracing_with`] with the default options: hourly files,
+/// never deleted, and one line per event on stderr.
 pub fn setup_tracing(
     log_directory: Option<&Path>,
     log_file_name: Option<&str>,
+) -> Option<WorkerGuard> {
+    setup_tracing_with(log_directory, log_file_name, LogOptions::default())
+}
+
+pub fn setup_tracing_with(
+    log_directory: Option<&Path>,
+    log_file_name: Option<&str>,
+    options: LogOptions,
 ) -> Option<WorkerGuard> {
     let stdout_filter = EnvFilter::builder()
         .with_env_var("RUST_LOG")
         .with_default_directive(LevelFilter::INFO.into())
         .from_env()
         .unwrap();
-    let stdout_layer = tracing_subscriber::fmt::layer()
-        .with_writer(std::io::stderr)
-        .with_filter(stdout_filter);
+    let stdout_layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let stdout_layer = match options.stdout_format {
+        StdoutFormat::Full => stdout_layer.with_filter(stdout_filter).boxed(),
+        StdoutFormat::Pretty => stdout_layer.pretty().with_filter(stdout_filter).boxed(),
+        StdoutFormat::Json => stdout_layer.json().with_filter(stdout_filter).boxed(),
+    };
 
     let (file_layer, file_guard) = log_directory
         .map(|directory| {
             let log_file_name = log_file_name.unwrap_or(LOG_FILE_NAME);
-            let file_appender = tracing_appender::rolling::hourly(directory, log_file_name);
+
+            let mut builder = RollingFileAppender::builder()
+                .rotation(options.rotation.into())
+                .filename_prefix(log_file_name);
+            if let Some(max_log_files) = options.max_log_files {
+                builder = builder.max_log_files(max_log_files);
+            }
+            let file_appender = builder.build(directory).unwrap();
             let (file_writer, file_guard) = tracing_appender::non_blocking(file_appender);
 
             let file_filter = std::env::var("RUST_FILE_LOG").ok();
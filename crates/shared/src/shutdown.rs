@@ -0,0 +1,31 @@
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+/// Exit code of a process stopped by SIGINT.
+const SIGINT_EXIT_CODE: i32 = 130;
+
+/// Runs `f` on Ctrl-C, then exits the process.
+///
+/// `f` runs on the blocking pool, so it can flush caches and write files even
+/// while the main task is busy. Move the `WorkerGuard` of the logger into `f`
+/// and drop it last so buffered logs are written, the process exits without
+/// running destructors.
+pub fn on_ctrl_c<F>(f: F) -> JoinHandle<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C. error={e:?}");
+            return;
+        }
+
+        info!("Received Ctrl-C, shutting down");
+
+        if let Err(e) = tokio::task::spawn_blocking(f).await {
+            error!("Shutdown handler failed. error={e:?}");
+        }
+
+        std::process::exit(SIGINT_EXIT_CODE);
+    })
+}
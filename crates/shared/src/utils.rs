@@ -1,30 +1,84 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use alloy::primitives::Address;
+use alloy::primitives::{address, Address, Bytes};
 use alloy::pubsub::PubSubFrontend;
 use alloy::rpc::types::{Filter, Log};
+use alloy::sol;
 use alloy::transports::Transport;
-use alloy_network::AnyNetwork;
+use alloy_network::{AnyNetwork, Network};
 use alloy_provider::{Provider, ProviderBuilder, RootProvider, WsConnect};
 use alloy_rpc_client::ClientBuilder;
 use alloy_transport_http::{Client, Http};
 use anyhow::Result;
+use tracing::debug;
+
+sol! {
+    #[sol(rpc)]
+    contract IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+
+        struct Call3Result {
+            bool success;
+            bytes returnData;
+        }
+
+        function aggregate3(Call3[] calldata calls)
+            external
+            payable
+            returns (Call3Result[] memory returnData);
+    }
+}
+
+/// Multicall3, deployed at the same address on every major chain.
+pub const MULTICALL3: Address = address!("cA11bde05977b3631167028862bE2a173976CA11");
+
+/// Calls per `aggregate3`, keeps each `eth_call` under the node's gas and
+/// response size limits.
+const MULTICALL_BATCH_SIZE: usize = 500;
 
 pub fn get_env(key: &str) -> String {
     std::env::var(key).unwrap_or_else(|err| panic!("Missing env; key={key}; err={err}"))
 }
 
+pub fn get_env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
 pub fn get_http_provider(endpoint: &str) -> RootProvider<Http<Client>, AnyNetwork> {
     ProviderBuilder::new()
         .network::<AnyNetwork>()
         .on_client(ClientBuilder::default().http(endpoint.parse().unwrap()))
 }
 
-pub async fn get_ws_provider(endpoint: &str) -> RootProvider<PubSubFrontend> {
+/// Same as `get_http_provider`, failing requests that take longer than
+/// `timeout`.
+pub fn get_http_provider_with_timeout(
+    endpoint: &str,
+    timeout: Duration,
+) -> RootProvider<Http<Client>, AnyNetwork> {
+    let client = Client::builder().timeout(timeout).build().unwrap();
+    let http = Http::with_client(client, endpoint.parse().unwrap());
+    let is_local = http.guess_local();
+
     ProviderBuilder::new()
+        .network::<AnyNetwork>()
+        .on_client(ClientBuilder::default().transport(http, is_local))
+}
+
+pub async fn get_ws_provider(endpoint: &str) -> RootProvider<PubSubFrontend> {
+    try_get_ws_provider(endpoint).await.unwrap()
+}
+
+/// Same as `get_ws_provider`, returning connection errors instead of panicking.
+pub async fn try_get_ws_provider(endpoint: &str) -> Result<RootProvider<PubSubFrontend>> {
+    Ok(ProviderBuilder::new()
         .on_ws(WsConnect::new(endpoint))
-        .await
-        .unwrap()
+        .await?)
 }
 
 pub fn get_block_range(from_block: u64, to_block: u64, chunk: u64) -> Vec<(u64, u64)> {
@@ -34,6 +88,29 @@ pub fn get_block_range(from_block: u64, to_block: u64, chunk: u64) -> Vec<(u64,
         .collect()
 }
 
+/// Errors providers return when a `eth_getLogs` range holds too many logs or
+/// spans too many blocks.
+const LOG_RANGE_ERRORS: [&str; 6] = [
+    "query returned more than",
+    "range too large",
+    "range is too large",
+    "exceed maximum block range",
+    "response size exceeded",
+    "too many results",
+];
+
+fn is_log_range_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    LOG_RANGE_ERRORS
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// Fetches the logs between `from_block` and `to_block`, both inclusive.
+///
+/// When the provider rejects a range for returning too many logs, the range
+/// is split in half until every part goes through. Logs are returned in block
+/// order.
 pub async fn get_logs<P, T>(
     provider: Arc<P>,
     from_block: u64,
@@ -45,15 +122,72 @@ where
     P: Provider<T> + ?Sized + Send + Sync + 'static,
     T: Transport + Clone + Send + Sync + 'static,
 {
-    let mut event_filter = Filter::new()
-        .from_block(from_block)
-        .to_block(to_block)
-        .events(events);
+    let mut logs = Vec::new();
+    // Ranges still to fetch, the next one on top.
+    let mut ranges = vec![(from_block, to_block)];
 
-    if let Some(address) = address {
-        event_filter = event_filter.address(address);
+    while let Some((from_block, to_block)) = ranges.pop() {
+        let mut event_filter = Filter::new()
+            .from_block(from_block)
+            .to_block(to_block)
+            .events(events);
+
+        if let Some(address) = address {
+            event_filter = event_filter.address(address);
+        }
+
+        match provider.get_logs(&event_filter).await {
+            Ok(chunk) => logs.extend(chunk),
+            Err(e) if from_block < to_block && is_log_range_error(&e.to_string()) => {
+                let mid = from_block + (to_block - from_block) / 2;
+                debug!(
+                    "splitting log range. from_block={from_block}, to_block={to_block}, error={e}"
+                );
+                ranges.push((mid + 1, to_block));
+                ranges.push((from_block, mid));
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
 
-    let logs = provider.get_logs(&event_filter).await?;
     Ok(logs)
 }
+
+/// Runs read-only `(target, calldata)` calls through Multicall3, in batches of
+/// `MULTICALL_BATCH_SIZE`, and returns the raw return data per call in input
+/// order.
+///
+/// A reverting call doesn't fail its batch, its return data is left empty so
+/// decoding it fails for that call only.
+pub async fn multicall<P, T, N>(provider: &P, calls: &[(Address, Bytes)]) -> Result<Vec<Bytes>>
+where
+    P: Provider<T, N>,
+    T: Transport + Clone,
+    N: Network,
+{
+    let multicall = IMulticall3::new(MULTICALL3, provider);
+    let mut results = Vec::with_capacity(calls.len());
+
+    for batch in calls.chunks(MULTICALL_BATCH_SIZE) {
+        let batch = batch
+            .iter()
+            .map(|(target, data)| IMulticall3::Call3 {
+                target: *target,
+                allowFailure: true,
+                callData: data.clone(),
+            })
+            .collect();
+
+        let returned = multicall.aggregate3(batch).call().await?.returnData;
+
+        results.extend(returned.into_iter().map(|result| {
+            if result.success {
+                result.returnData
+            } else {
+                Bytes::new()
+            }
+        }));
+    }
+
+    Ok(results)
+}
@@ -17,6 +17,46 @@ sol! {
         function balanceOf(address account) external view returns (uint256 balance);
 
         function transfer(address to, uint value) external returns (bool success);
+
+        function transferFrom(address from, address to, uint256 value)
+            external
+            returns (bool success);
+
+        function approve(address spender, uint256 value) external returns (bool success);
+
+        function allowance(address owner, address spender)
+            external
+            view
+            returns (uint256 remaining);
+    }
+}
+
+sol! {
+    #[derive(Debug, PartialEq, Eq)]
+    #[sol(rpc)]
+    contract IERC2612 {
+        /// EIP-712 typed data signed by the owner.
+        struct Permit {
+            address owner;
+            address spender;
+            uint256 value;
+            uint256 nonce;
+            uint256 deadline;
+        }
+
+        function permit(
+            address owner,
+            address spender,
+            uint256 value,
+            uint256 deadline,
+            uint8 v,
+            bytes32 r,
+            bytes32 s
+        ) external;
+
+        function nonces(address owner) external view returns (uint256);
+
+        function DOMAIN_SEPARATOR() external view returns (bytes32);
     }
 }
 
@@ -84,6 +124,8 @@ sol! {
         function previewMint(uint256 shares) external view returns (uint256);
 
         function previewDeposit(uint256 assets) external view returns (uint256);
+
+        function previewRedeem(uint256 shares) external view returns (uint256);
     }
 }
 
@@ -108,6 +150,24 @@ sol! {
     }
 }
 
+sol! {
+    #[derive(Debug, PartialEq, Eq)]
+    #[sol(rpc)]
+    contract ICurveFactory {
+        event PlainPoolDeployed(address[4] coins, uint256 A, uint256 fee, address deployer);
+
+        event MetaPoolDeployed(
+            address coin,
+            address base_pool,
+            uint256 A,
+            uint256 fee,
+            address deployer
+        );
+
+        function find_pool_for_coins(address from, address to) external view returns (address);
+    }
+}
+
 sol! {
     #[derive(Debug, PartialEq, Eq)]
     #[sol(rpc)]
@@ -180,6 +240,8 @@ sol! {
                 bool initialized
             );
 
+        function tickBitmap(int16 wordPosition) external view returns (uint256);
+
         function swap(
             address recipient,
             bool zeroForOne,
@@ -208,6 +270,24 @@ sol! {
             int128 baseFlow,
             int128 quoteFlow
         );
+
+        /// Emitted by the warm path, which mints and burns liquidity.
+        event CrocWarmCmd(bytes input, int128 baseFlow, int128 quoteFlow);
+
+        function swap(
+            address base,
+            address quote,
+            uint256 poolIdx,
+            bool isBuy,
+            bool inBaseQty,
+            uint128 qty,
+            uint16 tip,
+            uint128 limitPrice,
+            uint128 minOut,
+            uint8 reserveFlags
+        ) external payable returns (int128 baseFlow, int128 quoteFlow);
+
+        function userCmd(uint16 callpath, bytes cmd) external payable returns (bytes);
     }
 }
 
@@ -232,6 +312,8 @@ sol! {
             address indexed to
         );
 
+        event Sync(uint112 reserve0, uint112 reserve1);
+
         function token0() external view returns (address);
 
         function token1() external view returns (address);
@@ -241,6 +323,8 @@ sol! {
             uint112 reserve1,
             uint32 blockTimestampLast
         );
+
+        function swap(uint amount0Out, uint amount1Out, address to, bytes calldata data) external;
     }
 }
 
@@ -258,6 +342,50 @@ sol! {
     }
 }
 
+sol! {
+    #[derive(Debug, PartialEq, Eq)]
+    #[sol(rpc)]
+    contract IAaveV3Pool {
+        function ADDRESSES_PROVIDER() external view returns (address);
+
+        function getUserAccountData(address user)
+            external
+            view
+            returns (
+                uint256 totalCollateralBase,
+                uint256 totalDebtBase,
+                uint256 availableBorrowsBase,
+                uint256 currentLiquidationThreshold,
+                uint256 ltv,
+                uint256 healthFactor
+            );
+
+        function liquidationCall(
+            address collateralAsset,
+            address debtAsset,
+            address user,
+            uint256 debtToCover,
+            bool receiveAToken
+        ) external;
+    }
+}
+
+sol! {
+    #[derive(Debug, PartialEq, Eq)]
+    #[sol(rpc)]
+    contract IAaveV3AddressesProvider {
+        function getPriceOracle() external view returns (address);
+    }
+}
+
+sol! {
+    #[derive(Debug, PartialEq, Eq)]
+    #[sol(rpc)]
+    contract IAaveV3Oracle {
+        function getAssetPrice(address asset) external view returns (uint256);
+    }
+}
+
 sol! {
     #[derive(Debug, PartialEq, Eq)]
     #[sol(rpc)]
@@ -269,3 +397,67 @@ sol! {
         ) external;
     }
 }
+
+sol! {
+    #[derive(Debug, PartialEq, Eq)]
+    #[sol(rpc)]
+    contract IBalancerVault {
+        enum SwapKind {
+            GIVEN_IN,
+            GIVEN_OUT
+        }
+
+        struct SingleSwap {
+            bytes32 poolId;
+            SwapKind kind;
+            address assetIn;
+            address assetOut;
+            uint256 amount;
+            bytes userData;
+        }
+
+        struct BatchSwapStep {
+            bytes32 poolId;
+            uint256 assetInIndex;
+            uint256 assetOutIndex;
+            uint256 amount;
+            bytes userData;
+        }
+
+        struct FundManagement {
+            address sender;
+            bool fromInternalBalance;
+            address recipient;
+            bool toInternalBalance;
+        }
+
+        event PoolRegistered(
+            bytes32 indexed poolId,
+            address indexed poolAddress,
+            uint8 specialization
+        );
+
+        event TokensRegistered(bytes32 indexed poolId, address[] tokens, address[] assetManagers);
+
+        function getPoolTokens(
+            bytes32 poolId
+        )
+            external
+            view
+            returns (address[] tokens, uint256[] balances, uint256 lastChangeBlock);
+
+        function swap(
+            SingleSwap singleSwap,
+            FundManagement funds,
+            uint256 limit,
+            uint256 deadline
+        ) external payable returns (uint256 amountCalculated);
+
+        function queryBatchSwap(
+            SwapKind kind,
+            BatchSwapStep[] swaps,
+            address[] assets,
+            FundManagement funds
+        ) external returns (int256[] assetDeltas);
+    }
+}
@@ -0,0 +1,216 @@
+//! Decoding of the events the bins care about, dispatched on the first topic.
+
+use alloy::primitives::Log;
+use alloy_sol_types::SolEvent;
+
+use crate::abi;
+
+#[derive(Debug, Clone)]
+pub enum DecodedEvent {
+    Transfer(abi::IERC20::Transfer),
+    V2Swap(abi::IUniswapV2Pair::Swap),
+    V2Sync(abi::IUniswapV2Pair::Sync),
+    V3Swap(abi::IUniswapV3Pool::Swap),
+    V3Mint(abi::IUniswapV3Pool::Mint),
+    V3Burn(abi::IUniswapV3Pool::Burn),
+    CrocSwap(abi::CrocSwapDex::CrocSwap),
+    CrocWarmCmd(abi::CrocSwapDex::CrocWarmCmd),
+}
+
+fn decode<E: SolEvent>(log: &Log) -> Option<E> {
+    E::decode_log(log, false).ok().map(|log| log.data)
+}
+
+/// Decodes `log` if its signature is one of `DecodedEvent`.
+///
+/// Returns `None` for anonymous logs, unknown signatures and logs that share
+/// a known signature but not its layout, such as ERC721 `Transfer`.
+pub fn decode_known_event(log: &Log) -> Option<DecodedEvent> {
+    let topic = *log.topics().first()?;
+
+    match topic {
+        abi::IERC20::Transfer::SIGNATURE_HASH => decode(log).map(DecodedEvent::Transfer),
+        abi::IUniswapV2Pair::Swap::SIGNATURE_HASH => decode(log).map(DecodedEvent::V2Swap),
+        abi::IUniswapV2Pair::Sync::SIGNATURE_HASH => decode(log).map(DecodedEvent::V2Sync),
+        abi::IUniswapV3Pool::Swap::SIGNATURE_HASH => decode(log).map(DecodedEvent::V3Swap),
+        abi::IUniswapV3Pool::Mint::SIGNATURE_HASH => decode(log).map(DecodedEvent::V3Mint),
+        abi::IUniswapV3Pool::Burn::SIGNATURE_HASH => decode(log).map(DecodedEvent::V3Burn),
+        abi::CrocSwapDex::CrocSwap::SIGNATURE_HASH => decode(log).map(DecodedEvent::CrocSwap),
+        abi::CrocSwapDex::CrocWarmCmd::SIGNATURE_HASH => decode(log).map(DecodedEvent::CrocWarmCmd),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::aliases::{I24, U112, U160};
+    use alloy::primitives::{address, b256, bytes, Address, Bytes, LogData, B256, I256, U256};
+
+    use super::*;
+
+    const POOL: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+    const ALICE: Address = address!("1111111111111111111111111111111111111111");
+    const BOB: Address = address!("2222222222222222222222222222222222222222");
+
+    fn raw_log(topics: Vec<B256>, data: Bytes) -> Log {
+        Log { address: POOL, data: LogData::new(topics, data).unwrap() }
+    }
+
+    fn encoded<E: SolEvent>(event: &E) -> Log {
+        Log { address: POOL, data: event.encode_log_data() }
+    }
+
+    #[test]
+    fn decodes_a_transfer_log() {
+        let log = raw_log(
+            vec![
+                b256!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"),
+                ALICE.into_word(),
+                BOB.into_word(),
+            ],
+            U256::from(1000).to_be_bytes_vec().into(),
+        );
+
+        match decode_known_event(&log) {
+            Some(DecodedEvent::Transfer(event)) => {
+                assert_eq!((event.from, event.to), (ALICE, BOB));
+                assert_eq!(event.value, U256::from(1000));
+            }
+            other => panic!("expected a transfer, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decodes_a_v2_sync_log() {
+        let log = raw_log(
+            vec![b256!("1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1")],
+            bytes!(
+                "0000000000000000000000000000000000000000000000000000000000000064"
+                "00000000000000000000000000000000000000000000000000000000000000c8"
+            ),
+        );
+
+        match decode_known_event(&log) {
+            Some(DecodedEvent::V2Sync(event)) => {
+                assert_eq!(event.reserve0, U112::from(100));
+                assert_eq!(event.reserve1, U112::from(200));
+            }
+            other => panic!("expected a sync, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn decodes_every_known_event() {
+        let v2_swap = abi::IUniswapV2Pair::Swap {
+            sender: ALICE,
+            amount0In: U256::from(1),
+            amount1In: U256::ZERO,
+            amount0Out: U256::ZERO,
+            amount1Out: U256::from(2),
+            to: BOB,
+        };
+        let v3_swap = abi::IUniswapV3Pool::Swap {
+            sender: ALICE,
+            recipient: BOB,
+            amount0: I256::try_from(-5).unwrap(),
+            amount1: I256::try_from(7).unwrap(),
+            sqrtPriceX96: U160::from(1) << 96,
+            liquidity: 1_000,
+            tick: I24::try_from(-887_272).unwrap(),
+        };
+        let mint = abi::IUniswapV3Pool::Mint {
+            sender: ALICE,
+            owner: BOB,
+            tickLower: I24::try_from(-60).unwrap(),
+            tickUpper: I24::try_from(60).unwrap(),
+            amount: 10,
+            amount0: U256::from(3),
+            amount1: U256::from(4),
+        };
+        let burn = abi::IUniswapV3Pool::Burn {
+            owner: BOB,
+            tickLower: I24::try_from(-60).unwrap(),
+            tickUpper: I24::try_from(60).unwrap(),
+            amount: 10,
+            amount0: U256::from(3),
+            amount1: U256::from(4),
+        };
+        let croc_swap = abi::CrocSwapDex::CrocSwap {
+            base: Address::ZERO,
+            quote: ALICE,
+            poolIdx: U256::from(420),
+            isBuy: true,
+            inBaseQty: false,
+            qty: 5,
+            tip: 0,
+            limitPrice: 21,
+            minOut: 1,
+            reserveFlags: 0,
+            baseFlow: -9,
+            quoteFlow: 8,
+        };
+        let warm_cmd =
+            abi::CrocSwapDex::CrocWarmCmd { input: bytes!("01020304"), baseFlow: 6, quoteFlow: -6 };
+
+        assert!(matches!(
+            decode_known_event(&encoded(&v2_swap)),
+            Some(DecodedEvent::V2Swap(event)) if event == v2_swap
+        ));
+        assert!(matches!(
+            decode_known_event(&encoded(&v3_swap)),
+            Some(DecodedEvent::V3Swap(event)) if event == v3_swap
+        ));
+        assert!(matches!(
+            decode_known_event(&encoded(&mint)),
+            Some(DecodedEvent::V3Mint(event)) if event == mint
+        ));
+        assert!(matches!(
+            decode_known_event(&encoded(&burn)),
+            Some(DecodedEvent::V3Burn(event)) if event == burn
+        ));
+        assert!(matches!(
+            decode_known_event(&encoded(&croc_swap)),
+            Some(DecodedEvent::CrocSwap(event)) if event == croc_swap
+        ));
+        assert!(matches!(
+            decode_known_event(&encoded(&warm_cmd)),
+            Some(DecodedEvent::CrocWarmCmd(event)) if event == warm_cmd
+        ));
+    }
+
+    #[test]
+    fn erc721_transfer_is_not_decoded() {
+        // Same signature as ERC20, but the token id is indexed and there is no data.
+        let log = raw_log(
+            vec![
+                abi::IERC20::Transfer::SIGNATURE_HASH,
+                ALICE.into_word(),
+                BOB.into_word(),
+                U256::from(1).into(),
+            ],
+            Bytes::new(),
+        );
+
+        assert!(decode_known_event(&log).is_none());
+    }
+
+    #[test]
+    fn anonymous_and_unknown_logs_are_not_decoded() {
+        let anonymous = raw_log(vec![], Bytes::from(vec![1; 32]));
+        let unknown = raw_log(vec![B256::repeat_byte(0xab)], Bytes::from(vec![1; 32]));
+
+        assert!(decode_known_event(&anonymous).is_none());
+        assert!(decode_known_event(&unknown).is_none());
+    }
+
+    #[test]
+    fn truncated_log_is_not_decoded() {
+        let mut log = encoded(&abi::IUniswapV2Pair::Sync {
+            reserve0: U112::from(1),
+            reserve1: U112::from(2),
+        });
+        log.data = LogData::new(log.topics().to_vec(), Bytes::from(vec![0; 32])).unwrap();
+
+        assert!(decode_known_event(&log).is_none());
+    }
+}
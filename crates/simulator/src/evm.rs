@@ -1,22 +1,134 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use alloy::primitives::Address;
-use alloy_sol_types::SolCall;
+use alloy::consensus::{Transaction, TxEnvelope};
+use alloy::eips::eip2718::Decodable2718;
+use alloy::eips::eip2930::{AccessList, AccessListItem};
+use alloy::network::AnyRpcTransaction;
+use alloy::primitives::{address, keccak256, Address, Bytes, B256, I256};
+use alloy::rpc::types::BlockId;
+use alloy::signers::local::PrivateKeySigner;
+use alloy::signers::SignerSync;
+use alloy_sol_types::{SolCall, SolStruct, SolValue};
 use anyhow::{anyhow, Result};
-use evm_fork_db::backend::SharedBackend;
+use evm_fork_db::backend::{BlockingMode, SharedBackend};
 use evm_fork_db::cache::{BlockchainDb, BlockchainDbMeta};
+use evm_fork_db::commit_log::StateWrite;
 use evm_fork_db::database::ForkedDatabase;
-use evm_fork_db::types::get_db_factory;
+use evm_fork_db::types::{get_db_factory, get_db_factory_with_spec};
+use foundry_evm::backend::RevertStateSnapshotAction;
+use rayon::prelude::*;
+use reth_chainspec::ChainSpec;
 use revm::db::WrapDatabaseRef;
 use revm::primitives::state::AccountInfo;
-use revm::primitives::{Account, Bytecode, ExecutionResult, Output, TransactTo, SHANGHAI, U256};
-use revm::{Database, Evm};
-use shared::utils::get_http_provider;
-use tracing::error;
+use revm::primitives::{
+    Account, BlockEnv, Bytecode, Env, EnvWithHandlerCfg, ExecutionResult, Output, SpecId,
+    TransactTo, TxEnv, KECCAK_EMPTY, U256,
+};
+use revm::{inspector_handle_register, Database, Evm};
+use shared::utils::{get_env_or, get_http_provider, get_http_provider_with_timeout};
+use tracing::{error, warn};
 
 use crate::abi;
 use crate::bytecode::SIMULATOR_BYTECODE;
+use crate::revert::{call_output, revert_reason_or_output};
+use crate::spec::{spec_id_for_chain, spec_id_from_block_number};
+use crate::trace::{CallTrace, CallTracer};
+use crate::tx::fill_tx_env;
+use crate::types::{
+    BlockOverrides, BundleSimResult, BundleTxResult, SandwichResult, SimOutcome, TxOptions,
+};
+
+pub type SnapshotId = U256;
+
+/// WETH on mainnet, the default of `EVM::from_env`.
+pub const MAINNET_WETH: Address = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
+
+/// Highest base slot tried when looking for an ERC20 balance mapping.
+const MAX_BALANCE_SLOT: u64 = 30;
+
+/// Amount moved by `is_fee_on_transfer`, in the token's smallest unit.
+const FEE_PROBE_AMOUNT: u64 = 1_000_000_000;
+
+/// Wei a transfer may lose to share rounding (stETH-like tokens) without
+/// counting as a fee.
+const FEE_ROUNDING_TOLERANCE: u64 = 2;
+
+/// ERC20 metadata read so far, per token.
+#[derive(Debug, Clone, Default)]
+struct TokenMetadata {
+    decimals: Option<u8>,
+    symbol: Option<String>,
+    name: Option<String>,
+}
+
+/// Decodes a `string` return value, or the `bytes32` returned by older tokens
+/// like MKR and SAI, trimmed at the first zero byte.
+fn decode_string_or_bytes32(value: &Bytes) -> Result<String> {
+    if let Ok(value) = String::abi_decode(value, false) {
+        return Ok(value);
+    }
+
+    if value.len() == 32 {
+        let end = value.iter().position(|b| *b == 0).unwrap_or(32);
+        return Ok(String::from_utf8_lossy(&value[..end]).into_owned());
+    }
+
+    Err(anyhow!("invalid string return value. value={}", value))
+}
+
+/// Location of an ERC20 balance mapping in the token's storage.
+#[derive(Debug, Clone, Copy)]
+struct BalanceSlot {
+    slot: U256,
+    /// Vyper hashes the slot before the key, Solidity the key before the slot.
+    vyper: bool,
+}
+
+impl BalanceSlot {
+    fn key(&self, holder: Address) -> U256 {
+        let encoded = if self.vyper {
+            (self.slot, holder).abi_encode()
+        } else {
+            (holder, self.slot).abi_encode()
+        };
+        keccak256(encoded).into()
+    }
+}
+
+/// Gas limit used when a fee is set without an explicit limit, so the caller
+/// doesn't need to cover `u64::MAX` gas.
+const DEFAULT_GAS_LIMIT: u64 = 30_000_000;
+
+/// Writes the gas fields of `tx_options` to `env`, see `EVM::set_tx_options`.
+fn apply_tx_options(env: &mut Env, tx_options: &TxOptions) -> Result<()> {
+    // The max fee would stay at zero, below the basefee and the tip.
+    if tx_options.max_priority_fee_per_gas.is_some() && tx_options.max_fee_per_gas.is_none() {
+        return Err(anyhow!("max_priority_fee_per_gas is set without max_fee_per_gas"));
+    }
+
+    let has_fee = tx_options.max_fee_per_gas.is_some();
+    env.tx.gas_limit = match tx_options.gas_limit {
+        Some(gas_limit) => gas_limit,
+        None if has_fee => DEFAULT_GAS_LIMIT,
+        None => u64::MAX,
+    };
+    env.tx.gas_price = U256::from(tx_options.max_fee_per_gas.unwrap_or_default());
+    env.tx.gas_priority_fee = tx_options.max_priority_fee_per_gas.map(U256::from);
+    env.cfg.disable_base_fee = !has_fee;
+
+    Ok(())
+}
+
+fn parse_bytecode(bytecode_str: &str) -> Result<Bytecode> {
+    let bytes: Bytes = bytecode_str
+        .parse()
+        .map_err(|e| anyhow!("failed to parse bytecode. error={:?}", e))?;
+
+    Ok(Bytecode::new_legacy(bytes))
+}
 
 pub struct EVM<'a> {
     backend: SharedBackend,
@@ -24,34 +136,223 @@ pub struct EVM<'a> {
     pub evm: Evm<'a, (), WrapDatabaseRef<ForkedDatabase>>,
 
     weth: Address,
+    /// Pinned block, can be a tag such as `pending`.
+    block_id: BlockId,
+    /// Number of the pinned block, resolved from the header for tags.
     block_number: u64,
     owner: Address,
+    owner_balance: U256,
+    /// Token balances of the owner, set again on `repin` and `reset`.
+    owner_tokens: Vec<(Address, U256)>,
     simulator: Address,
+    /// Runtime code deployed as the simulator, `None` if the simulator is a
+    /// contract of the fork set with `set_simulator_address`.
+    simulator_bytecode: Option<String>,
+    read_only: bool,
+    /// Block number and block env to restore for each live snapshot.
+    snapshots: HashMap<SnapshotId, (u64, BlockEnv)>,
+    /// Balance mapping slots found by `set_token_balance`, per token.
+    balance_slots: HashMap<Address, BalanceSlot>,
+    /// Block env values applied on top of the pinned block's header.
+    block_overrides: BlockOverrides,
+    /// Hardfork set by the caller, otherwise it follows the pinned block.
+    fixed_spec_id: Option<SpecId>,
+    /// Token metadata never changes, it is cached across blocks.
+    token_metadata: HashMap<Address, TokenMetadata>,
+    /// `(token0, token1)` per pool, immutable like the token metadata.
+    pool_tokens: HashMap<Address, (Address, Address)>,
+    /// Chain the hardforks are picked from, mainnet if `None`.
+    chain_spec: Option<Arc<ChainSpec>>,
 }
 
-impl<'a> EVM<'a> {
-    pub async fn new(
-        rpc_url: &str,
-        db_path: Option<&str>,
-        static_path: Option<&str>,
-        block_number: u64,
-        weth: Address,
-        owner: Address,
-        balance: U256,
-    ) -> Self {
-        let http_provider = get_http_provider(rpc_url);
+/// An `EVM` without its revm `Evm`, which isn't `Send`, so it can be kept
+/// by a pool shared across threads. See `EVM::into_parts`.
+pub(crate) struct EvmParts {
+    backend: SharedBackend,
+    fork: ForkedDatabase,
+    db: ForkedDatabase,
+    env: EnvWithHandlerCfg,
+    weth: Address,
+    block_id: BlockId,
+    block_number: u64,
+    owner: Address,
+    owner_balance: U256,
+    owner_tokens: Vec<(Address, U256)>,
+    simulator: Address,
+    simulator_bytecode: Option<String>,
+    read_only: bool,
+    snapshots: HashMap<SnapshotId, (u64, BlockEnv)>,
+    balance_slots: HashMap<Address, BalanceSlot>,
+    block_overrides: BlockOverrides,
+    fixed_spec_id: Option<SpecId>,
+    token_metadata: HashMap<Address, TokenMetadata>,
+    pool_tokens: HashMap<Address, (Address, Address)>,
+    chain_spec: Option<Arc<ChainSpec>>,
+}
 
-        let file_db_factory = db_path.map(|path| {
-            get_db_factory(path, static_path.expect("static_path must be provided with db_path"))
-        });
+pub struct EVMBuilder {
+    rpc_url: String,
+    db_path: Option<String>,
+    static_path: Option<String>,
+    cache_path: Option<PathBuf>,
+    block_id: BlockId,
+    weth: Address,
+    owner: Address,
+    balance: U256,
+    owner_tokens: Vec<(Address, U256)>,
+    simulator_bytecode: Option<String>,
+    read_only: bool,
+    tx_options: TxOptions,
+    request_timeout: Option<Duration>,
+    spec_id: Option<SpecId>,
+    chain_spec: Option<Arc<ChainSpec>>,
+    prune_on_repin: bool,
+}
+
+impl EVMBuilder {
+    /// `block` is a block number or a `BlockId`, including
+    /// `BlockNumberOrTag::Pending` to fork from the pending block.
+    pub fn new(rpc_url: &str, block: impl Into<BlockId>, weth: Address) -> Self {
+        Self {
+            rpc_url: rpc_url.to_string(),
+            db_path: None,
+            static_path: None,
+            cache_path: None,
+            block_id: block.into(),
+            weth,
+            owner: Address::ZERO,
+            balance: U256::ZERO,
+            owner_tokens: Vec::new(),
+            simulator_bytecode: None,
+            read_only: false,
+            tx_options: TxOptions::default(),
+            request_timeout: None,
+            spec_id: None,
+            chain_spec: None,
+            prune_on_repin: false,
+        }
+    }
+
+    /// Reads state from a local reth database before falling back to RPC.
+    pub fn local_db(mut self, db_path: &str, static_path: &str) -> Self {
+        self.db_path = Some(db_path.to_string());
+        self.static_path = Some(static_path.to_string());
+        self
+    }
+
+    /// Loads accounts, storage and block hashes from a JSON cache written by a
+    /// previous run, only hitting RPC for misses. The cache is flushed back to
+    /// the same file when the last backend handle is dropped.
+    ///
+    /// The cache is not keyed by block, so it should only be reused for the
+    /// block it was filled at.
+    pub fn cache_path(mut self, cache_path: impl Into<PathBuf>) -> Self {
+        self.cache_path = Some(cache_path.into());
+        self
+    }
+
+    pub fn owner(mut self, owner: Address, balance: U256) -> Self {
+        self.owner = owner;
+        self.balance = balance;
+        self
+    }
+
+    /// Funds the owner with `amount` of `token` once built, see
+    /// `EVM::fund_owner_token`.
+    pub fn owner_token(mut self, token: Address, amount: U256) -> Self {
+        self.owner_tokens.push((token, amount));
+        self
+    }
+
+    /// Deploys `bytecode`, the runtime code of a custom helper, as the
+    /// simulator instead of the bundled contract.
+    pub fn simulator_bytecode(mut self, bytecode: &str) -> Self {
+        self.simulator_bytecode = Some(bytecode.to_string());
+        self
+    }
+
+    /// Skips funding the owner and deploying the simulator contract.
+    ///
+    /// Meant for workflows that only read chain state, such as pool hydration.
+    /// Methods that need the simulator return an error on a read-only EVM.
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn tx_options(mut self, tx_options: TxOptions) -> Self {
+        self.tx_options = tx_options;
+        self
+    }
+
+    /// Fails RPC requests that take longer than `timeout`, so a hung node
+    /// surfaces as a database error instead of stalling the simulation.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Runs with `spec_id` instead of the mainnet hardfork of the pinned
+    /// block.
+    pub fn spec_id(mut self, spec_id: SpecId) -> Self {
+        self.spec_id = Some(spec_id);
+        self
+    }
+
+    /// Forks a chain other than mainnet, see
+    /// `evm_fork_db::types::chain_spec_by_id`. Hardforks follow its
+    /// activations and the local db is opened with it.
+    pub fn chain_spec(mut self, chain_spec: Arc<ChainSpec>) -> Self {
+        self.chain_spec = Some(chain_spec);
+        self
+    }
+
+    /// Drops the fetched state whenever `set_block` moves to another block,
+    /// so values fetched at the previous block are never served for the new
+    /// one. Committed changes are kept, use `EVM::repin` to drop them too.
+    pub fn prune_on_repin(mut self) -> Self {
+        self.prune_on_repin = true;
+        self
+    }
+
+    /// Fails if the simulator can't be deployed, unless the EVM is read-only.
+    pub async fn build<'a>(self) -> Result<EVM<'a>> {
+        let http_provider = match self.request_timeout {
+            Some(timeout) => get_http_provider_with_timeout(&self.rpc_url, timeout),
+            None => get_http_provider(&self.rpc_url),
+        };
+
+        // Reth history is read at a block number, a tag can only use RPC.
+        let file_db_factory = match self.db_path.as_deref() {
+            Some(_) if self.block_id.as_u64().is_none() => {
+                warn!(
+                    "local db needs a block number, using RPC only. block_id={:?}",
+                    self.block_id
+                );
+                None
+            }
+            Some(path) => {
+                let static_path = self
+                    .static_path
+                    .as_deref()
+                    .expect("static_path must be provided with db_path");
+                Some(match self.chain_spec.clone() {
+                    Some(chain_spec) => get_db_factory_with_spec(path, static_path, chain_spec),
+                    None => get_db_factory(path, static_path),
+                })
+            }
+            None => None,
+        };
 
         let meta = BlockchainDbMeta {
             cfg_env: Default::default(),
             block_env: Default::default(),
-            hosts: BTreeSet::from([rpc_url.to_string()]),
+            hosts: BTreeSet::from([self.rpc_url.clone()]),
         };
 
-        let db = BlockchainDb::new(meta, None);
+        // The stored meta holds the RPC host, skip the check so a cache filled
+        // with a different endpoint can still be loaded.
+        let db = BlockchainDb::new_skip_check(meta, self.cache_path.clone());
 
         let backend = SharedBackend::spawn_backend(
             Arc::new(http_provider.clone()),
@@ -61,42 +362,324 @@ impl<'a> EVM<'a> {
         )
         .await;
 
+        if self.request_timeout.is_some() {
+            if let Err(e) = backend.set_request_timeout(self.request_timeout) {
+                error!("failed to set request timeout. error={e:?}");
+            }
+        }
+
+        if self.prune_on_repin {
+            if let Err(e) = backend.set_prune_on_repin(true) {
+                error!("failed to enable pruning on repin. error={e:?}");
+            }
+        }
+
         let fork = ForkedDatabase::new(backend.clone(), db.clone());
 
-        let evm = Evm::builder()
-            .with_spec_id(SHANGHAI)
-            .with_ref_db(fork.clone())
-            .build();
+        let evm = Evm::builder().with_ref_db(fork.clone()).build();
 
-        let mut _self = Self {
+        let mut _evm = EVM {
             backend,
             fork,
             evm,
-            weth,
-            block_number,
+            weth: self.weth,
+            block_id: self.block_id,
+            block_number: self.block_id.as_u64().unwrap_or_default(),
             owner: Address::default(),
+            owner_balance: U256::ZERO,
+            owner_tokens: Vec::new(),
             simulator: Address::default(),
+            simulator_bytecode: Some(
+                self.simulator_bytecode
+                    .unwrap_or_else(|| SIMULATOR_BYTECODE.to_string()),
+            ),
+            read_only: self.read_only,
+            snapshots: HashMap::new(),
+            balance_slots: HashMap::new(),
+            block_overrides: BlockOverrides::default(),
+            fixed_spec_id: self.spec_id,
+            token_metadata: HashMap::new(),
+            pool_tokens: HashMap::new(),
+            chain_spec: self.chain_spec,
         };
 
-        _self.set_block_number(block_number);
-        _self.setup_owner(owner, balance);
+        _evm.set_block(self.block_id);
+        _evm.set_tx_options(&self.tx_options)?;
+
+        if !self.read_only {
+            _evm.setup_owner(self.owner, self.balance);
+            _evm.simulator = _evm.deploy_simulator(None)?;
+
+            for (token, amount) in self.owner_tokens {
+                _evm.fund_owner_token(token, amount)?;
+            }
+        }
+
+        Ok(_evm)
+    }
+}
+
+impl<'a> EVM<'a> {
+    pub async fn new(
+        rpc_url: &str,
+        db_path: Option<&str>,
+        static_path: Option<&str>,
+        block: impl Into<BlockId>,
+        weth: Address,
+        owner: Address,
+        balance: U256,
+    ) -> Result<Self> {
+        let mut builder = EVMBuilder::new(rpc_url, block, weth).owner(owner, balance);
 
-        _self.simulator = _self.deploy_simulator(None);
+        if let Some(db_path) = db_path {
+            let static_path = static_path.expect("static_path must be provided with db_path");
+            builder = builder.local_db(db_path, static_path);
+        }
+
+        builder.build().await
+    }
+
+    /// Builds an EVM from the environment, for the bins:
+    ///
+    /// - `RPC_HTTPS_URL`, required
+    /// - `RETH_DB_PATH` and `RETH_STATIC_PATH`, optional, both or neither
+    /// - `WETH_ADDRESS`, defaults to `MAINNET_WETH`
+    /// - `RPC_CACHE_DIR`, optional, caches the fetched state in
+    ///   `<RPC_CACHE_DIR>/<block_number>.json`, see `EVMBuilder::cache_path`
+    ///
+    /// The owner is a random address funded with 1 ETH.
+    pub async fn from_env(block_number: u64) -> Result<Self> {
+        let weth = get_env_or("WETH_ADDRESS", &MAINNET_WETH.to_string());
+        let weth: Address = weth
+            .parse()
+            .map_err(|e| anyhow!("invalid WETH_ADDRESS={weth}. error={e:?}"))?;
+
+        Self::from_env_with_weth(block_number, weth).await
+    }
+
+    /// Same as `from_env`, with `weth` given instead of read from
+    /// `WETH_ADDRESS`.
+    pub async fn from_env_with_weth(block_number: u64, weth: Address) -> Result<Self> {
+        let rpc_url = std::env::var("RPC_HTTPS_URL")
+            .map_err(|e| anyhow!("missing env. key=RPC_HTTPS_URL, error={e:?}"))?;
+
+        let db_path = std::env::var("RETH_DB_PATH").ok();
+        let static_path = std::env::var("RETH_STATIC_PATH").ok();
+        if db_path.is_some() != static_path.is_some() {
+            return Err(anyhow!("RETH_DB_PATH and RETH_STATIC_PATH must be set together"));
+        }
+
+        let mut builder = EVMBuilder::new(&rpc_url, block_number, weth)
+            .owner(Address::random(), U256::from(10_u64.pow(18))); // 1 ETH
+
+        if let (Some(db_path), Some(static_path)) = (db_path, static_path) {
+            builder = builder.local_db(&db_path, &static_path);
+        }
+
+        // One file per block, the cache isn't keyed by block.
+        if let Ok(cache_dir) = std::env::var("RPC_CACHE_DIR") {
+            builder =
+                builder.cache_path(Path::new(&cache_dir).join(format!("{block_number}.json")));
+        }
+
+        builder.build().await
+    }
+
+    /// Warm-starts an EVM from a JSON cache flushed by a previous run, see
+    /// [`EVMBuilder::cache_path`]. `rpc_url` is only used for cache misses.
+    pub async fn new_from_cache(
+        rpc_url: &str,
+        cache_path: impl Into<PathBuf>,
+        block_number: u64,
+        weth: Address,
+        owner: Address,
+        balance: U256,
+    ) -> Result<Self> {
+        EVMBuilder::new(rpc_url, block_number, weth)
+            .cache_path(cache_path)
+            .owner(owner, balance)
+            .build()
+            .await
+    }
 
-        _self
+    pub fn builder(rpc_url: &str, block: impl Into<BlockId>, weth: Address) -> EVMBuilder {
+        EVMBuilder::new(rpc_url, block, weth)
     }
 
     pub fn db(&self) -> &ForkedDatabase {
         &self.fork
     }
 
+    pub fn backend(&self) -> &SharedBackend {
+        &self.backend
+    }
+
+    /// Writes the fetched chain state to the cache path, if one was set.
+    pub fn flush_cache(&self) {
+        self.backend.flush_cache();
+    }
+
     pub fn evm_cloned(&self) -> Evm<'_, (), WrapDatabaseRef<ForkedDatabase>> {
         Evm::builder()
-            .with_spec_id(SHANGHAI)
-            .with_ref_db(self.db().clone())
+            .with_spec_id(self.spec_id())
+            .with_ref_db(self.db().detached())
             .build()
     }
 
+    /// Runs `f` for every input in parallel on the rayon thread pool and
+    /// returns the results in input order.
+    ///
+    /// Each input gets its own `Evm` over a clone of the current fork state,
+    /// including the owner and simulator, with the current block and tx env.
+    /// State changes made by `f` are discarded, and not recorded in the
+    /// commit log.
+    ///
+    /// The clones share one `SharedBackend`, so an account or slot fetched by
+    /// one thread lands in the shared `BlockchainDb` cache (behind `RwLock`s)
+    /// and is visible to the others, and concurrent misses on the same key
+    /// are deduplicated by the `BackendHandler`. Rayon workers are not tokio
+    /// threads, where `block_in_place` doesn't apply, so the clones use
+    /// `BlockingMode::Block`.
+    pub fn simulate_many<F>(&self, inputs: &[u128], f: F) -> Vec<u128>
+    where
+        F: Fn(&mut Evm<'_, (), WrapDatabaseRef<ForkedDatabase>>, u128) -> u128 + Sync,
+    {
+        let spec_id = self.spec_id();
+        let cfg_env = self.evm.cfg().clone();
+        let block_env = self.evm.block().clone();
+        let tx_env = self.evm.tx().clone();
+
+        // `self` is not `Sync`, clone the databases before fanning out.
+        let dbs: Vec<ForkedDatabase> = inputs
+            .iter()
+            .map(|_| {
+                let mut db = self.evm.db().0.detached();
+                let cache_db = db.database_mut();
+                cache_db.db = cache_db.db.with_blocking_mode(BlockingMode::Block);
+                db
+            })
+            .collect();
+
+        dbs.into_par_iter()
+            .zip(inputs.par_iter())
+            .map(|(db, input)| {
+                let mut evm = Evm::builder()
+                    .with_spec_id(spec_id)
+                    .with_ref_db(db)
+                    .modify_cfg_env(|cfg| *cfg = cfg_env.clone())
+                    .modify_block_env(|block| *block = block_env.clone())
+                    .modify_tx_env(|tx| *tx = tx_env.clone())
+                    .build();
+
+                f(&mut evm, *input)
+            })
+            .collect()
+    }
+
+    /// A new EVM on the same `SharedBackend` and fetched state, with its own
+    /// local state set up like after `reset`: the owner balances and the
+    /// simulator at the same addresses, nothing else this EVM committed.
+    ///
+    /// Fetches of either EVM land in the shared cache and serve the other.
+    /// `blocking_mode` is how the new EVM waits on the backend, see
+    /// `pool::EvmPool`.
+    pub fn sibling<'b>(&self, blocking_mode: BlockingMode) -> Result<EVM<'b>> {
+        let backend = self.backend.with_blocking_mode(blocking_mode);
+        let fork = ForkedDatabase::new(backend.clone(), self.fork.inner().clone());
+
+        let cfg_env = self.evm.cfg().clone();
+        let block_env = self.evm.block().clone();
+        let tx_env = self.evm.tx().clone();
+        let evm = Evm::builder()
+            .with_spec_id(self.spec_id())
+            .with_ref_db(fork.clone())
+            .modify_cfg_env(|cfg| *cfg = cfg_env)
+            .modify_block_env(|block| *block = block_env)
+            .modify_tx_env(|tx| *tx = tx_env)
+            .build();
+
+        let mut sibling = EVM {
+            backend,
+            fork,
+            evm,
+            weth: self.weth,
+            block_id: self.block_id,
+            block_number: self.block_number,
+            owner: self.owner,
+            owner_balance: self.owner_balance,
+            owner_tokens: self.owner_tokens.clone(),
+            simulator: self.simulator,
+            simulator_bytecode: self.simulator_bytecode.clone(),
+            read_only: self.read_only,
+            snapshots: HashMap::new(),
+            balance_slots: self.balance_slots.clone(),
+            block_overrides: self.block_overrides.clone(),
+            fixed_spec_id: self.fixed_spec_id,
+            token_metadata: self.token_metadata.clone(),
+            pool_tokens: self.pool_tokens.clone(),
+            chain_spec: self.chain_spec.clone(),
+        };
+        sibling.reset()?;
+
+        Ok(sibling)
+    }
+
+    pub(crate) fn into_parts(self) -> EvmParts {
+        let (db, env) = self.evm.into_db_and_env_with_handler_cfg();
+
+        EvmParts {
+            backend: self.backend,
+            fork: self.fork,
+            db: db.0,
+            env,
+            weth: self.weth,
+            block_id: self.block_id,
+            block_number: self.block_number,
+            owner: self.owner,
+            owner_balance: self.owner_balance,
+            owner_tokens: self.owner_tokens,
+            simulator: self.simulator,
+            simulator_bytecode: self.simulator_bytecode,
+            read_only: self.read_only,
+            snapshots: self.snapshots,
+            balance_slots: self.balance_slots,
+            block_overrides: self.block_overrides,
+            fixed_spec_id: self.fixed_spec_id,
+            token_metadata: self.token_metadata,
+            pool_tokens: self.pool_tokens,
+            chain_spec: self.chain_spec,
+        }
+    }
+
+    pub(crate) fn from_parts(parts: EvmParts) -> Self {
+        let evm = Evm::builder()
+            .with_ref_db(parts.db)
+            .with_env_with_handler_cfg(parts.env)
+            .build();
+
+        EVM {
+            backend: parts.backend,
+            fork: parts.fork,
+            evm,
+            weth: parts.weth,
+            block_id: parts.block_id,
+            block_number: parts.block_number,
+            owner: parts.owner,
+            owner_balance: parts.owner_balance,
+            owner_tokens: parts.owner_tokens,
+            simulator: parts.simulator,
+            simulator_bytecode: parts.simulator_bytecode,
+            read_only: parts.read_only,
+            snapshots: parts.snapshots,
+            balance_slots: parts.balance_slots,
+            block_overrides: parts.block_overrides,
+            fixed_spec_id: parts.fixed_spec_id,
+            token_metadata: parts.token_metadata,
+            pool_tokens: parts.pool_tokens,
+            chain_spec: parts.chain_spec,
+        }
+    }
+
     pub fn weth(&self) -> Address {
         self.weth
     }
@@ -105,6 +688,10 @@ impl<'a> EVM<'a> {
         self.block_number
     }
 
+    pub fn block_id(&self) -> BlockId {
+        self.block_id
+    }
+
     pub fn owner(&self) -> Address {
         self.owner
     }
@@ -113,27 +700,623 @@ impl<'a> EVM<'a> {
         self.simulator
     }
 
+    /// Tokens of `pool` read so far by `UniswapV3PoolContract::pool_tokens`.
+    pub(crate) fn pool_tokens_cache(&mut self) -> &mut HashMap<Address, (Address, Address)> {
+        &mut self.pool_tokens
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Points the simulator at a helper contract already on the fork, e.g. one
+    /// deployed on chain, instead of the deployed bytecode. `repin` and
+    /// `reset` leave its code as the fork has it.
+    pub fn set_simulator_address(&mut self, address: Address) {
+        self.simulator = address;
+        self.simulator_bytecode = None;
+    }
+
+    /// Returns the simulator address, or an error if the EVM was built
+    /// read-only.
+    pub fn require_simulator(&self) -> Result<Address> {
+        if self.read_only {
+            return Err(anyhow!("simulator is not deployed on a read-only EVM"));
+        }
+        Ok(self.simulator)
+    }
+
+    pub fn spec_id(&self) -> SpecId {
+        self.evm.spec_id()
+    }
+
+    /// Switches the hardfork rules used for execution. The spec id then stays
+    /// fixed when the block changes, see `reset_spec_id`.
+    ///
+    /// Transient storage (`TLOAD`/`TSTORE`) is only available from `CANCUN`
+    /// onwards. revm clears it when each transaction is finalized, so values
+    /// written with `TSTORE` never leak into the next `transact_commit`.
+    pub fn set_spec_id(&mut self, spec_id: SpecId) {
+        self.fixed_spec_id = Some(spec_id);
+        self.evm.modify_spec_id(spec_id);
+    }
+
+    /// Goes back to the mainnet hardfork of the pinned block.
+    pub fn reset_spec_id(&mut self) {
+        self.fixed_spec_id = None;
+        self.update_spec_id();
+    }
+
+    fn update_spec_id(&mut self) {
+        let spec_id = self
+            .fixed_spec_id
+            .unwrap_or_else(|| match &self.chain_spec {
+                Some(chain_spec) => spec_id_for_chain(
+                    chain_spec,
+                    self.block_number,
+                    self.evm.block().timestamp.saturating_to(),
+                ),
+                None => spec_id_from_block_number(self.block_number),
+            });
+        if self.evm.spec_id() != spec_id {
+            self.evm.modify_spec_id(spec_id);
+        }
+    }
+
+    /// Sets the gas fields used by all following transactions.
+    ///
+    /// Without a fee the base fee check is disabled, so gas-free calls still
+    /// go through on blocks with a non-zero `basefee`. Fails if a priority fee
+    /// is set without a max fee.
+    pub fn set_tx_options(&mut self, tx_options: &TxOptions) -> Result<()> {
+        apply_tx_options(&mut self.evm.context.evm.env, tx_options)
+    }
+
     pub fn set_block_number(&mut self, block_number: u64) {
-        if let Err(e) = self.backend.set_pinned_block(block_number) {
+        self.set_block(block_number);
+    }
+
+    /// Pins the backend to `block`, which can be a tag such as `pending`.
+    ///
+    /// Already fetched state is kept and still served for the new block
+    /// unless the EVM was built with `EVMBuilder::prune_on_repin`, see
+    /// `clear_fetched_state` and `repin` to drop it.
+    pub fn set_block(&mut self, block: impl Into<BlockId>) {
+        let block_id = block.into();
+        if let Err(e) = self.backend.set_pinned_block(block_id) {
             error!("failed to set block. error={e:?}");
         }
+        self.block_id = block_id;
+        if let Some(block_number) = block_id.as_u64() {
+            self.block_number = block_number;
+        }
+        self.set_block_env();
+    }
+
+    /// Drops the accounts and storage fetched so far, so they are fetched
+    /// again at the pinned block. Committed changes are kept.
+    pub fn clear_fetched_state(&mut self) -> Result<()> {
+        self.backend
+            .clear_cache()
+            .map_err(|e| anyhow!("failed to clear fetched state. error={:?}", e))
+    }
+
+    /// Starts or stops recording every write committed to the fork, see
+    /// `commit_log`. Off by default, it costs an account lookup per commit.
+    pub fn record_commits(&mut self, enabled: bool) {
+        self.evm.db().0.record_commits(enabled);
+    }
+
+    /// Storage, balance and nonce writes committed since recording started,
+    /// or since the last `reset` or `repin`, in commit order. Writes undone by
+    /// `revert` are dropped. Empty if recording is off.
+    pub fn commit_log(&self) -> Vec<StateWrite> {
+        self.fork.commit_log()
+    }
+
+    /// Drops every committed change and snapshot, so the next simulation
+    /// starts from the forked block state again. Unlike `repin`, the state
+    /// fetched so far is kept and nothing is fetched again.
+    ///
+    /// The owner balances and the simulator contract are set up again at the
+    /// same addresses. The block env is left as is.
+    pub fn reset(&mut self) -> Result<()> {
+        self.evm.db_mut().0.clear_local();
+        self.snapshots.clear();
+
+        if !self.read_only {
+            self.setup_owner(self.owner, self.owner_balance);
+            if self.simulator_bytecode.is_some() {
+                self.deploy_simulator(Some(self.simulator))?;
+            }
+            self.refund_owner_tokens()?;
+        }
+
+        Ok(())
+    }
+
+    /// Pins the EVM to `block_number` and drops all fetched and modified
+    /// state, so the next calls see the chain exactly as of that block.
+    ///
+    /// The owner balances and the simulator contract are set up again at the
+    /// same addresses.
+    pub fn repin(&mut self, block_number: u64) -> Result<()> {
+        self.evm
+            .db_mut()
+            .0
+            .repin(block_number)
+            .map_err(|e| anyhow!("failed to repin. block_number={}, error={}", block_number, e))?;
+
+        self.block_id = block_number.into();
         self.block_number = block_number;
         self.set_block_env();
+
+        if !self.read_only {
+            self.setup_owner(self.owner, self.owner_balance);
+            if self.simulator_bytecode.is_some() {
+                self.deploy_simulator(Some(self.simulator))?;
+            }
+            self.refund_owner_tokens()?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes a snapshot of the current state and block env.
+    ///
+    /// Every snapshot clones the modified state and all state fetched so far,
+    /// so memory grows with the size of the warm fork for every snapshot that
+    /// is kept alive. Snapshots stack: they can be taken on top of each other
+    /// and reverted in reverse order for nested simulations.
+    pub fn snapshot(&mut self) -> SnapshotId {
+        let id = self.evm.db().0.insert_state_snapshot();
+        self.snapshots
+            .insert(id, (self.block_number, self.evm.block().clone()));
+        id
+    }
+
+    /// Restores the state and block env captured by `snapshot` and discards
+    /// the snapshot. The commit log is rolled back to the snapshot too.
+    pub fn revert(&mut self, id: SnapshotId) -> Result<()> {
+        let (block_number, block_env) = self
+            .snapshots
+            .remove(&id)
+            .ok_or_else(|| anyhow!("unknown snapshot. id={}", id))?;
+
+        if !self
+            .evm
+            .db_mut()
+            .0
+            .revert_state_snapshot(id, RevertStateSnapshotAction::RevertRemove)
+        {
+            return Err(anyhow!("failed to revert snapshot. id={}", id));
+        }
+
+        if block_number != self.block_number {
+            self.set_block_number(block_number);
+        }
+        *self.evm.block_mut() = block_env;
+
+        Ok(())
+    }
+
+    /// Drops a snapshot taken with `snapshot`, keeping the current state.
+    pub fn discard_snapshot(&mut self, id: SnapshotId) -> Result<()> {
+        self.snapshots
+            .remove(&id)
+            .ok_or_else(|| anyhow!("unknown snapshot. id={}", id))?;
+        self.evm.db().0.state_snapshots().lock().remove_at(id);
+
+        Ok(())
+    }
+
+    /// Sets manual block env values, kept across `set_block_number` and
+    /// `repin`. Fields left as `None` come from the pinned block's header.
+    pub fn set_block_overrides(&mut self, overrides: BlockOverrides) {
+        self.block_overrides = overrides;
+        self.set_block_env();
     }
 
+    pub fn block_overrides(&self) -> &BlockOverrides {
+        &self.block_overrides
+    }
+
+    /// Fills the block env from the pinned block's header and applies the
+    /// block overrides on top. If the header can't be fetched, only the block
+    /// number and the overrides are set. The spec id follows the block unless
+    /// it was set with `set_spec_id`.
+    ///
+    /// For `pending`, the header's base fee is the node's estimate for the
+    /// next block, and the block number is taken from the header.
     pub fn set_block_env(&mut self) {
+        let header = match self.backend.get_block_header(self.block_id) {
+            Ok(header) => Some(header),
+            Err(e) => {
+                error!("failed to get block header. block_id={:?}, error={e:?}", self.block_id);
+                None
+            }
+        };
+
+        if let Some(header) = &header {
+            self.block_number = header.number;
+        }
+
+        let overrides = self.block_overrides.clone();
         let block_env = self.evm.block_mut();
         block_env.number = U256::from(self.block_number);
+
+        if let Some(header) = header {
+            block_env.timestamp = U256::from(header.timestamp);
+            block_env.basefee = U256::from(header.base_fee_per_gas.unwrap_or_default());
+            block_env.gas_limit = U256::from(header.gas_limit);
+            block_env.coinbase = header.beneficiary;
+            block_env.difficulty = header.difficulty;
+            // Post-merge, `mixHash` carries the beacon chain randomness.
+            if let Some(mix_hash) = header.mix_hash {
+                block_env.prevrandao = Some(mix_hash);
+            }
+        }
+
+        if let Some(timestamp) = overrides.timestamp {
+            block_env.timestamp = U256::from(timestamp);
+        }
+        if let Some(basefee) = overrides.basefee {
+            block_env.basefee = U256::from(basefee);
+        }
+        if let Some(gas_limit) = overrides.gas_limit {
+            block_env.gas_limit = U256::from(gas_limit);
+        }
+        if let Some(coinbase) = overrides.coinbase {
+            block_env.coinbase = coinbase;
+        }
+        if let Some(difficulty) = overrides.difficulty {
+            block_env.difficulty = difficulty;
+        }
+        if let Some(prevrandao) = overrides.prevrandao {
+            block_env.prevrandao = Some(prevrandao);
+        }
+
+        self.update_spec_id();
+    }
+
+    /// Replays `txs` in order on the current state, committing each one, to
+    /// rebuild the state in the middle of a block.
+    ///
+    /// A reverted or halted transaction is still committed and reported in
+    /// its `SimOutcome`. The tx env is restored afterwards.
+    pub fn apply_transactions(&mut self, txs: &[AnyRpcTransaction]) -> Result<Vec<SimOutcome>> {
+        let tx_env = self.evm.tx().clone();
+
+        let mut outcomes = Vec::with_capacity(txs.len());
+
+        for (index, tx) in txs.iter().enumerate() {
+            fill_tx_env(self.evm.tx_mut(), tx.from, &tx.inner);
+
+            match self.evm.transact_commit() {
+                Ok(result) => outcomes.push(result.into()),
+                Err(e) => {
+                    *self.evm.tx_mut() = tx_env;
+                    return Err(anyhow!(
+                        "failed to apply transaction. index={}, from={}, error={:?}",
+                        index,
+                        tx.from,
+                        e
+                    ));
+                }
+            }
+        }
+
+        *self.evm.tx_mut() = tx_env;
+
+        Ok(outcomes)
+    }
+
+    /// Calls `to` with arbitrary calldata, e.g. a router or aggregator swap
+    /// captured from the mempool.
+    ///
+    /// State changes are kept only if `commit` is set. A revert or halt is
+    /// reported in the `SimOutcome`, not as an error.
+    pub fn call(
+        &mut self,
+        to: Address,
+        data: Bytes,
+        caller: Address,
+        value: U256,
+        commit: bool,
+    ) -> Result<SimOutcome> {
+        let result = self.transact_call(caller, to, data, value, commit)?;
+
+        if let ExecutionResult::Revert { output, .. } = &result {
+            warn!("call reverted. to={}, reason={}", to, revert_reason_or_output(output));
+        }
+
+        Ok(result.into())
+    }
+
+    /// Runs the current tx env, committing the state changes only if
+    /// `commit` is set. A preview discards the `ResultAndState`.
+    pub(crate) fn transact_tx_env(&mut self, commit: bool) -> Result<ExecutionResult> {
+        if commit {
+            Ok(self.evm.transact_commit()?)
+        } else {
+            Ok(self.evm.transact()?.result)
+        }
+    }
+
+    /// Calls `to` from `caller`, committing the state changes only if
+    /// `commit` is set.
+    fn transact_call(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Bytes,
+        value: U256,
+        commit: bool,
+    ) -> Result<ExecutionResult> {
+        let tx_env = self.evm.tx_mut();
+        tx_env.transact_to = TransactTo::Call(to);
+        tx_env.data = data;
+        tx_env.caller = caller;
+        tx_env.value = value;
+
+        self.transact_tx_env(commit)
+    }
+
+    /// Calls `to` from `caller` and returns the output, committing the state
+    /// changes only if `commit` is set. Unlike `call`, a revert or halt is an
+    /// error, named after `function`.
+    pub(crate) fn checked_call(
+        &mut self,
+        function: &str,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        commit: bool,
+    ) -> Result<Bytes> {
+        let result = self.transact_call(caller, to, data.into(), value, commit)?;
+        call_output(result, function, to)
+    }
+
+    /// Calls `to` from the owner without committing, see `checked_call`.
+    pub(crate) fn static_call(&mut self, to: Address, data: Vec<u8>) -> Result<Bytes> {
+        self.checked_call("call", self.owner, to, data, U256::ZERO, false)
+    }
+
+    /// Calls `to` from the owner with `value` and commits it, see
+    /// `checked_call`.
+    pub(crate) fn commit_call(
+        &mut self,
+        function: &str,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<Bytes> {
+        self.checked_call(function, self.owner, to, data, value, true)
+    }
+
+    /// Runs the current tx env with a call tracer attached, without
+    /// committing, and returns the outcome with its geth-style call trace.
+    ///
+    /// The inspector slows execution down, so it only runs here, on a clone
+    /// of the fork state. The root frame's gas covers the whole transaction,
+    /// like `debug_traceCall`.
+    pub fn transact_traced(&mut self) -> Result<(SimOutcome, CallTrace)> {
+        let mut evm = Evm::builder()
+            .with_spec_id(self.spec_id())
+            .with_ref_db(self.evm.db().0.detached())
+            .with_external_context(CallTracer::default())
+            .modify_cfg_env(|cfg| *cfg = self.evm.cfg().clone())
+            .modify_block_env(|block| *block = self.evm.block().clone())
+            .modify_tx_env(|tx| *tx = self.evm.tx().clone())
+            .append_handler_register(inspector_handle_register)
+            .build();
+
+        let result = evm.transact()?.result;
+        let gas_limit = evm.tx().gas_limit;
+        let tracer = std::mem::take(&mut evm.context.external);
+
+        let mut trace = tracer
+            .into_trace()
+            .ok_or_else(|| anyhow!("no call frame recorded"))?;
+        trace.gas = gas_limit;
+        trace.gas_used = result.gas_used();
+
+        Ok((result.into(), trace))
+    }
+
+    /// EIP-2930 access list `tx` would use on the current state, like
+    /// `eth_createAccessList`.
+    ///
+    /// Runs `tx` without committing and lists every account and storage slot
+    /// it touched. The caller, the coinbase and precompiles are warm anyway
+    /// and left out, as is the call target unless it has slots.
+    pub fn access_list_for(&mut self, tx: TxEnv) -> Result<AccessList> {
+        let caller = tx.caller;
+        let target = match tx.transact_to {
+            TransactTo::Call(address) => Some(address),
+            TransactTo::Create => None,
+        };
+
+        let tx_env = std::mem::replace(self.evm.tx_mut(), tx);
+        let result = self.evm.transact();
+        *self.evm.tx_mut() = tx_env;
+
+        let state = result?.state;
+        let coinbase = self.evm.block().coinbase;
+        let precompiles = &self.evm.context.evm.precompiles;
+
+        let mut items: Vec<AccessListItem> = state
+            .into_iter()
+            .filter(|(address, account)| {
+                *address != caller
+                    && *address != coinbase
+                    && !precompiles.contains(address)
+                    && (Some(*address) != target || !account.storage.is_empty())
+            })
+            .map(|(address, account)| {
+                let mut storage_keys: Vec<B256> = account
+                    .storage
+                    .keys()
+                    .map(|slot| B256::from(*slot))
+                    .collect();
+                storage_keys.sort();
+
+                AccessListItem { address, storage_keys }
+            })
+            .collect();
+        items.sort_by_key(|item| item.address);
+
+        Ok(AccessList(items))
     }
 
+    /// Commits `frontrun`, `victim` and `backrun` in order and measures the
+    /// balance changes of the frontrun caller.
+    ///
+    /// Runs on a snapshot that is reverted afterwards, so the EVM can be
+    /// reused. A reverted leg is reported in its `SimOutcome`, not as an
+    /// error.
+    pub fn simulate_sandwich(
+        &mut self,
+        frontrun: TxEnv,
+        victim: AnyRpcTransaction,
+        backrun: TxEnv,
+    ) -> Result<SandwichResult> {
+        let attacker = frontrun.caller;
+        let weth = self.weth;
+        let tx_env = self.evm.tx().clone();
+
+        let snapshot_id = self.snapshot();
+
+        let result = (|| {
+            let weth_before = self.get_token_balance(weth, attacker)?.0;
+            let eth_before = self.get_eth_balance(attacker);
+
+            *self.evm.tx_mut() = frontrun;
+            let frontrun: SimOutcome = self.evm.transact_commit()?.into();
+
+            let victim = self
+                .apply_transactions(std::slice::from_ref(&victim))?
+                .remove(0);
+
+            *self.evm.tx_mut() = backrun;
+            let backrun: SimOutcome = self.evm.transact_commit()?.into();
+
+            let weth_after = self.get_token_balance(weth, attacker)?.0;
+            let eth_after = self.get_eth_balance(attacker);
+
+            Ok::<_, anyhow::Error>(SandwichResult {
+                victim_success: victim.success,
+                frontrun,
+                victim,
+                backrun,
+                weth_delta: I256::try_from(weth_after)? - I256::try_from(weth_before)?,
+                eth_delta: I256::try_from(eth_after)? - I256::try_from(eth_before)?,
+            })
+        })();
+
+        *self.evm.tx_mut() = tx_env;
+        self.revert(snapshot_id)?;
+
+        result
+    }
+
+    /// Runs raw signed transactions in order on top of the pinned block with
+    /// `coinbase` as the block's coinbase, like Flashbots' `eth_callBundle`.
+    ///
+    /// Each transaction is committed on a snapshot that is reverted
+    /// afterwards. The miner payment is the coinbase's balance change, so it
+    /// covers priority fees and direct transfers. A reverted transaction is
+    /// reported in its result, a transaction that can't be decoded or run
+    /// fails the bundle.
+    pub fn call_bundle(&mut self, txs: &[Bytes], coinbase: Address) -> Result<BundleSimResult> {
+        let tx_env = self.evm.tx().clone();
+
+        let snapshot_id = self.snapshot();
+        self.evm.block_mut().coinbase = coinbase;
+
+        let result = (|| {
+            let basefee = self.evm.block().basefee;
+            let mut bundle = BundleSimResult::default();
+
+            for (index, raw) in txs.iter().enumerate() {
+                let tx = TxEnvelope::decode_2718(&mut raw.as_ref()).map_err(|e| {
+                    anyhow!("failed to decode bundle transaction. index={}, error={:?}", index, e)
+                })?;
+                let from = tx.recover_signer().map_err(|e| {
+                    anyhow!("failed to recover bundle signer. index={}, error={:?}", index, e)
+                })?;
+
+                fill_tx_env(self.evm.tx_mut(), from, &tx);
+                let gas_price = tx.effective_gas_price(Some(basefee.saturating_to::<u64>()));
+
+                let coinbase_before = self.coinbase_balance(coinbase)?;
+                let outcome: SimOutcome = self.evm.transact_commit().map_err(|e| {
+                    anyhow!(
+                        "failed to run bundle transaction. index={}, from={}, error={:?}",
+                        index,
+                        from,
+                        e
+                    )
+                })?;
+                let coinbase_after = self.coinbase_balance(coinbase)?;
+
+                let coinbase_diff = coinbase_after.saturating_sub(coinbase_before);
+                let gas_fees =
+                    U256::from(outcome.gas_used) * U256::from(gas_price).saturating_sub(basefee);
+                let eth_sent_to_coinbase = coinbase_diff.saturating_sub(gas_fees);
+
+                bundle.total_gas_used += outcome.gas_used;
+                bundle.coinbase_diff += coinbase_diff;
+                bundle.gas_fees += gas_fees;
+                bundle.eth_sent_to_coinbase += eth_sent_to_coinbase;
+                bundle.results.push(BundleTxResult {
+                    tx_hash: *tx.tx_hash(),
+                    from,
+                    to: tx.to(),
+                    outcome,
+                    gas_price: U256::from(gas_price),
+                    gas_fees,
+                    coinbase_diff,
+                    eth_sent_to_coinbase,
+                });
+            }
+
+            if bundle.total_gas_used > 0 {
+                bundle.bundle_gas_price = bundle.coinbase_diff / U256::from(bundle.total_gas_used);
+            }
+
+            Ok::<_, anyhow::Error>(bundle)
+        })();
+
+        *self.evm.tx_mut() = tx_env;
+        self.revert(snapshot_id)?;
+
+        result
+    }
+
+    /// Balance of `coinbase`, zero if the account doesn't exist yet.
+    fn coinbase_balance(&mut self, coinbase: Address) -> Result<U256> {
+        Ok(self
+            .basic(coinbase)?
+            .map(|account| account.balance)
+            .unwrap_or_default())
+    }
+
+    /// Inserts `bytecode_str` as runtime code and checks that the code read
+    /// back from the fork has the expected hash.
     pub fn deploy_contract(
         &mut self,
         contract_addr: Option<Address>,
         bytecode_str: &str,
-    ) -> Address {
-        let bytes = bytecode_str.parse().unwrap();
-        let code = Bytecode::new_legacy(bytes);
-        let account = AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code);
+    ) -> Result<Address> {
+        let code = parse_bytecode(bytecode_str)?;
+        if code.is_empty() {
+            return Err(anyhow!("failed to deploy contract. error=empty bytecode"));
+        }
+
+        let code_hash = code.hash_slow();
+        let account = AccountInfo::new(U256::ZERO, 0, code_hash, code);
 
         let addy = match contract_addr {
             Some(addy) => addy,
@@ -143,18 +1326,116 @@ impl<'a> EVM<'a> {
         let cache_db_mut = self.evm.db_mut().0.database_mut();
         cache_db_mut.insert_account_info(addy, account);
 
-        addy
+        self.check_code_hash(addy, code_hash)?;
+
+        Ok(addy)
+    }
+
+    /// Replaces the runtime code of `address`, keeping its balance, nonce and
+    /// storage. Same as an `eth_call` code override.
+    pub fn override_code(&mut self, address: Address, bytecode_str: &str) -> Result<()> {
+        let code = parse_bytecode(bytecode_str)?;
+        let code_hash = code.hash_slow();
+
+        let mut account = self.basic(address)?.unwrap_or_default();
+        account.code_hash = code_hash;
+        account.code = Some(code);
+
+        let cache_db_mut = self.evm.db_mut().0.database_mut();
+        cache_db_mut.insert_account_info(address, account);
+
+        self.check_code_hash(address, code_hash)
+    }
+
+    /// Sets a single storage slot of `address`, the other slots are kept.
+    /// Same as an `eth_call` `stateDiff` override.
+    pub fn override_storage(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.set_storage(address, slot, value)
+    }
+
+    fn check_code_hash(&mut self, address: Address, code_hash: B256) -> Result<()> {
+        let deployed_hash = keccak256(self.code_at(address)?);
+        if deployed_hash != code_hash {
+            return Err(anyhow!(
+                "deployed code hash mismatch. address={}, expected={}, got={}",
+                address,
+                code_hash,
+                deployed_hash
+            ));
+        }
+
+        Ok(())
     }
 
-    pub fn deploy_simulator(&mut self, contract_addr: Option<Address>) -> Address {
-        self.deploy_contract(contract_addr, SIMULATOR_BYTECODE)
+    /// Deploys the simulator, the bundled contract unless the EVM was built
+    /// with a custom bytecode, and runs an empty call against it.
+    ///
+    /// The call catches bytecode that can't run (a halt) and creation code
+    /// pasted in place of runtime code, which returns the runtime code
+    /// instead of executing it.
+    pub fn deploy_simulator(&mut self, contract_addr: Option<Address>) -> Result<Address> {
+        let bytecode = self
+            .simulator_bytecode
+            .clone()
+            .unwrap_or_else(|| SIMULATOR_BYTECODE.to_string());
+        let simulator = self.deploy_contract(contract_addr, &bytecode)?;
+
+        let tx_env = self.evm.tx_mut();
+        tx_env.transact_to = TransactTo::Call(simulator);
+        tx_env.data = Bytes::new();
+        tx_env.caller = self.owner;
+        tx_env.value = U256::ZERO;
+
+        let result = self
+            .evm
+            .transact()
+            .map_err(|e| anyhow!("failed to call simulator. error={:?}", e))?
+            .result;
+
+        match result {
+            ExecutionResult::Halt { reason, .. } => Err(anyhow!(
+                "simulator code halted on an empty call. address={}, reason={:?}",
+                simulator,
+                reason
+            )),
+            ExecutionResult::Success { output: Output::Call(value), .. }
+                if !value.is_empty() && self.code_at(simulator)?.ends_with(&value) =>
+            {
+                Err(anyhow!("simulator bytecode is creation code. address={}", simulator))
+            }
+            _ => Ok(simulator),
+        }
     }
 
     pub fn setup_owner(&mut self, owner: Address, balance: U256) {
         self.owner = owner;
+        self.owner_balance = balance;
         self.set_eth_balance(owner, balance);
     }
 
+    /// Sets the owner's balance of `token` to `amount` through
+    /// `set_token_balance`, so any token with a plain balance mapping can fund
+    /// a simulation without wrapping or transfers. Kept across `repin` and
+    /// `reset`.
+    pub fn fund_owner_token(&mut self, token: Address, amount: U256) -> Result<()> {
+        self.set_token_balance(token, self.owner, amount)?;
+
+        match self.owner_tokens.iter_mut().find(|(t, _)| *t == token) {
+            Some((_, balance)) => *balance = amount,
+            None => self.owner_tokens.push((token, amount)),
+        }
+
+        Ok(())
+    }
+
+    fn refund_owner_tokens(&mut self) -> Result<()> {
+        for (token, amount) in self.owner_tokens.clone() {
+            self.set_token_balance(token, self.owner, amount)?;
+        }
+
+        Ok(())
+    }
+
     pub fn basic(&mut self, target: Address) -> Result<Option<AccountInfo>> {
         self.evm
             .db_mut()
@@ -163,6 +1444,38 @@ impl<'a> EVM<'a> {
             .map_err(|e| anyhow!("failed to get basic. error={e:?}"))
     }
 
+    /// Returns the runtime bytecode of `target`, empty for EOAs and unknown
+    /// accounts.
+    pub fn code_at(&mut self, target: Address) -> Result<Bytes> {
+        let account = match self.basic(target)? {
+            Some(account) => account,
+            None => return Ok(Bytes::new()),
+        };
+
+        let code = match account.code {
+            Some(code) => code,
+            None if account.code_hash == KECCAK_EMPTY => return Ok(Bytes::new()),
+            None => self
+                .evm
+                .db_mut()
+                .0
+                .code_by_hash(account.code_hash)
+                .map_err(|e| anyhow!("failed to get code. target={}, error={:?}", target, e))?,
+        };
+
+        Ok(code.original_bytes())
+    }
+
+    pub fn has_code(&mut self, target: Address) -> bool {
+        match self.code_at(target) {
+            Ok(code) => !code.is_empty(),
+            Err(e) => {
+                error!("failed to get code. target={}, error={:?}", target, e);
+                false
+            }
+        }
+    }
+
     pub fn get_eth_balance(&mut self, target: Address) -> U256 {
         match self.basic(target) {
             Ok(basic) => match basic {
@@ -196,22 +1509,18 @@ impl<'a> EVM<'a> {
     }
 
     pub fn wrap_eth(&mut self, amount: U256) -> Result<()> {
-        let encoded = abi::IWETH::depositCall::new(()).abi_encode();
-
-        let tx_env = self.evm.tx_mut();
-        tx_env.transact_to = TransactTo::Call(self.weth);
-        tx_env.data = encoded.into();
-        tx_env.caller = self.owner;
-        tx_env.value = amount;
-
-        let result = self.evm.transact_commit()?;
+        let result = self.transact_wrap_eth(amount, true)?;
 
         match result {
             ExecutionResult::Halt { reason, gas_used } => {
                 error!("wrap_weth halted. gas_used={}, reason={:?}", gas_used, reason);
             }
             ExecutionResult::Revert { gas_used, output } => {
-                error!("wrap_weth reverted. gas_used={}, output={}", gas_used, output);
+                error!(
+                    "wrap_weth reverted. gas_used={}, reason={}",
+                    gas_used,
+                    revert_reason_or_output(&output)
+                );
             }
             _ => {}
         }
@@ -219,6 +1528,57 @@ impl<'a> EVM<'a> {
         Ok(())
     }
 
+    /// Same as `wrap_eth` without committing, the outcome tells whether the
+    /// deposit would succeed.
+    pub fn wrap_eth_preview(&mut self, amount: U256) -> Result<SimOutcome> {
+        Ok(self.transact_wrap_eth(amount, false)?.into())
+    }
+
+    fn transact_wrap_eth(&mut self, amount: U256, commit: bool) -> Result<ExecutionResult> {
+        let encoded = abi::IWETH::depositCall::new(()).abi_encode();
+        self.transact_call(self.owner, self.weth, encoded.into(), amount, commit)
+    }
+
+    /// Burns `amount` WETH of the owner for ETH.
+    ///
+    /// Fails if the call reverts or halts, or if the balances didn't move by
+    /// `amount`: WETH down exactly, ETH up by `amount` less the gas paid.
+    pub fn unwrap_eth(&mut self, amount: U256) -> Result<()> {
+        let owner = self.owner;
+        let weth_before = self.get_token_balance(self.weth, owner)?.0;
+        let eth_before = self.get_eth_balance(owner);
+
+        let encoded = abi::IWETH::withdrawCall::new((amount,)).abi_encode();
+        let result = self.transact_call(owner, self.weth, encoded.into(), U256::ZERO, true)?;
+        let gas_used = result.gas_used();
+        call_output(result, "unwrap_eth", self.weth)?;
+
+        let weth_after = self.get_token_balance(self.weth, owner)?.0;
+        let eth_after = self.get_eth_balance(owner);
+
+        if weth_before.checked_sub(weth_after) != Some(amount) {
+            return Err(anyhow!(
+                "unwrap_eth WETH balance mismatch. amount={}, before={}, after={}",
+                amount,
+                weth_before,
+                weth_after
+            ));
+        }
+
+        // The gas price is the max fee, so this is an upper bound on the gas paid.
+        let max_gas_cost = U256::from(gas_used) * self.evm.tx().gas_price;
+        if eth_after > eth_before + amount || eth_after + max_gas_cost < eth_before + amount {
+            return Err(anyhow!(
+                "unwrap_eth ETH balance mismatch. amount={}, before={}, after={}",
+                amount,
+                eth_before,
+                eth_after
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn transfer_token(
         &mut self,
         token: Address,
@@ -226,22 +1586,18 @@ impl<'a> EVM<'a> {
         to: Address,
         amount: U256,
     ) -> Result<()> {
-        let encoded = abi::IERC20::transferCall::new((to, amount)).abi_encode();
-
-        let tx_env = self.evm.tx_mut();
-        tx_env.transact_to = TransactTo::Call(token);
-        tx_env.data = encoded.into();
-        tx_env.caller = from;
-        tx_env.value = U256::ZERO;
-
-        let result = self.evm.transact_commit()?;
+        let result = self.transact_transfer(token, from, to, amount, true)?;
 
         match result {
             ExecutionResult::Halt { reason, gas_used } => {
                 error!("transfer_token halted. gas_used={}, reason={:?}", gas_used, reason);
             }
             ExecutionResult::Revert { gas_used, output } => {
-                error!("transfer_token reverted. gas_used={}, output={}", gas_used, output);
+                error!(
+                    "transfer_token reverted. gas_used={}, reason={}",
+                    gas_used,
+                    revert_reason_or_output(&output)
+                );
             }
             _ => {}
         }
@@ -249,9 +1605,394 @@ impl<'a> EVM<'a> {
         Ok(())
     }
 
-    pub fn fund_simulator(&mut self, amount: U256) -> Result<()> {
+    /// Same as `transfer_token` without committing, the outcome tells whether
+    /// the transfer would succeed.
+    pub fn transfer_token_preview(
+        &mut self,
+        token: Address,
+        from: Address,
+        to: Address,
+        amount: U256,
+    ) -> Result<SimOutcome> {
+        Ok(self
+            .transact_transfer(token, from, to, amount, false)?
+            .into())
+    }
+
+    fn transact_transfer(
+        &mut self,
+        token: Address,
+        from: Address,
+        to: Address,
+        amount: U256,
+        commit: bool,
+    ) -> Result<ExecutionResult> {
+        let encoded = abi::IERC20::transferCall::new((to, amount)).abi_encode();
+        self.transact_call(from, token, encoded.into(), U256::ZERO, commit)
+    }
+
+    /// Whether `token` takes a cut of transfers, so balances moved by a swap
+    /// don't add up.
+    ///
+    /// Funds a throwaway account with `set_token_balance`, transfers a probe
+    /// amount to another one and compares the amount received with the amount
+    /// sent. Runs on a snapshot that is reverted afterwards. A transfer that
+    /// reverts is an error.
+    pub fn is_fee_on_transfer(&mut self, token: Address) -> Result<bool> {
+        let from = Address::repeat_byte(0xf1);
+        let to = Address::repeat_byte(0xf2);
+        let amount = U256::from(FEE_PROBE_AMOUNT);
+
+        let snapshot_id = self.snapshot();
+
+        let result = (|| {
+            self.set_token_balance(token, from, amount)?;
+            let to_before = self.get_token_balance(token, to)?.0;
+
+            let encoded = abi::IERC20::transferCall::new((to, amount)).abi_encode();
+            let outcome = self.call(token, encoded.into(), from, U256::ZERO, true)?;
+            if !outcome.success {
+                return Err(anyhow!(
+                    "probe transfer failed. token={}, reason={}",
+                    token,
+                    revert_reason_or_output(&outcome.output)
+                ));
+            }
+
+            let sent = amount.saturating_sub(self.get_token_balance(token, from)?.0);
+            let received = self
+                .get_token_balance(token, to)?
+                .0
+                .saturating_sub(to_before);
+
+            Ok::<_, anyhow::Error>((sent, received))
+        })();
+
+        self.revert(snapshot_id)?;
+
+        let (sent, received) = result?;
+
+        Ok(sent.saturating_sub(received) > U256::from(FEE_ROUNDING_TOLERANCE))
+    }
+
+    /// Approves `spender` to pull `amount` of `token` from `owner`.
+    ///
+    /// Unlike `transfer_token`, reverts and halts are returned as errors.
+    pub fn approve_token(
+        &mut self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        amount: U256,
+    ) -> Result<()> {
+        let encoded = abi::IERC20::approveCall::new((spender, amount)).abi_encode();
+        let value = self.checked_call("approve_token", owner, token, encoded, U256::ZERO, true)?;
+
+        // Tokens like USDT don't return a bool.
+        if !value.is_empty()
+            && !abi::IERC20::approveCall::abi_decode_returns(&value, false)?.success
+        {
+            return Err(anyhow!("approve_token returned false. token={}", token));
+        }
+
+        Ok(())
+    }
+
+    /// Has `spender` pull `amount` of `token` from `from` to `to`, the way a
+    /// router spends an approval, and commits it.
+    ///
+    /// Fails if the call reverts, halts or returns false, or if the balances
+    /// didn't move by exactly `amount`, e.g. for fee-on-transfer tokens.
+    pub fn transfer_from(
+        &mut self,
+        token: Address,
+        spender: Address,
+        from: Address,
+        to: Address,
+        amount: U256,
+    ) -> Result<()> {
+        let from_before = self.get_token_balance(token, from)?.0;
+        let to_before = self.get_token_balance(token, to)?.0;
+
+        let encoded = abi::IERC20::transferFromCall::new((from, to, amount)).abi_encode();
+        let value =
+            self.checked_call("transfer_from", spender, token, encoded, U256::ZERO, true)?;
+
+        // Tokens like USDT don't return a bool.
+        if !value.is_empty()
+            && !abi::IERC20::transferFromCall::abi_decode_returns(&value, false)?.success
+        {
+            return Err(anyhow!("transfer_from returned false. token={}", token));
+        }
+
+        // A transfer to self moves nothing.
+        if from == to {
+            return Ok(());
+        }
+
+        let from_after = self.get_token_balance(token, from)?.0;
+        let to_after = self.get_token_balance(token, to)?.0;
+
+        if from_before.checked_sub(from_after) != Some(amount)
+            || to_after.checked_sub(to_before) != Some(amount)
+        {
+            return Err(anyhow!(
+                "transfer_from balance mismatch. token={}, amount={}, from_before={}, \
+                 from_after={}, to_before={}, to_after={}",
+                token,
+                amount,
+                from_before,
+                from_after,
+                to_before,
+                to_after
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Approves `spender` for `value` of `token` with an EIP-2612 permit
+    /// signed by `owner_key`, submitted by `spender` the way a router would,
+    /// and commits it.
+    ///
+    /// The permit is signed over the token's own `DOMAIN_SEPARATOR` and the
+    /// owner's current nonce. Fails if the call reverts or halts, or if the
+    /// allowance isn't `value` afterwards.
+    pub fn permit(
+        &mut self,
+        token: Address,
+        owner_key: &PrivateKeySigner,
+        spender: Address,
+        value: U256,
+        deadline: U256,
+    ) -> Result<()> {
+        let owner = owner_key.address();
+
+        let encoded = abi::IERC2612::noncesCall::new((owner,)).abi_encode();
+        let nonce = self.call_token(token, encoded, "nonces")?;
+        let nonce = abi::IERC2612::noncesCall::abi_decode_returns(&nonce, false)?._0;
+
+        let encoded = abi::IERC2612::DOMAIN_SEPARATORCall::new(()).abi_encode();
+        let domain_separator = self.call_token(token, encoded, "DOMAIN_SEPARATOR")?;
+        let domain_separator =
+            abi::IERC2612::DOMAIN_SEPARATORCall::abi_decode_returns(&domain_separator, false)?._0;
+
+        let permit = abi::IERC2612::Permit { owner, spender, value, nonce, deadline };
+        let digest = keccak256(
+            [&[0x19, 0x01], domain_separator.as_slice(), permit.eip712_hash_struct().as_slice()]
+                .concat(),
+        );
+        let signature = owner_key
+            .sign_hash_sync(&digest)
+            .map_err(|e| anyhow!("failed to sign permit. token={}, error={:?}", token, e))?;
+
+        let encoded = abi::IERC2612::permitCall::new((
+            owner,
+            spender,
+            value,
+            deadline,
+            27 + signature.v() as u8,
+            signature.r().to_be_bytes::<32>().into(),
+            signature.s().to_be_bytes::<32>().into(),
+        ))
+        .abi_encode();
+        self.checked_call("permit", spender, token, encoded, U256::ZERO, true)?;
+
+        let allowance = self.get_allowance(token, owner, spender)?;
+        if allowance != value {
+            return Err(anyhow!(
+                "permit allowance mismatch. token={}, expected={}, allowance={}",
+                token,
+                value,
+                allowance
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn get_allowance(
+        &mut self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+    ) -> Result<U256> {
+        let encoded = abi::IERC20::allowanceCall::new((owner, spender)).abi_encode();
+
+        let tx_env = self.evm.tx_mut();
+        tx_env.transact_to = TransactTo::Call(token);
+        tx_env.data = encoded.into();
+        tx_env.caller = Address::ZERO;
+        tx_env.value = U256::ZERO;
+
+        let ref_tx = self.evm.transact()?;
+
+        let value = match ref_tx.result {
+            ExecutionResult::Success { output: Output::Call(value), .. } => Ok(value),
+            ExecutionResult::Revert { output, .. } => Err(anyhow!(
+                "failed to get allowance. token={}, reason={}",
+                token,
+                revert_reason_or_output(&output)
+            )),
+            _ => Err(anyhow!("failed to get allowance. token={}", token)),
+        }?;
+
+        let result = abi::IERC20::allowanceCall::abi_decode_returns(&value, false)?;
+
+        Ok(result.remaining)
+    }
+
+    /// Decimals of `token`, read once and cached.
+    pub fn token_decimals(&mut self, token: Address) -> Result<u8> {
+        if let Some(decimals) = self.token_metadata.get(&token).and_then(|m| m.decimals) {
+            return Ok(decimals);
+        }
+
+        let encoded = abi::IERC20::decimalsCall::new(()).abi_encode();
+        let value = self.call_token(token, encoded, "decimals")?;
+        let decimals = abi::IERC20::decimalsCall::abi_decode_returns(&value, false)?._0;
+
+        self.token_metadata.entry(token).or_default().decimals = Some(decimals);
+        Ok(decimals)
+    }
+
+    pub fn token_symbol(&mut self, token: Address) -> Result<String> {
+        if let Some(symbol) = self
+            .token_metadata
+            .get(&token)
+            .and_then(|m| m.symbol.clone())
+        {
+            return Ok(symbol);
+        }
+
+        let encoded = abi::IERC20::symbolCall::new(()).abi_encode();
+        let value = self.call_token(token, encoded, "symbol")?;
+        let symbol = decode_string_or_bytes32(&value)?;
+
+        self.token_metadata.entry(token).or_default().symbol = Some(symbol.clone());
+        Ok(symbol)
+    }
+
+    pub fn token_name(&mut self, token: Address) -> Result<String> {
+        if let Some(name) = self.token_metadata.get(&token).and_then(|m| m.name.clone()) {
+            return Ok(name);
+        }
+
+        let encoded = abi::IERC20::nameCall::new(()).abi_encode();
+        let value = self.call_token(token, encoded, "name")?;
+        let name = decode_string_or_bytes32(&value)?;
+
+        self.token_metadata.entry(token).or_default().name = Some(name.clone());
+        Ok(name)
+    }
+
+    fn call_token(&mut self, token: Address, encoded: Vec<u8>, function: &str) -> Result<Bytes> {
+        let tx_env = self.evm.tx_mut();
+        tx_env.transact_to = TransactTo::Call(token);
+        tx_env.data = encoded.into();
+        tx_env.caller = Address::ZERO;
+        tx_env.value = U256::ZERO;
+
+        match self.evm.transact()?.result {
+            ExecutionResult::Success { output: Output::Call(value), .. } => Ok(value),
+            ExecutionResult::Revert { output, .. } => Err(anyhow!(
+                "failed to get token {}. token={}, reason={}",
+                function,
+                token,
+                revert_reason_or_output(&output)
+            )),
+            _ => Err(anyhow!("failed to get token {}. token={}", function, token)),
+        }
+    }
+
+    /// Wraps `amount` ETH of the owner and sends the WETH to the simulator.
+    /// Returns the simulator's WETH balance afterwards.
+    ///
+    /// `wrap_eth` and `transfer_token` only log reverts, so the balance is
+    /// checked instead: it must have grown by exactly `amount`.
+    pub fn fund_simulator(&mut self, amount: U256) -> Result<U256> {
+        let simulator = self.require_simulator()?;
+        let balance_before = self.get_token_balance(self.weth, simulator)?.0;
+
         self.wrap_eth(amount)?;
-        self.transfer_token(self.weth, self.owner, self.simulator, amount)
+        self.transfer_token(self.weth, self.owner, simulator, amount)?;
+
+        let balance_after = self.get_token_balance(self.weth, simulator)?.0;
+        let received = balance_after.saturating_sub(balance_before);
+        if received != amount {
+            return Err(anyhow!(
+                "failed to fund simulator. expected={}, received={}",
+                amount,
+                received
+            ));
+        }
+
+        Ok(balance_after)
+    }
+
+    /// Sets the ERC20 balance of `holder` by writing the token storage
+    /// directly.
+    ///
+    /// The balance mapping slot is found by writing a probe value to candidate
+    /// slots 0 to 30, with both the Solidity and Vyper layouts, until
+    /// `balanceOf` returns it. The slot is cached per token. Tokens that
+    /// derive balances from shares, like stETH, are not supported. The total
+    /// supply is left untouched.
+    pub fn set_token_balance(
+        &mut self,
+        token: Address,
+        holder: Address,
+        amount: U256,
+    ) -> Result<()> {
+        let balance_slot = match self.balance_slots.get(&token) {
+            Some(balance_slot) => *balance_slot,
+            None => {
+                let balance_slot = self.find_balance_slot(token, holder)?;
+                self.balance_slots.insert(token, balance_slot);
+                balance_slot
+            }
+        };
+
+        self.set_storage(token, balance_slot.key(holder), amount)
+    }
+
+    fn find_balance_slot(&mut self, token: Address, holder: Address) -> Result<BalanceSlot> {
+        let probe = U256::from(0xdead_beef_u64);
+
+        for slot in 0..=MAX_BALANCE_SLOT {
+            for vyper in [false, true] {
+                let balance_slot = BalanceSlot { slot: U256::from(slot), vyper };
+                let key = balance_slot.key(holder);
+
+                let original = self
+                    .evm
+                    .db_mut()
+                    .0
+                    .database_mut()
+                    .storage(token, key)
+                    .map_err(|e| anyhow!("failed to get storage. error={e:?}"))?;
+
+                self.set_storage(token, key, probe)?;
+                let found =
+                    matches!(self.get_token_balance(token, holder), Ok((b, _)) if b == probe);
+                self.set_storage(token, key, original)?;
+
+                if found {
+                    return Ok(balance_slot);
+                }
+            }
+        }
+
+        Err(anyhow!("balance slot not found. token={}", token))
+    }
+
+    fn set_storage(&mut self, target: Address, slot: U256, value: U256) -> Result<()> {
+        self.evm
+            .db_mut()
+            .0
+            .database_mut()
+            .insert_account_storage(target, slot, value)
+            .map_err(|e| anyhow!("failed to set storage. target={}, error={e:?}", target))
     }
 
     pub fn get_token_balance(
@@ -272,6 +2013,11 @@ impl<'a> EVM<'a> {
 
         let value = match result {
             ExecutionResult::Success { output: Output::Call(value), .. } => Ok(value),
+            ExecutionResult::Revert { output, .. } => Err(anyhow!(
+                "failed to get token balance. token={}, reason={}",
+                token,
+                revert_reason_or_output(&output)
+            )),
             _ => Err(anyhow!("failed to get token balance. token={}", token)),
         }?;
 
@@ -286,3 +2032,167 @@ impl<'a> EVM<'a> {
         Ok((result.balance, touched_account.to_owned()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use revm::db::{CacheDB, EmptyDB};
+    use revm::DatabaseRef;
+
+    use super::*;
+
+    const BASEFEE: u64 = 10_000_000_000;
+
+    /// Runs a plain transfer from a funded account with `tx_options` applied,
+    /// on a block with a 10 gwei basefee.
+    fn transfer_with(tx_options: &TxOptions) -> Result<ExecutionResult> {
+        let caller = Address::random();
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            caller,
+            AccountInfo { balance: U256::from(10u128.pow(21)), ..Default::default() },
+        );
+
+        let mut evm = Evm::builder()
+            .with_db(db)
+            .modify_block_env(|block| block.basefee = U256::from(BASEFEE))
+            .modify_tx_env(|tx| {
+                tx.caller = caller;
+                tx.transact_to = TransactTo::Call(Address::random());
+                tx.value = U256::from(1);
+            })
+            .build();
+        apply_tx_options(&mut evm.context.evm.env, tx_options)?;
+
+        Ok(evm.transact()?.result)
+    }
+
+    #[test]
+    fn no_fee_skips_the_base_fee_check() -> Result<()> {
+        let result = transfer_with(&TxOptions::default())?;
+
+        assert!(result.is_success());
+        Ok(())
+    }
+
+    #[test]
+    fn max_fee_alone_is_a_legacy_gas_price() -> Result<()> {
+        let tx_options =
+            TxOptions { max_fee_per_gas: Some(2 * BASEFEE as u128), ..Default::default() };
+        let result = transfer_with(&tx_options)?;
+
+        assert!(result.is_success());
+        Ok(())
+    }
+
+    #[test]
+    fn max_fee_and_priority_fee_are_an_eip1559_tx() -> Result<()> {
+        let tx_options = TxOptions {
+            max_fee_per_gas: Some(2 * BASEFEE as u128),
+            max_priority_fee_per_gas: Some(1_000_000_000),
+            ..Default::default()
+        };
+        let result = transfer_with(&tx_options)?;
+
+        assert!(result.is_success());
+        Ok(())
+    }
+
+    #[test]
+    fn priority_fee_without_max_fee_is_rejected() {
+        let tx_options =
+            TxOptions { max_priority_fee_per_gas: Some(1_000_000_000), ..Default::default() };
+
+        assert!(transfer_with(&tx_options).is_err());
+    }
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Uniswap V3 USDC/WETH 0.05%, its `slot0` changes nearly every block.
+    const USDC_WETH_POOL: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+
+    /// Returns the value of transient slot 0, then sets it to 1:
+    /// `TLOAD(0)`, `TSTORE(0, 1)`, `MSTORE(0, old)`, `RETURN(0, 32)`.
+    const TRANSIENT_COUNTER: &str = "0x60005c600160005d60005260206000f3";
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn transient_storage_is_cleared_between_commits() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        evm.set_spec_id(SpecId::CANCUN);
+
+        let target = Address::random();
+        evm.override_code(target, TRANSIENT_COUNTER)?;
+
+        for _ in 0..2 {
+            let outcome = evm.call(target, Bytes::new(), evm.owner(), U256::ZERO, true)?;
+            assert!(outcome.success);
+            assert_eq!(U256::from_be_slice(&outcome.output), U256::ZERO);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn weth_has_code_and_eoa_does_not() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+
+        assert!(evm.has_code(MAINNET_WETH));
+        assert!(!evm.code_at(MAINNET_WETH)?.is_empty());
+
+        let eoa = Address::random();
+        assert!(!evm.has_code(eoa));
+        assert!(evm.code_at(eoa)?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn simulate_many_leaves_the_commit_log_alone() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        evm.record_commits(true);
+
+        let owner = evm.owner();
+        evm.call(Address::random(), Bytes::new(), owner, U256::from(1), true)?;
+        let log = evm.commit_log();
+        assert!(!log.is_empty());
+
+        let to = Address::random();
+        let gas = evm.simulate_many(&[1, 2, 3], |evm, amount| {
+            let tx = evm.tx_mut();
+            tx.caller = owner;
+            tx.transact_to = TransactTo::Call(to);
+            tx.data = Bytes::new();
+            tx.value = U256::from(amount);
+            evm.transact_commit()
+                .map_or(0, |result| result.gas_used() as u128)
+        });
+
+        assert!(gas.iter().all(|gas| *gas > 0));
+        assert_eq!(evm.commit_log(), log);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn repinned_evm_reads_the_new_block() -> Result<()> {
+        let later = CANCUN_BLOCK + 100;
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        evm.backend()
+            .set_prune_on_repin(true)
+            .map_err(|e| anyhow!("{e:?}"))?;
+
+        let before = evm.db().storage_ref(USDC_WETH_POOL, U256::ZERO)?;
+        evm.set_block(later);
+        let after = evm.db().storage_ref(USDC_WETH_POOL, U256::ZERO)?;
+
+        let fresh = EVM::from_env(later).await?;
+        assert_ne!(before, after);
+        assert_eq!(after, fresh.db().storage_ref(USDC_WETH_POOL, U256::ZERO)?);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,16 @@
 pub mod abi;
 pub mod bytecode;
+pub mod events;
 pub mod evm;
+pub mod math;
+pub mod optimize;
+pub mod path;
+pub mod pool;
+pub mod revert;
+pub mod spec;
+pub mod trace;
 pub mod traits;
+pub mod tx;
+pub mod types;
+pub mod uniswap_v2_math;
+pub mod uniswap_v3_math;
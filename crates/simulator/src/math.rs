@@ -0,0 +1,357 @@
+//! Off-chain Uniswap V3 swap math for screening opportunities before running
+//! them through the EVM.
+
+use alloy::primitives::{U256, U512};
+
+/// 2^96, the fixed point scale of `sqrtPriceX96`.
+pub const Q96: U256 = U256::from_limbs([0, 1 << 32, 0, 0]);
+
+/// Fee denominator, V3 fees are expressed in hundredths of a bip.
+pub const FEE_DENOMINATOR: u32 = 1_000_000;
+
+/// Computes `a * b / denominator` with a 512-bit intermediate, rounding down.
+///
+/// Returns `None` on division by zero or if the result does not fit in 256
+/// bits.
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+
+    let product: U512 = a.widening_mul(b);
+    let quotient = product / U512::from(denominator);
+
+    U256::checked_from_limbs_slice(quotient.as_limbs())
+}
+
+/// Same as `mul_div`, rounding up.
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+
+    let product: U512 = a.widening_mul(b);
+    let quotient = product.div_ceil(U512::from(denominator));
+
+    U256::checked_from_limbs_slice(quotient.as_limbs())
+}
+
+/// Price after swapping `amount_in` into the pool without leaving the current
+/// tick range. Mirrors `SqrtPriceMath.getNextSqrtPriceFromInput`.
+pub fn get_next_sqrt_price_from_input(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Option<U256> {
+    if amount_in.is_zero() {
+        return Some(sqrt_price_x96);
+    }
+
+    let liquidity = U256::from(liquidity);
+
+    if zero_for_one {
+        // sqrtP' = L * sqrtP / (L + amount * sqrtP), rounded up.
+        let numerator = liquidity.checked_shl(96)?;
+        let denominator = amount_in
+            .checked_mul(sqrt_price_x96)
+            .and_then(|product| numerator.checked_add(product));
+
+        match denominator {
+            Some(denominator) => mul_div_rounding_up(numerator, sqrt_price_x96, denominator),
+            // Equivalent form that avoids the overflowing product.
+            None => {
+                let denominator = (numerator / sqrt_price_x96).checked_add(amount_in)?;
+                Some(numerator.div_ceil(denominator))
+            }
+        }
+    } else {
+        // sqrtP' = sqrtP + amount / L, rounded down.
+        let quotient = mul_div(amount_in, Q96, liquidity)?;
+        sqrt_price_x96.checked_add(quotient)
+    }
+}
+
+/// Amount of token0 between two prices, rounded down.
+pub fn get_amount0_delta(
+    sqrt_price_a_x96: U256,
+    sqrt_price_b_x96: U256,
+    liquidity: u128,
+) -> Option<U256> {
+    let (lower, upper) = if sqrt_price_a_x96 > sqrt_price_b_x96 {
+        (sqrt_price_b_x96, sqrt_price_a_x96)
+    } else {
+        (sqrt_price_a_x96, sqrt_price_b_x96)
+    };
+
+    if lower.is_zero() {
+        return None;
+    }
+
+    let numerator = U256::from(liquidity).checked_shl(96)?;
+    mul_div(numerator, upper - lower, upper).map(|value| value / lower)
+}
+
+/// Amount of token1 between two prices, rounded down.
+pub fn get_amount1_delta(
+    sqrt_price_a_x96: U256,
+    sqrt_price_b_x96: U256,
+    liquidity: u128,
+) -> Option<U256> {
+    let diff = if sqrt_price_a_x96 > sqrt_price_b_x96 {
+        sqrt_price_a_x96 - sqrt_price_b_x96
+    } else {
+        sqrt_price_b_x96 - sqrt_price_a_x96
+    };
+
+    mul_div(U256::from(liquidity), diff, Q96)
+}
+
+/// Amount of token0 between two prices, rounded up.
+pub fn get_amount0_delta_rounding_up(
+    sqrt_price_a_x96: U256,
+    sqrt_price_b_x96: U256,
+    liquidity: u128,
+) -> Option<U256> {
+    let (lower, upper) = if sqrt_price_a_x96 > sqrt_price_b_x96 {
+        (sqrt_price_b_x96, sqrt_price_a_x96)
+    } else {
+        (sqrt_price_a_x96, sqrt_price_b_x96)
+    };
+
+    if lower.is_zero() {
+        return None;
+    }
+
+    let numerator = U256::from(liquidity).checked_shl(96)?;
+    mul_div_rounding_up(numerator, upper - lower, upper).map(|value| value.div_ceil(lower))
+}
+
+/// Amount of token1 between two prices, rounded up.
+pub fn get_amount1_delta_rounding_up(
+    sqrt_price_a_x96: U256,
+    sqrt_price_b_x96: U256,
+    liquidity: u128,
+) -> Option<U256> {
+    let diff = if sqrt_price_a_x96 > sqrt_price_b_x96 {
+        sqrt_price_a_x96 - sqrt_price_b_x96
+    } else {
+        sqrt_price_b_x96 - sqrt_price_a_x96
+    };
+
+    mul_div_rounding_up(U256::from(liquidity), diff, Q96)
+}
+
+/// Approximates the output of an exact-input V3 swap assuming `liquidity`
+/// stays constant for the whole swap.
+///
+/// This is exact (up to rounding) as long as the swap does not cross an
+/// initialized tick. When it does, the liquidity used past the boundary is
+/// wrong and the error grows with the share of `amount_in` swapped outside
+/// the current range, so the result should only be used for screening and
+/// confirmed with an EVM simulation. `fee` is in hundredths of a bip
+/// (3000 = 0.3%). Returns zero if the pool has no liquidity or on overflow.
+pub fn v3_get_amount_out_single_tick(
+    sqrt_price_x96: U256,
+    liquidity: u128,
+    zero_for_one: bool,
+    amount_in: U256,
+    fee: u32,
+) -> U256 {
+    if liquidity == 0 || fee >= FEE_DENOMINATOR {
+        return U256::ZERO;
+    }
+
+    let amount_in_less_fee =
+        match mul_div(amount_in, U256::from(FEE_DENOMINATOR - fee), U256::from(FEE_DENOMINATOR)) {
+            Some(amount) => amount,
+            None => return U256::ZERO,
+        };
+
+    let sqrt_price_next_x96 = match get_next_sqrt_price_from_input(
+        sqrt_price_x96,
+        liquidity,
+        amount_in_less_fee,
+        zero_for_one,
+    ) {
+        Some(price) => price,
+        None => return U256::ZERO,
+    };
+
+    let amount_out = if zero_for_one {
+        get_amount1_delta(sqrt_price_next_x96, sqrt_price_x96, liquidity)
+    } else {
+        get_amount0_delta(sqrt_price_x96, sqrt_price_next_x96, liquidity)
+    };
+
+    amount_out.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::aliases::{U160, U24};
+    use alloy::primitives::{address, Address};
+    use alloy::sol;
+    use alloy::sol_types::SolCall;
+
+    use super::*;
+    use crate::evm::{EVM, MAINNET_WETH};
+    use crate::traits::UniswapV3PoolContract;
+    use crate::uniswap_v3_math::{compute_swap_step, MAX_SQRT_RATIO, MIN_SQRT_RATIO};
+
+    const LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
+    fn u(value: u128) -> U256 {
+        U256::from(value)
+    }
+
+    #[test]
+    fn mul_div_keeps_the_overflowing_product() {
+        assert_eq!(mul_div(U256::MAX, U256::MAX, U256::MAX), Some(U256::MAX));
+        assert_eq!(mul_div(U256::MAX, u(2), u(1)), None);
+        assert_eq!(mul_div(u(1), u(1), U256::ZERO), None);
+    }
+
+    #[test]
+    fn mul_div_rounding_up_rounds_only_remainders() {
+        assert_eq!(mul_div(u(5), u(1), u(2)), Some(u(2)));
+        assert_eq!(mul_div_rounding_up(u(5), u(1), u(2)), Some(u(3)));
+        assert_eq!(mul_div_rounding_up(u(6), u(1), u(2)), Some(u(3)));
+    }
+
+    #[test]
+    fn single_tick_at_price_one() {
+        let amount_in = u(1_000_000_000_000_000);
+
+        assert_eq!(
+            v3_get_amount_out_single_tick(Q96, LIQUIDITY, true, amount_in, 3000),
+            u(996_006_981_039_903)
+        );
+        assert_eq!(
+            v3_get_amount_out_single_tick(Q96, LIQUIDITY, false, amount_in, 3000),
+            u(996_006_981_039_903)
+        );
+    }
+
+    #[test]
+    fn single_tick_at_price_four() {
+        let sqrt_price_x96 = Q96 * u(2);
+        let amount_in = u(1_000_000_000_000_000);
+
+        assert_eq!(
+            v3_get_amount_out_single_tick(sqrt_price_x96, LIQUIDITY, true, amount_in, 500),
+            u(3_990_023_942_139_662)
+        );
+        assert_eq!(
+            v3_get_amount_out_single_tick(sqrt_price_x96, LIQUIDITY, false, amount_in, 500),
+            u(249_750_187_343_874)
+        );
+    }
+
+    #[test]
+    fn single_tick_returns_zero_when_nothing_can_be_swapped() {
+        let amount_in = u(1_000_000);
+
+        assert_eq!(v3_get_amount_out_single_tick(Q96, 0, true, amount_in, 3000), U256::ZERO);
+        assert_eq!(
+            v3_get_amount_out_single_tick(Q96, LIQUIDITY, true, amount_in, FEE_DENOMINATOR),
+            U256::ZERO
+        );
+        assert_eq!(
+            v3_get_amount_out_single_tick(Q96, LIQUIDITY, true, U256::ZERO, 3000),
+            U256::ZERO
+        );
+    }
+
+    /// Without a tick in the way, the swap loop does a single step towards
+    /// the price limit, which must give the same output.
+    #[test]
+    fn single_tick_matches_a_swap_step_within_range() -> anyhow::Result<()> {
+        for (zero_for_one, target) in
+            [(true, MIN_SQRT_RATIO + u(1)), (false, MAX_SQRT_RATIO - u(1))]
+        {
+            for amount_in in [1_000_u128, 1_000_000_000, 1_000_000_000_000_000] {
+                let step = compute_swap_step(Q96, target, LIQUIDITY, u(amount_in), 3000)?;
+
+                assert_eq!(
+                    v3_get_amount_out_single_tick(Q96, LIQUIDITY, zero_for_one, u(amount_in), 3000),
+                    step.amount_out,
+                    "zero_for_one={zero_for_one}, amount_in={amount_in}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Uniswap V3 USDC/WETH 0.05%, USDC is token0.
+    const USDC_WETH_POOL: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+
+    /// Uniswap V3 SwapRouter on mainnet, it wraps ETH sent with the call.
+    const SWAP_ROUTER: Address = address!("E592427A0AEce92De3Edee1F18E0157C05861564");
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+    sol! {
+        contract ISwapRouter {
+            struct ExactInputSingleParams {
+                address tokenIn;
+                address tokenOut;
+                uint24 fee;
+                address recipient;
+                uint256 deadline;
+                uint256 amountIn;
+                uint256 amountOutMinimum;
+                uint160 sqrtPriceLimitX96;
+            }
+
+            function exactInputSingle(ExactInputSingleParams calldata params)
+                external
+                payable
+                returns (uint256 amountOut);
+        }
+    }
+
+    /// A 0.5 WETH swap stays within the current range of the pool, so the
+    /// single tick approximation must match the real swap.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn single_tick_matches_an_in_range_swap() -> anyhow::Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        let owner = evm.owner();
+        let amount_in = u(500_000_000_000_000_000);
+
+        let (sqrt_price_x96, _) = evm.slot0(USDC_WETH_POOL)?;
+        let liquidity = evm.liquidity(USDC_WETH_POOL)?;
+        let fee = evm.fee(USDC_WETH_POOL)?;
+        let expected =
+            v3_get_amount_out_single_tick(sqrt_price_x96, liquidity, false, amount_in, fee);
+
+        let encoded =
+            ISwapRouter::exactInputSingleCall::new((ISwapRouter::ExactInputSingleParams {
+                tokenIn: MAINNET_WETH,
+                tokenOut: USDC,
+                fee: U24::from(fee),
+                recipient: owner,
+                deadline: U256::MAX,
+                amountIn: amount_in,
+                amountOutMinimum: U256::ZERO,
+                sqrtPriceLimitX96: U160::ZERO,
+            },))
+            .abi_encode();
+        let outcome = evm.call(SWAP_ROUTER, encoded.into(), owner, amount_in, true)?;
+        assert!(outcome.success);
+
+        let amount_out =
+            ISwapRouter::exactInputSingleCall::abi_decode_returns(&outcome.output, false)?
+                .amountOut;
+
+        assert_eq!(evm.liquidity(USDC_WETH_POOL)?, liquidity, "the swap must stay in range");
+        assert_eq!(amount_out, expected);
+
+        Ok(())
+    }
+}
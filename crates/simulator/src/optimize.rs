@@ -0,0 +1,531 @@
+//! Amount-in search for the flashswap LST arbitrage, shared by the bins.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Instant;
+
+use alloy::primitives::Address;
+use anyhow::Result;
+use rayon::prelude::*;
+use revm::primitives::U256;
+use tracing::info;
+
+use crate::evm::EVM;
+use crate::pool::EvmPool;
+use crate::traits::SimulatorContract;
+
+#[derive(Debug, Clone)]
+pub struct Optimized {
+    pub optimized_in: u128,
+    pub optimized_out: u128,
+}
+
+/// Outcome of one arbitrage simulation, all amounts in WETH wei.
+#[derive(Debug, Clone)]
+pub struct ArbResult {
+    pub amount_in: u128,
+    /// WETH gained by the simulator, before gas.
+    pub gross_profit: u128,
+    pub gas_used: u64,
+    /// `gas_used * (basefee + priority fee)`, the basefee of the pinned block.
+    pub gas_cost_wei: u128,
+    pub net_profit: i128,
+    pub block_number: u64,
+}
+
+/// One amount evaluated by `optimize_arbitrage_sweep`.
+#[derive(Debug, Clone)]
+pub struct SweepPoint {
+    pub amount_in: u128,
+    /// `None` if the simulation failed.
+    pub result: Option<ArbResult>,
+    pub took_ms: u128,
+}
+
+/// Runs the arbitrage for `amount_in` and reverts the state afterwards, so
+/// every candidate sees the same fork. A reverted arbitrage is an error, not
+/// a zero profit.
+///
+/// `priority_fee` is the tip per gas the arbitrage would pay on top of the
+/// basefee, in wei. It only counts in the gas cost, the simulation itself
+/// runs without fees.
+pub fn simulate_arbitrage(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    zfo: bool,
+    amount_in: u128,
+    priority_fee: u128,
+) -> Result<ArbResult> {
+    let snapshot_id = evm.snapshot();
+    let result = simulate_arbitrage_unreverted(evm, pool, zfo, amount_in, priority_fee);
+    evm.revert(snapshot_id)?;
+
+    result
+}
+
+/// `simulate_arbitrage` for every amount in parallel on the rayon thread
+/// pool, each on an EVM checked out of `evms` and returned right after.
+/// Results are in input order.
+///
+/// At most `evms.size()` simulations run at once, build the pool with
+/// `BlockingMode::Block` since rayon workers aren't tokio threads.
+pub fn simulate_arbitrage_pooled(
+    evms: &EvmPool,
+    pool: Address,
+    zfo: bool,
+    amounts_in: &[u128],
+    priority_fee: u128,
+) -> Vec<Result<ArbResult>> {
+    amounts_in
+        .par_iter()
+        .map(|&amount_in| {
+            // Reset when returned to the pool, no snapshot needed. Reverting
+            // one would also refill the fetched state the siblings share.
+            let mut evm = evms.checkout()?;
+            simulate_arbitrage_unreverted(&mut evm, pool, zfo, amount_in, priority_fee)
+        })
+        .collect()
+}
+
+/// `simulate_arbitrage` without the snapshot, the arbitrage stays committed.
+fn simulate_arbitrage_unreverted(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    zfo: bool,
+    amount_in: u128,
+    priority_fee: u128,
+) -> Result<ArbResult> {
+    let weth = evm.weth();
+    let simulator = evm.require_simulator()?;
+    let balance_before = evm.get_token_balance(weth, simulator)?.0;
+
+    let outcome = evm.flashswap_lst_arbitrage_checked(pool, zfo, U256::from(amount_in))?;
+
+    let balance_after = evm.get_token_balance(weth, simulator)?.0;
+    let gas_used = outcome.gas_used;
+
+    let gross_profit: u128 = balance_after
+        .saturating_sub(balance_before)
+        .try_into()
+        .unwrap_or_else(|_| {
+            info!("Profit too large for u128, returning 0");
+            0
+        });
+
+    let basefee: u128 = evm.evm.block().basefee.try_into().unwrap_or(u128::MAX);
+    let gas_price = basefee.saturating_add(priority_fee);
+    let gas_cost_wei = gas_price.saturating_mul(gas_used as u128);
+    let net_profit = (gross_profit as i128).saturating_sub(gas_cost_wei as i128);
+
+    Ok(ArbResult {
+        amount_in,
+        gross_profit,
+        gas_used,
+        gas_cost_wei,
+        net_profit,
+        block_number: evm.block_number(),
+    })
+}
+
+// 1 / golden ratio, scaled by 1e6.
+const INV_PHI: u128 = 618_034;
+
+fn golden_step(width: u128) -> u128 {
+    mul_ppm(width, INV_PHI)
+}
+
+/// `value * ppm / 1e6` without overflowing.
+fn mul_ppm(value: u128, ppm: u128) -> u128 {
+    value / 1_000_000 * ppm + value % 1_000_000 * ppm / 1_000_000
+}
+
+/// When a search stops: once its window is within `abs`, or within `rel_ppm`
+/// parts per million of the amounts in the window, whichever is wider.
+///
+/// An absolute tolerance alone is too coarse for small opportunities and
+/// wastes iterations on large ones, the relative part scales with the amount
+/// and `abs` stays as the floor. A plain `u128` is an absolute tolerance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tolerance {
+    pub abs: u128,
+    pub rel_ppm: u128,
+}
+
+impl Tolerance {
+    pub fn absolute(abs: u128) -> Self {
+        Self { abs, rel_ppm: 0 }
+    }
+
+    pub fn relative(abs: u128, rel_ppm: u128) -> Self {
+        Self { abs, rel_ppm }
+    }
+
+    /// Window width to stop at around `amount_in`, at least 1.
+    pub fn window(&self, amount_in: u128) -> u128 {
+        self.abs.max(mul_ppm(amount_in, self.rel_ppm)).max(1)
+    }
+}
+
+impl From<u128> for Tolerance {
+    fn from(abs: u128) -> Self {
+        Self::absolute(abs)
+    }
+}
+
+/// Golden-section search for the maximum of a unimodal function on [lo, hi].
+///
+/// Each iteration shrinks the window by ~0.618 with a single new evaluation.
+/// `hi` is always evaluated too, so a profit that keeps increasing up to the
+/// ceiling is still found. A relative `tol` is taken around the middle of the
+/// window, where the maximum lies.
+pub fn optimize_unimodal(
+    mut f: impl FnMut(u128) -> u128,
+    lo: u128,
+    hi: u128,
+    tol: impl Into<Tolerance>,
+) -> Optimized {
+    let tol = tol.into();
+
+    let mut best = Optimized { optimized_in: lo, optimized_out: 0 };
+    let mut eval = |amount_in: u128| {
+        let profit = f(amount_in);
+        if profit > best.optimized_out {
+            best = Optimized { optimized_in: amount_in, optimized_out: profit };
+        }
+        profit
+    };
+
+    eval(hi);
+
+    let mut a = lo;
+    let mut b = hi;
+    let mut c = b - golden_step(b - a);
+    let mut d = a + golden_step(b - a);
+    let mut fc = eval(c);
+    let mut fd = eval(d);
+
+    while b - a > tol.window(a + (b - a) / 2) {
+        if fc >= fd {
+            b = d;
+            d = c;
+            fd = fc;
+            c = b - golden_step(b - a);
+            fc = eval(c);
+        } else {
+            a = c;
+            c = d;
+            fc = fd;
+            d = a + golden_step(b - a);
+            fd = eval(d);
+        }
+    }
+
+    best
+}
+
+/// Grid search for the maximum of `f`, starting on `bounds` and never going
+/// past `ceiling`.
+///
+/// Each round evaluates `intervals + 1` evenly spaced points and narrows the
+/// window to one step around the best one. When the best point is the lower
+/// bound the window slides down to `[min, min + step]`, when it is the upper
+/// bound the window keeps its upper bound. Stops once the window is within
+/// `tolerance` or the step rounds to zero. `intervals` is raised to 3, below
+/// that the window around an interior point doesn't shrink.
+pub fn grid_search<F>(
+    mut f: F,
+    bounds: Range<u128>,
+    intervals: u32,
+    tolerance: u128,
+    ceiling: u128,
+) -> Optimized
+where
+    F: FnMut(u128) -> u128,
+{
+    let intervals = u128::from(intervals.max(3));
+
+    let mut min_amount_in = bounds.start.min(ceiling);
+    let mut max_amount_in = bounds.end.min(ceiling);
+    let mut best = Optimized { optimized_in: min_amount_in, optimized_out: 0 };
+
+    while max_amount_in.saturating_sub(min_amount_in) > tolerance {
+        let step = (max_amount_in - min_amount_in) / intervals;
+        if step == 0 {
+            break;
+        }
+
+        let mut best_local_profit = 0;
+        let mut best_local_amount_in = min_amount_in;
+
+        for i in 0..=intervals {
+            let amount_in = (min_amount_in + i * step).min(ceiling);
+            let profit = f(amount_in);
+
+            if profit > best_local_profit {
+                best_local_profit = profit;
+                best_local_amount_in = amount_in;
+            }
+
+            if profit > best.optimized_out {
+                best = Optimized { optimized_in: amount_in, optimized_out: profit };
+            }
+
+            if amount_in == ceiling {
+                break;
+            }
+        }
+
+        if best_local_amount_in == min_amount_in {
+            max_amount_in = (best_local_amount_in + step).min(ceiling);
+        } else if best_local_amount_in == max_amount_in {
+            // NB: Intentionally leave max_amount_in unchanged.
+            min_amount_in = max_amount_in.saturating_sub(step);
+        } else {
+            min_amount_in = best_local_amount_in.saturating_sub(step);
+            max_amount_in = (best_local_amount_in + step).min(ceiling);
+        }
+    }
+
+    best
+}
+
+/// Searches `[lo, hi]` for the amount in with the highest net profit, after
+/// gas at the pinned basefee plus `priority_fee`, see `simulate_arbitrage`.
+///
+/// Failed simulations and net losses count as zero profit. Returns `None` if
+/// no amount made a net profit, even if some made a gross one.
+pub fn optimize_arbitrage(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    zfo: bool,
+    lo: u128,
+    hi: u128,
+    tol: impl Into<Tolerance>,
+    priority_fee: u128,
+) -> Option<ArbResult> {
+    optimize_arbitrage_sweep(evm, pool, zfo, lo, hi, tol, priority_fee).0
+}
+
+/// Same as `optimize_arbitrage`, also returning every evaluated amount in
+/// evaluation order, e.g. to plot the profit curve.
+pub fn optimize_arbitrage_sweep(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    zfo: bool,
+    lo: u128,
+    hi: u128,
+    tol: impl Into<Tolerance>,
+    priority_fee: u128,
+) -> (Option<ArbResult>, Vec<SweepPoint>) {
+    let mut results = HashMap::new();
+    let mut sweep = Vec::new();
+
+    let optimized = optimize_unimodal(
+        |amount_in| {
+            let s = Instant::now();
+            let result = simulate_arbitrage(evm, pool, zfo, amount_in, priority_fee);
+            let took = s.elapsed().as_millis();
+
+            match result {
+                Ok(result) => {
+                    info!(
+                        "amount_in={amount_in}, gross_profit={}, gas_cost={}, net_profit={}, \
+                         took={took}ms",
+                        result.gross_profit, result.gas_cost_wei, result.net_profit
+                    );
+                    let net_profit = result.net_profit.max(0) as u128;
+                    sweep.push(SweepPoint {
+                        amount_in,
+                        result: Some(result.clone()),
+                        took_ms: took,
+                    });
+                    results.insert(amount_in, result);
+                    net_profit
+                }
+                Err(e) => {
+                    info!("amount_in={amount_in}, simulation failed. error={e:?}, took={took}ms");
+                    sweep.push(SweepPoint { amount_in, result: None, took_ms: took });
+                    0
+                }
+            }
+        },
+        lo,
+        hi,
+        tol,
+    );
+
+    if optimized.optimized_out == 0 {
+        return (None, sweep);
+    }
+
+    (results.remove(&optimized.optimized_in), sweep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETH: u128 = 1_000_000_000_000_000_000;
+
+    /// Concave profit peaking at `peak` with `max_profit`.
+    fn concave(peak: u128, max_profit: u128) -> impl Fn(u128) -> u128 {
+        move |amount_in| {
+            let distance = amount_in.abs_diff(peak) / 1_000_000_000_000;
+            max_profit.saturating_sub(distance * distance)
+        }
+    }
+
+    #[test]
+    fn optimize_unimodal_finds_the_peak_within_tolerance() {
+        let tol = 10_u128.pow(15);
+        for peak in [ETH / 10, 3 * ETH, 400 * ETH, 999 * ETH] {
+            let optimized = optimize_unimodal(concave(peak, 10 * ETH), 0, 1000 * ETH, tol);
+
+            assert!(
+                optimized.optimized_in.abs_diff(peak) <= tol,
+                "peak={peak}, optimized_in={}",
+                optimized.optimized_in
+            );
+            assert!(optimized.optimized_out > 0);
+        }
+    }
+
+    #[test]
+    fn optimize_unimodal_takes_a_logarithmic_number_of_steps() {
+        let f = concave(400 * ETH, 10 * ETH);
+        let mut evaluations = 0;
+
+        optimize_unimodal(
+            |amount_in| {
+                evaluations += 1;
+                f(amount_in)
+            },
+            0,
+            1000 * ETH,
+            10_u128.pow(15),
+        );
+
+        // The window shrinks from 1e21 to 1e15 by ~0.618 per evaluation.
+        assert!(evaluations <= 35, "evaluations={evaluations}");
+    }
+
+    #[test]
+    fn optimize_unimodal_finds_an_optimum_at_the_ceiling() {
+        let optimized = optimize_unimodal(|amount_in| amount_in, 0, 1000 * ETH, 10_u128.pow(15));
+
+        assert_eq!(optimized.optimized_in, 1000 * ETH);
+        assert_eq!(optimized.optimized_out, 1000 * ETH);
+    }
+
+    #[test]
+    fn optimize_unimodal_without_profit_returns_lo() {
+        let optimized = optimize_unimodal(|_| 0, 5, 1000 * ETH, 10_u128.pow(15));
+
+        assert_eq!(optimized.optimized_in, 5);
+        assert_eq!(optimized.optimized_out, 0);
+    }
+
+    #[test]
+    fn optimize_unimodal_with_relative_tolerance() {
+        let peak = 400 * ETH;
+        // 0.1% of the amount, the absolute floor is far below.
+        let tol = Tolerance::relative(10_u128.pow(15), 1_000);
+
+        let optimized = optimize_unimodal(concave(peak, 10 * ETH), 0, 1000 * ETH, tol);
+
+        assert!(optimized.optimized_in.abs_diff(peak) <= tol.window(peak));
+    }
+
+    /// Runs `grid_search`, panicking if it evaluates `f` more than
+    /// `max_evaluations` times.
+    fn bounded_grid_search(
+        f: impl Fn(u128) -> u128,
+        bounds: Range<u128>,
+        intervals: u32,
+        tolerance: u128,
+        ceiling: u128,
+        max_evaluations: usize,
+    ) -> Optimized {
+        let mut evaluations = 0;
+        grid_search(
+            |amount_in| {
+                evaluations += 1;
+                assert!(evaluations <= max_evaluations, "grid search doesn't terminate");
+                assert!(amount_in <= ceiling, "evaluated {amount_in} above the ceiling");
+                f(amount_in)
+            },
+            bounds,
+            intervals,
+            tolerance,
+            ceiling,
+        )
+    }
+
+    #[test]
+    fn grid_search_finds_the_peak() {
+        let tol = 10_u128.pow(15);
+        for intervals in [3, 10, 20] {
+            for peak in [ETH / 10, 3 * ETH, 400 * ETH, 999 * ETH] {
+                let optimized = bounded_grid_search(
+                    concave(peak, 10 * ETH),
+                    0..1000 * ETH,
+                    intervals,
+                    tol,
+                    u128::MAX,
+                    1000,
+                );
+
+                assert!(
+                    optimized.optimized_in.abs_diff(peak) <= tol,
+                    "intervals={intervals}, peak={peak}, optimized_in={}",
+                    optimized.optimized_in
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn grid_search_without_profit_terminates() {
+        for intervals in [0, 3, 10] {
+            let optimized =
+                bounded_grid_search(|_| 0, 5..1000 * ETH, intervals, 0, u128::MAX, 1000);
+
+            assert_eq!(optimized.optimized_in, 5);
+            assert_eq!(optimized.optimized_out, 0);
+        }
+    }
+
+    #[test]
+    fn grid_search_on_a_plateau_terminates() {
+        // Profit grows up to 10 ETH in, then stays flat.
+        let plateau = |amount_in: u128| amount_in.min(10 * ETH);
+
+        let optimized = bounded_grid_search(plateau, 0..1000 * ETH, 10, 0, u128::MAX, 1000);
+
+        assert_eq!(optimized.optimized_out, 10 * ETH);
+        assert!(optimized.optimized_in >= 10 * ETH);
+    }
+
+    #[test]
+    fn grid_search_stops_once_the_step_is_zero() {
+        let optimized =
+            bounded_grid_search(|amount_in| 10 - amount_in.abs_diff(3), 0..7, 3, 0, u128::MAX, 100);
+        assert_eq!(optimized.optimized_in, 3);
+
+        // Narrower than `intervals`, nothing is evaluated.
+        let optimized = bounded_grid_search(|_| 1, 0..5, 10, 0, u128::MAX, 0);
+        assert_eq!(optimized.optimized_in, 0);
+        assert_eq!(optimized.optimized_out, 0);
+    }
+
+    #[test]
+    fn grid_search_never_goes_past_the_ceiling() {
+        let tol = 10_u128.pow(15);
+
+        let optimized =
+            bounded_grid_search(|amount_in| amount_in, 0..1000 * ETH, 10, tol, 10 * ETH, 1000);
+
+        assert_eq!(optimized.optimized_in, 10 * ETH);
+        assert_eq!(optimized.optimized_out, 10 * ETH);
+    }
+}
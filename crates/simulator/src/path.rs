@@ -0,0 +1,390 @@
+//! Multi-hop arbitrage search over a set of pools, quoted off-chain.
+//!
+//! Quotes use the V2 constant-product formula and the single-tick V3
+//! approximation, so found paths are candidates to confirm with an EVM
+//! simulation.
+
+use std::collections::HashMap;
+
+use alloy::primitives::{Address, U256};
+
+use crate::math::v3_get_amount_out_single_tick;
+use crate::optimize::optimize_unimodal;
+use crate::uniswap_v2_math::get_amount_out;
+
+/// Live state of a pool, as read from reserves or `slot0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolState {
+    V2 { reserve0: U256, reserve1: U256 },
+    V3 { sqrt_price_x96: U256, liquidity: u128 },
+}
+
+#[derive(Debug, Clone)]
+pub struct PathPool {
+    pub address: Address,
+    pub token0: Address,
+    pub token1: Address,
+    /// Swap fee in hundredths of a bip (3000 = 0.3%), for every venue.
+    pub fee: u32,
+    pub state: PoolState,
+}
+
+impl PathPool {
+    /// The other token of the pool, `None` if `token` isn't in it.
+    pub fn other(&self, token: Address) -> Option<Address> {
+        if token == self.token0 {
+            Some(self.token1)
+        } else if token == self.token1 {
+            Some(self.token0)
+        } else {
+            None
+        }
+    }
+
+    /// Output of swapping `amount_in` of `token_in`, zero if it can't be
+    /// quoted.
+    pub fn amount_out(&self, token_in: Address, amount_in: U256) -> U256 {
+        let zero_for_one = token_in == self.token0;
+
+        match self.state {
+            PoolState::V2 { reserve0, reserve1 } => {
+                let (reserve_in, reserve_out) =
+                    if zero_for_one { (reserve0, reserve1) } else { (reserve1, reserve0) };
+                get_amount_out(amount_in, reserve_in, reserve_out, self.fee / 100)
+            }
+            PoolState::V3 { sqrt_price_x96, liquidity } => v3_get_amount_out_single_tick(
+                sqrt_price_x96,
+                liquidity,
+                zero_for_one,
+                amount_in,
+                self.fee,
+            ),
+        }
+    }
+}
+
+/// One swap of a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hop {
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+}
+
+/// A profitable cycle and its optimized amount in, amounts in the start token.
+#[derive(Debug, Clone)]
+pub struct ArbPath {
+    pub hops: Vec<Hop>,
+    pub amount_in: u128,
+    pub amount_out: u128,
+    pub profit: u128,
+}
+
+/// Finds cycles of 2 and 3 hops starting and ending in one token, usually
+/// WETH, and ranks them by profit.
+#[derive(Debug, Clone)]
+pub struct PathFinder {
+    start: Address,
+    pools: Vec<PathPool>,
+    /// Indices of the pools holding each token.
+    by_token: HashMap<Address, Vec<usize>>,
+    by_address: HashMap<Address, usize>,
+}
+
+impl PathFinder {
+    pub fn new(start: Address, pools: impl IntoIterator<Item = PathPool>) -> Self {
+        let pools: Vec<PathPool> = pools.into_iter().collect();
+
+        let mut by_token: HashMap<Address, Vec<usize>> = HashMap::new();
+        let mut by_address = HashMap::new();
+        for (index, pool) in pools.iter().enumerate() {
+            by_token.entry(pool.token0).or_default().push(index);
+            by_token.entry(pool.token1).or_default().push(index);
+            by_address.insert(pool.address, index);
+        }
+
+        Self { start, pools, by_token, by_address }
+    }
+
+    fn hop(&self, index: usize, token_in: Address) -> Option<Hop> {
+        let pool = &self.pools[index];
+        pool.other(token_in)
+            .map(|token_out| Hop { pool: pool.address, token_in, token_out })
+    }
+
+    /// Every cycle of 2 to `max_hops` (at most 3) distinct pools through
+    /// distinct tokens. Both directions of a cycle are listed.
+    pub fn cycles(&self, max_hops: usize) -> Vec<Vec<Hop>> {
+        let mut cycles = Vec::new();
+        let Some(first_pools) = self.by_token.get(&self.start) else {
+            return cycles;
+        };
+
+        for &first in first_pools {
+            let Some(first_hop) = self.hop(first, self.start) else {
+                continue;
+            };
+            let middle = first_hop.token_out;
+            if middle == self.start {
+                continue;
+            }
+
+            for &second in &self.by_token[&middle] {
+                if second == first {
+                    continue;
+                }
+                let Some(second_hop) = self.hop(second, middle) else {
+                    continue;
+                };
+
+                if second_hop.token_out == self.start {
+                    cycles.push(vec![first_hop, second_hop]);
+                    continue;
+                }
+                if max_hops < 3 {
+                    continue;
+                }
+
+                let last = second_hop.token_out;
+                for &third in &self.by_token[&last] {
+                    if third == first || third == second {
+                        continue;
+                    }
+                    match self.hop(third, last) {
+                        Some(third_hop) if third_hop.token_out == self.start => {
+                            cycles.push(vec![first_hop, second_hop, third_hop]);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        cycles
+    }
+
+    /// Output of running `amount_in` through `hops`, zero if any hop can't be
+    /// quoted.
+    pub fn quote(&self, hops: &[Hop], amount_in: U256) -> U256 {
+        hops.iter()
+            .try_fold(amount_in, |amount, hop| {
+                self.by_address
+                    .get(&hop.pool)
+                    .map(|&index| self.pools[index].amount_out(hop.token_in, amount))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Optimizes the amount in of every cycle on `[0, ceiling]` and returns
+    /// the profitable ones, most profitable first.
+    pub fn find(&self, max_hops: usize, ceiling: u128, tolerance: u128) -> Vec<ArbPath> {
+        let amount_out = |hops: &[Hop], amount_in: u128| -> u128 {
+            self.quote(hops, U256::from(amount_in))
+                .try_into()
+                .unwrap_or_default()
+        };
+
+        let mut paths: Vec<ArbPath> = self
+            .cycles(max_hops)
+            .into_iter()
+            .filter_map(|hops| {
+                let optimized = optimize_unimodal(
+                    |amount_in| amount_out(&hops, amount_in).saturating_sub(amount_in),
+                    0,
+                    ceiling,
+                    tolerance,
+                );
+                if optimized.optimized_out == 0 {
+                    return None;
+                }
+
+                let amount_in = optimized.optimized_in;
+                Some(ArbPath {
+                    amount_out: amount_out(&hops, amount_in),
+                    hops,
+                    amount_in,
+                    profit: optimized.optimized_out,
+                })
+            })
+            .collect();
+
+        paths.sort_by(|a, b| b.profit.cmp(&a.profit));
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::math::Q96;
+
+    const WETH: Address = Address::repeat_byte(0xee);
+    const TOKEN_A: Address = Address::repeat_byte(0x0a);
+    const TOKEN_B: Address = Address::repeat_byte(0x0b);
+
+    const ETHER: u128 = 1_000_000_000_000_000_000;
+
+    /// V2 pool with reserves in whole tokens and the 0.3% fee.
+    fn v2(id: u8, token0: Address, token1: Address, reserve0: u128, reserve1: u128) -> PathPool {
+        PathPool {
+            address: Address::repeat_byte(id),
+            token0,
+            token1,
+            fee: 3000,
+            state: PoolState::V2 {
+                reserve0: U256::from(reserve0 * ETHER),
+                reserve1: U256::from(reserve1 * ETHER),
+            },
+        }
+    }
+
+    /// V3 pool at price 1 with the 0.05% fee.
+    fn v3(id: u8, token0: Address, token1: Address) -> PathPool {
+        PathPool {
+            address: Address::repeat_byte(id),
+            token0,
+            token1,
+            fee: 500,
+            state: PoolState::V3 { sqrt_price_x96: Q96, liquidity: 1_000_000 * ETHER },
+        }
+    }
+
+    fn pool_ids(hops: &[Hop]) -> Vec<u8> {
+        hops.iter().map(|hop| hop.pool[0]).collect()
+    }
+
+    fn sorted_pool_ids(cycles: &[Vec<Hop>]) -> Vec<Vec<u8>> {
+        let mut ids: Vec<Vec<u8>> = cycles.iter().map(|hops| pool_ids(hops)).collect();
+        ids.sort();
+        ids
+    }
+
+    /// WETH/A twice, A/B and B/WETH.
+    fn triangle_and_pair() -> PathFinder {
+        PathFinder::new(
+            WETH,
+            [
+                v2(1, WETH, TOKEN_A, 1000, 1000),
+                v2(2, TOKEN_A, TOKEN_B, 1000, 1000),
+                v2(3, TOKEN_B, WETH, 1000, 1000),
+                v2(4, WETH, TOKEN_A, 1000, 1000),
+            ],
+        )
+    }
+
+    #[test]
+    fn two_hop_cycles_go_both_ways() {
+        let finder =
+            PathFinder::new(WETH, [v2(1, WETH, TOKEN_A, 1000, 1000), v3(2, TOKEN_A, WETH)]);
+
+        let cycles = finder.cycles(2);
+        assert_eq!(sorted_pool_ids(&cycles), [vec![1, 2], vec![2, 1]]);
+
+        for hops in &cycles {
+            assert_eq!(hops[0].token_in, WETH);
+            assert_eq!(hops[0].token_out, TOKEN_A);
+            assert_eq!(hops[1].token_in, TOKEN_A);
+            assert_eq!(hops[1].token_out, WETH);
+        }
+    }
+
+    #[test]
+    fn three_hop_cycles_go_both_ways() {
+        let finder = PathFinder::new(
+            WETH,
+            [
+                v2(1, WETH, TOKEN_A, 1000, 1000),
+                v3(2, TOKEN_A, TOKEN_B),
+                v2(3, TOKEN_B, WETH, 1000, 1000),
+            ],
+        );
+
+        let cycles = finder.cycles(3);
+        assert_eq!(sorted_pool_ids(&cycles), [vec![1, 2, 3], vec![3, 2, 1]]);
+
+        for hops in &cycles {
+            assert_eq!(hops[0].token_in, WETH);
+            assert_eq!(hops[2].token_out, WETH);
+            for pair in hops.windows(2) {
+                assert_eq!(pair[0].token_out, pair[1].token_in);
+            }
+        }
+    }
+
+    #[test]
+    fn a_pool_is_never_reused() {
+        let single = PathFinder::new(WETH, [v2(1, WETH, TOKEN_A, 1000, 1000)]);
+        assert!(single.cycles(3).is_empty());
+
+        for hops in triangle_and_pair().cycles(3) {
+            let pools: HashSet<Address> = hops.iter().map(|hop| hop.pool).collect();
+            assert_eq!(pools.len(), hops.len(), "pools={:?}", pool_ids(&hops));
+        }
+    }
+
+    #[test]
+    fn max_hops_below_three_skips_three_hop_cycles() {
+        let finder = triangle_and_pair();
+
+        assert_eq!(sorted_pool_ids(&finder.cycles(2)), [vec![1, 4], vec![4, 1]]);
+        assert_eq!(
+            sorted_pool_ids(&finder.cycles(3)),
+            [vec![1, 2, 3], vec![1, 4], vec![3, 2, 1], vec![3, 2, 4], vec![4, 1], vec![4, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn quote_chains_the_hops() {
+        let pools = [v2(1, WETH, TOKEN_A, 1000, 1100), v3(2, TOKEN_A, WETH)];
+        let finder = PathFinder::new(WETH, pools.clone());
+        let hops = [
+            Hop { pool: pools[0].address, token_in: WETH, token_out: TOKEN_A },
+            Hop { pool: pools[1].address, token_in: TOKEN_A, token_out: WETH },
+        ];
+
+        let amount_in = U256::from(ETHER);
+        let expected = pools[1].amount_out(TOKEN_A, pools[0].amount_out(WETH, amount_in));
+
+        assert!(!expected.is_zero());
+        assert_eq!(finder.quote(&hops, amount_in), expected);
+    }
+
+    #[test]
+    fn unknown_pool_quotes_to_zero() {
+        let finder = PathFinder::new(WETH, [v2(1, WETH, TOKEN_A, 1000, 1000)]);
+        let known = Hop { pool: Address::repeat_byte(1), token_in: WETH, token_out: TOKEN_A };
+        let unknown = Hop { pool: Address::repeat_byte(0x99), token_in: TOKEN_A, token_out: WETH };
+
+        assert!(!finder.quote(&[known], U256::from(ETHER)).is_zero());
+        assert_eq!(finder.quote(&[unknown], U256::from(ETHER)), U256::ZERO);
+        assert_eq!(finder.quote(&[known, unknown], U256::from(ETHER)), U256::ZERO);
+    }
+
+    #[test]
+    fn found_paths_are_ordered_by_profit() {
+        // A is 10% cheaper on pool 2, B 3% cheaper on pool 4.
+        let finder = PathFinder::new(
+            WETH,
+            [
+                v2(1, WETH, TOKEN_A, 1000, 1000),
+                v2(2, WETH, TOKEN_A, 1000, 1100),
+                v2(3, WETH, TOKEN_B, 1000, 1000),
+                v2(4, WETH, TOKEN_B, 1000, 1030),
+            ],
+        );
+
+        let paths = finder.find(2, 100 * ETHER, ETHER / 1000);
+
+        // Only the direction buying on the cheap pool is profitable.
+        let ids: Vec<Vec<u8>> = paths.iter().map(|path| pool_ids(&path.hops)).collect();
+        assert_eq!(ids, [vec![2, 1], vec![4, 3]]);
+        assert!(paths[0].profit > paths[1].profit);
+
+        for path in &paths {
+            assert!(path.amount_in > 0 && path.amount_in <= 100 * ETHER);
+            assert_eq!(path.amount_out - path.amount_in, path.profit);
+        }
+    }
+}
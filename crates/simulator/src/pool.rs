@@ -0,0 +1,334 @@
+//! Fixed-size pool of warm `EVM`s sharing one fork, for concurrent
+//! simulations without an `EVM` per candidate.
+//!
+//! Every pooled EVM is a `EVM::sibling` of the one the pool is built from: it
+//! has its own local state but reads through the same `SharedBackend`, so a
+//! slot fetched by one simulation is served from the shared cache to all the
+//! others. Memory is bounded by the pool size instead of the candidate count.
+//!
+//! # Blocking mode
+//!
+//! A cache miss blocks the simulating thread until the `BackendHandler`, a
+//! task on the tokio runtime, answers. How the thread blocks is the
+//! `BlockingMode` the pool is built with:
+//!
+//! - `Block` for rayon workers, `std::thread`s and `spawn_blocking` tasks. They
+//!   are not tokio workers, so blocking them doesn't stall the handler.
+//! - `BlockInPlace` (or `Timeout`) to simulate from an async task. It needs the
+//!   multi-threaded runtime and hands the worker's other tasks off while
+//!   waiting. `Block` there can deadlock if the handler is queued behind the
+//!   blocked task.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+use anyhow::{anyhow, Result};
+use evm_fork_db::backend::BlockingMode;
+use tracing::error;
+
+use crate::evm::{EvmParts, EVM};
+
+pub struct EvmPool {
+    slots: Slots<EVM<'static>>,
+}
+
+impl EvmPool {
+    /// Builds `size` siblings of `evm`, which stays usable and isn't part of
+    /// the pool.
+    ///
+    /// The siblings share the pinned block of `evm`'s backend: moving the block
+    /// of any of them, or of `evm`, moves it for all. Pin `evm` before building
+    /// the pool.
+    pub fn new(evm: &EVM<'_>, size: usize, blocking_mode: BlockingMode) -> Result<Self> {
+        let parts = (0..size)
+            .map(|_| Ok(evm.sibling(blocking_mode.clone())?.into_parts()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { slots: Slots::new(parts) })
+    }
+
+    /// Takes an idle EVM, waiting for one to be returned if all are checked
+    /// out. Fails if the pool is empty.
+    pub fn checkout(&self) -> Result<PooledEvm<'_>> {
+        self.slots.checkout().map(PooledEvm)
+    }
+
+    /// Same as `checkout`, `None` instead of waiting if all are checked out.
+    pub fn try_checkout(&self) -> Option<PooledEvm<'_>> {
+        self.slots.try_checkout().map(PooledEvm)
+    }
+
+    /// Number of EVMs in the pool, checked out or not.
+    pub fn size(&self) -> usize {
+        self.slots.size()
+    }
+}
+
+/// An EVM checked out of an `EvmPool`. It is reset, like `EVM::reset`, and
+/// returned to the pool when dropped.
+///
+/// The block env isn't restored, see `EvmPool::new`.
+pub struct PooledEvm<'p>(Checkout<'p, EVM<'static>>);
+
+impl Deref for PooledEvm<'_> {
+    type Target = EVM<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledEvm<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// What the pool needs of what it keeps, so the pooling can be tested
+/// without a fork.
+trait Poolable: Sized {
+    /// Kept while idle, `EVM` itself isn't `Send`.
+    type Parts;
+
+    fn into_parts(self) -> Self::Parts;
+
+    fn from_parts(parts: Self::Parts) -> Self;
+
+    fn reset(&mut self) -> Result<()>;
+}
+
+impl Poolable for EVM<'static> {
+    type Parts = EvmParts;
+
+    fn into_parts(self) -> EvmParts {
+        EVM::into_parts(self)
+    }
+
+    fn from_parts(parts: EvmParts) -> Self {
+        EVM::from_parts(parts)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        EVM::reset(self)
+    }
+}
+
+struct Idle<P> {
+    parts: Vec<P>,
+    /// Checked out or idle, lowered when one fails to reset.
+    live: usize,
+}
+
+struct Slots<T: Poolable> {
+    idle: Mutex<Idle<T::Parts>>,
+    available: Condvar,
+}
+
+impl<T: Poolable> Slots<T> {
+    fn new(parts: Vec<T::Parts>) -> Self {
+        let live = parts.len();
+        Self { idle: Mutex::new(Idle { parts, live }), available: Condvar::new() }
+    }
+
+    fn checkout(&self) -> Result<Checkout<'_, T>> {
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|_| anyhow!("evm pool lock poisoned"))?;
+
+        loop {
+            if let Some(parts) = idle.parts.pop() {
+                return Ok(Checkout { slots: self, item: Some(T::from_parts(parts)) });
+            }
+            if idle.live == 0 {
+                return Err(anyhow!("evm pool is empty"));
+            }
+            idle = self
+                .available
+                .wait(idle)
+                .map_err(|_| anyhow!("evm pool lock poisoned"))?;
+        }
+    }
+
+    fn try_checkout(&self) -> Option<Checkout<'_, T>> {
+        let parts = self.idle.lock().ok()?.parts.pop()?;
+        Some(Checkout { slots: self, item: Some(T::from_parts(parts)) })
+    }
+
+    fn size(&self) -> usize {
+        self.idle.lock().map(|idle| idle.live).unwrap_or_default()
+    }
+
+    fn give_back(&self, item: Option<T>) {
+        let Ok(mut idle) = self.idle.lock() else {
+            return;
+        };
+
+        match item {
+            Some(item) => {
+                idle.parts.push(item.into_parts());
+                self.available.notify_one();
+            }
+            // Waiters must fail if that was the last one.
+            None => {
+                idle.live -= 1;
+                self.available.notify_all();
+            }
+        }
+    }
+}
+
+struct Checkout<'p, T: Poolable> {
+    slots: &'p Slots<T>,
+    item: Option<T>,
+}
+
+impl<T: Poolable> Deref for Checkout<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item
+            .as_ref()
+            .expect("pooled evm is only taken on drop")
+    }
+}
+
+impl<T: Poolable> DerefMut for Checkout<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item
+            .as_mut()
+            .expect("pooled evm is only taken on drop")
+    }
+}
+
+impl<T: Poolable> Drop for Checkout<'_, T> {
+    fn drop(&mut self) {
+        let Some(mut item) = self.item.take() else {
+            return;
+        };
+
+        // An EVM whose state can't be reset would leak it into the next
+        // simulation, it is dropped instead.
+        let item = match item.reset() {
+            Ok(()) => Some(item),
+            Err(e) => {
+                error!("failed to reset pooled evm, dropping it. error={e:?}");
+                None
+            }
+        };
+
+        self.slots.give_back(item);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Long enough for a waiting thread to be blocked on the condvar.
+    const WAIT: Duration = Duration::from_millis(50);
+
+    #[derive(Default)]
+    struct FakeEvm {
+        /// Set by a simulation, cleared by `reset`.
+        dirty: bool,
+        resets: usize,
+        fail_reset: bool,
+    }
+
+    impl Poolable for FakeEvm {
+        type Parts = FakeEvm;
+
+        fn into_parts(self) -> FakeEvm {
+            self
+        }
+
+        fn from_parts(parts: FakeEvm) -> Self {
+            parts
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            if self.fail_reset {
+                return Err(anyhow!("reset failed"));
+            }
+            self.dirty = false;
+            self.resets += 1;
+            Ok(())
+        }
+    }
+
+    fn slots(size: usize) -> Slots<FakeEvm> {
+        Slots::new((0..size).map(|_| FakeEvm::default()).collect())
+    }
+
+    #[test]
+    fn dropped_checkout_is_reset_and_returned() -> Result<()> {
+        let slots = slots(1);
+
+        let mut evm = slots.checkout()?;
+        evm.dirty = true;
+        assert!(slots.try_checkout().is_none());
+        drop(evm);
+
+        let evm = slots.checkout()?;
+        assert!(!evm.dirty);
+        assert_eq!(evm.resets, 1);
+        assert_eq!(slots.size(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn checkout_waits_for_a_returned_evm() -> Result<()> {
+        let slots = slots(1);
+        let held = slots.checkout()?;
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| slots.checkout().map(|evm| evm.resets));
+            thread::sleep(WAIT);
+            assert!(!waiter.is_finished());
+
+            drop(held);
+            assert_eq!(waiter.join().unwrap()?, 1);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failed_reset_drops_the_evm() -> Result<()> {
+        let slots = slots(2);
+
+        let mut evm = slots.checkout()?;
+        evm.fail_reset = true;
+        drop(evm);
+
+        assert_eq!(slots.size(), 1);
+        let _last = slots.checkout()?;
+        assert!(slots.try_checkout().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn waiters_fail_once_the_last_evm_is_dropped() -> Result<()> {
+        let slots = slots(1);
+        let mut evm = slots.checkout()?;
+        evm.fail_reset = true;
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| slots.checkout().map(|_| ()));
+            thread::sleep(WAIT);
+            assert!(!waiter.is_finished());
+
+            drop(evm);
+            let err = waiter.join().unwrap().unwrap_err();
+            assert_eq!(err.to_string(), "evm pool is empty");
+        });
+        assert_eq!(slots.size(), 0);
+
+        Ok(())
+    }
+}
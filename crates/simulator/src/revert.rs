@@ -0,0 +1,88 @@
+use alloy::primitives::{Address, Bytes};
+use alloy_sol_types::{Panic, Revert, SolError};
+use anyhow::{anyhow, Result};
+use revm::primitives::ExecutionResult;
+
+/// Decodes a Solidity `Error(string)` revert into its reason string, or a
+/// `Panic(uint256)` into a description of the panic code.
+pub fn decode_revert(output: &Bytes) -> Option<String> {
+    if let Ok(revert) = Revert::abi_decode(output, false) {
+        return Some(revert.reason);
+    }
+
+    Panic::abi_decode(output, false)
+        .ok()
+        .map(|panic| panic.to_string())
+}
+
+/// Decoded revert reason for error messages, falling back to the raw output.
+pub fn revert_reason_or_output(output: &Bytes) -> String {
+    decode_revert(output).unwrap_or_else(|| output.to_string())
+}
+
+/// Output of a successful call to `contract`. A revert or halt is an error
+/// named after `function`, with the decoded revert reason.
+pub fn call_output(result: ExecutionResult, function: &str, contract: Address) -> Result<Bytes> {
+    match result {
+        ExecutionResult::Success { output, .. } => Ok(output.into_data()),
+        ExecutionResult::Revert { gas_used, output } => Err(anyhow!(
+            "{} reverted. contract={}, gas_used={}, reason={}",
+            function,
+            contract,
+            gas_used,
+            revert_reason_or_output(&output)
+        )),
+        ExecutionResult::Halt { reason, gas_used } => Err(anyhow!(
+            "{} halted. contract={}, gas_used={}, reason={:?}",
+            function,
+            contract,
+            gas_used,
+            reason
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use revm::primitives::{HaltReason, OutOfGasError, Output, SuccessReason};
+
+    use super::*;
+
+    const CONTRACT: Address = Address::repeat_byte(0x11);
+
+    #[test]
+    fn success_returns_the_output() {
+        let result = ExecutionResult::Success {
+            reason: SuccessReason::Return,
+            gas_used: 21_000,
+            gas_refunded: 0,
+            logs: vec![],
+            output: Output::Call(Bytes::from_static(&[1, 2])),
+        };
+
+        assert_eq!(call_output(result, "swap", CONTRACT).unwrap(), Bytes::from_static(&[1, 2]));
+    }
+
+    #[test]
+    fn revert_is_an_error_with_the_reason() {
+        let output = Revert { reason: "STF".into() }.abi_encode().into();
+        let result = ExecutionResult::Revert { gas_used: 30_000, output };
+
+        let err = call_output(result, "swap", CONTRACT).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("swap reverted. contract={CONTRACT}, gas_used=30000, reason=STF")
+        );
+    }
+
+    #[test]
+    fn halt_is_an_error() {
+        let result = ExecutionResult::Halt {
+            reason: HaltReason::OutOfGas(OutOfGasError::Basic),
+            gas_used: 50_000,
+        };
+
+        let err = call_output(result, "swap", CONTRACT).unwrap_err();
+        assert!(err.to_string().starts_with("swap halted."));
+    }
+}
@@ -0,0 +1,88 @@
+//! Hardfork selection from mainnet activation blocks, or from a reth chain
+//! spec for other chains.
+
+use reth_chainspec::{ChainSpec, EthereumHardfork};
+use revm::primitives::SpecId;
+
+/// Mainnet activation block of each hardfork, latest first.
+const MAINNET_FORKS: [(u64, SpecId); 17] = [
+    (19_426_587, SpecId::CANCUN),
+    (17_034_870, SpecId::SHANGHAI),
+    (15_537_394, SpecId::MERGE),
+    (15_050_000, SpecId::GRAY_GLACIER),
+    (13_773_000, SpecId::ARROW_GLACIER),
+    (12_965_000, SpecId::LONDON),
+    (12_244_000, SpecId::BERLIN),
+    (9_200_000, SpecId::MUIR_GLACIER),
+    (9_069_000, SpecId::ISTANBUL),
+    (7_280_000, SpecId::PETERSBURG),
+    (4_370_000, SpecId::BYZANTIUM),
+    (2_675_000, SpecId::SPURIOUS_DRAGON),
+    (2_463_000, SpecId::TANGERINE),
+    (1_920_000, SpecId::DAO_FORK),
+    (1_150_000, SpecId::HOMESTEAD),
+    (200_000, SpecId::FRONTIER_THAWING),
+    (0, SpecId::FRONTIER),
+];
+
+/// Hardfork rules active on mainnet at `block_number`.
+///
+/// Stops at Cancun: Prague is still a draft in this revm version, so later
+/// blocks run with Cancun rules.
+pub fn spec_id_from_block_number(block_number: u64) -> SpecId {
+    MAINNET_FORKS
+        .iter()
+        .find(|(activation, _)| block_number >= *activation)
+        .map_or(SpecId::FRONTIER, |(_, spec_id)| *spec_id)
+}
+
+/// Block and timestamp activated hardforks, latest first. Paris activates on
+/// total difficulty and is handled separately.
+const CHAIN_FORKS: [(EthereumHardfork, SpecId); 15] = [
+    (EthereumHardfork::Cancun, SpecId::CANCUN),
+    (EthereumHardfork::Shanghai, SpecId::SHANGHAI),
+    (EthereumHardfork::GrayGlacier, SpecId::GRAY_GLACIER),
+    (EthereumHardfork::ArrowGlacier, SpecId::ARROW_GLACIER),
+    (EthereumHardfork::London, SpecId::LONDON),
+    (EthereumHardfork::Berlin, SpecId::BERLIN),
+    (EthereumHardfork::MuirGlacier, SpecId::MUIR_GLACIER),
+    (EthereumHardfork::Istanbul, SpecId::ISTANBUL),
+    (EthereumHardfork::Petersburg, SpecId::PETERSBURG),
+    (EthereumHardfork::Constantinople, SpecId::CONSTANTINOPLE),
+    (EthereumHardfork::Byzantium, SpecId::BYZANTIUM),
+    (EthereumHardfork::SpuriousDragon, SpecId::SPURIOUS_DRAGON),
+    (EthereumHardfork::Tangerine, SpecId::TANGERINE),
+    (EthereumHardfork::Dao, SpecId::DAO_FORK),
+    (EthereumHardfork::Homestead, SpecId::HOMESTEAD),
+];
+
+/// Hardfork rules active on `chain_spec` at the given block, capped at Cancun
+/// like `spec_id_from_block_number`.
+pub fn spec_id_for_chain(chain_spec: &ChainSpec, block_number: u64, timestamp: u64) -> SpecId {
+    let is_active = |fork: EthereumHardfork| {
+        let condition = chain_spec.fork(fork);
+        condition.active_at_block(block_number) || condition.active_at_timestamp(timestamp)
+    };
+
+    for (fork, spec_id) in CHAIN_FORKS {
+        if is_active(fork) {
+            // Post-merge forks are all timestamp based, Paris sits between the
+            // last block based fork and Shanghai.
+            if spec_id < SpecId::MERGE && is_merged(chain_spec, block_number) {
+                return SpecId::MERGE;
+            }
+            return spec_id;
+        }
+    }
+
+    SpecId::FRONTIER
+}
+
+fn is_merged(chain_spec: &ChainSpec, block_number: u64) -> bool {
+    chain_spec
+        .fork(EthereumHardfork::Paris)
+        .active_at_block(block_number)
+        || chain_spec
+            .paris_block_and_final_difficulty
+            .is_some_and(|(paris_block, _)| block_number >= paris_block)
+}
@@ -0,0 +1,316 @@
+//! Call tracer for local simulations, producing the same shape as geth's
+//! `callTracer` (alloy's `CallFrame`) so it can be diffed against an on-chain
+//! trace.
+
+use alloy::primitives::{Address, Bytes, Log, B256, U256};
+use revm::interpreter::{
+    CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, Interpreter,
+    InterpreterResult,
+};
+use revm::primitives::CreateScheme;
+use revm::{Database, EvmContext, Inspector};
+
+use crate::revert::decode_revert;
+
+/// One call frame, mirrors alloy's `CallFrame`.
+#[derive(Debug, Clone, Default)]
+pub struct CallTrace {
+    /// `CALL`, `STATICCALL`, `DELEGATECALL`, `CALLCODE`, `CREATE`, `CREATE2`
+    /// or `SELFDESTRUCT`.
+    pub typ: String,
+    pub from: Address,
+    /// Created address for creates, `None` if the creation failed.
+    pub to: Option<Address>,
+    /// `None` for delegate and static calls, which move no value.
+    pub value: Option<U256>,
+    pub gas: u64,
+    pub gas_used: u64,
+    pub input: Bytes,
+    pub output: Bytes,
+    pub error: Option<String>,
+    pub revert_reason: Option<String>,
+    pub calls: Vec<CallTrace>,
+    pub logs: Vec<CallLog>,
+}
+
+/// Log emitted by a frame, mirrors alloy's `CallLogFrame`.
+#[derive(Debug, Clone, Default)]
+pub struct CallLog {
+    pub address: Address,
+    pub topics: Vec<B256>,
+    pub data: Bytes,
+    /// Number of subcalls of the frame made before the log was emitted.
+    pub position: u64,
+}
+
+/// Inspector building a `CallTrace` tree. Logs of reverted frames are kept,
+/// geth's `withLog` option drops them.
+#[derive(Debug, Default)]
+pub struct CallTracer {
+    stack: Vec<CallTrace>,
+    root: Option<CallTrace>,
+}
+
+impl CallTracer {
+    /// Root frame of the last transaction, `None` if nothing was executed.
+    pub fn into_trace(self) -> Option<CallTrace> {
+        self.root
+    }
+
+    fn start_frame(&mut self, frame: CallTrace) {
+        self.stack.push(frame);
+    }
+
+    fn end_frame(&mut self, result: &InterpreterResult, to: Option<Address>) {
+        let Some(mut frame) = self.stack.pop() else {
+            return;
+        };
+
+        frame.gas_used = result.gas.spent();
+
+        if result.result.is_ok() {
+            // The output of a create is the deployed code.
+            frame.output = result.output.clone();
+            if to.is_some() {
+                frame.to = to;
+            }
+        } else if result.result.is_revert() {
+            frame.output = result.output.clone();
+            frame.error = Some("execution reverted".to_string());
+            frame.revert_reason = decode_revert(&result.output);
+        } else {
+            frame.error = Some(format!("{:?}", result.result));
+        }
+
+        self.push_finished(frame);
+    }
+
+    fn push_finished(&mut self, frame: CallTrace) {
+        match self.stack.last_mut() {
+            Some(parent) => parent.calls.push(frame),
+            None => self.root = Some(frame),
+        }
+    }
+}
+
+fn call_type(scheme: CallScheme) -> &'static str {
+    match scheme {
+        CallScheme::Call | CallScheme::ExtCall => "CALL",
+        CallScheme::CallCode => "CALLCODE",
+        CallScheme::DelegateCall | CallScheme::ExtDelegateCall => "DELEGATECALL",
+        CallScheme::StaticCall | CallScheme::ExtStaticCall => "STATICCALL",
+    }
+}
+
+impl<DB: Database> Inspector<DB> for CallTracer {
+    fn log(&mut self, _interp: &mut Interpreter, _context: &mut EvmContext<DB>, log: &Log) {
+        if let Some(frame) = self.stack.last_mut() {
+            frame.logs.push(CallLog {
+                address: log.address,
+                topics: log.data.topics().to_vec(),
+                data: log.data.data.clone(),
+                position: frame.calls.len() as u64,
+            });
+        }
+    }
+
+    fn call(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        let value = match inputs.scheme {
+            CallScheme::StaticCall | CallScheme::ExtStaticCall => None,
+            _ => inputs.value.transfer(),
+        };
+
+        self.start_frame(CallTrace {
+            typ: call_type(inputs.scheme).to_string(),
+            from: inputs.caller,
+            to: Some(inputs.target_address),
+            value,
+            gas: inputs.gas_limit,
+            input: inputs.input.clone(),
+            ..Default::default()
+        });
+
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.end_frame(&outcome.result, None);
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let typ = match inputs.scheme {
+            CreateScheme::Create2 { .. } => "CREATE2",
+            _ => "CREATE",
+        };
+
+        self.start_frame(CallTrace {
+            typ: typ.to_string(),
+            from: inputs.caller,
+            value: Some(inputs.value),
+            gas: inputs.gas_limit,
+            input: inputs.init_code.clone(),
+            ..Default::default()
+        });
+
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.end_frame(&outcome.result, outcome.address);
+        outcome
+    }
+
+    fn selfdestruct(&mut self, contract: Address, target: Address, value: U256) {
+        if let Some(frame) = self.stack.last_mut() {
+            frame.calls.push(CallTrace {
+                typ: "SELFDESTRUCT".to_string(),
+                from: contract,
+                to: Some(target),
+                value: Some(value),
+                ..Default::default()
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_sol_types::{Revert, SolError};
+    use revm::db::{CacheDB, EmptyDB};
+    use revm::primitives::{AccountInfo, Bytecode, TransactTo};
+    use revm::{inspector_handle_register, Evm};
+
+    use super::*;
+
+    const CALLER: Address = Address::repeat_byte(0x01);
+    const PARENT: Address = Address::repeat_byte(0xaa);
+    const LOGGER: Address = Address::repeat_byte(0xbb);
+    const REVERTER: Address = Address::repeat_byte(0xcc);
+
+    /// `LOG1(0, 0, topic)`
+    fn log1(topic: u8) -> Vec<u8> {
+        vec![0x60, topic, 0x60, 0x00, 0x60, 0x00, 0xa1]
+    }
+
+    /// `POP(CALL(GAS, to, 0, 0, 0, 0, 0))`
+    fn call(to: Address) -> Vec<u8> {
+        let mut code = vec![0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x73];
+        code.extend_from_slice(to.as_slice());
+        code.extend([0x5a, 0xf1, 0x50]);
+        code
+    }
+
+    /// Copies the `Error(reason)` appended to the code and reverts with it.
+    fn revert_with(reason: &str) -> Vec<u8> {
+        let revert = Revert { reason: reason.into() }.abi_encode();
+        let len = revert.len() as u8;
+
+        let mut code = vec![0x60, len, 0x60, 0x0c, 0x60, 0x00, 0x39, 0x60, len, 0x60, 0x00, 0xfd];
+        code.extend(revert);
+        code
+    }
+
+    fn contract(code: Vec<u8>) -> AccountInfo {
+        AccountInfo::from_bytecode(Bytecode::new_legacy(code.into()))
+    }
+
+    /// The parent logs, calls a contract that logs, calls one that reverts
+    /// and logs again.
+    fn trace_parent() -> CallTrace {
+        let mut db = CacheDB::new(EmptyDB::default());
+        db.insert_account_info(
+            PARENT,
+            contract([log1(1), call(LOGGER), call(REVERTER), log1(2)].concat()),
+        );
+        db.insert_account_info(LOGGER, contract(log1(3)));
+        db.insert_account_info(REVERTER, contract(revert_with("nope")));
+
+        let mut evm = Evm::builder()
+            .with_db(db)
+            .with_external_context(CallTracer::default())
+            .modify_tx_env(|tx| {
+                tx.caller = CALLER;
+                tx.transact_to = TransactTo::Call(PARENT);
+                tx.gas_limit = 1_000_000;
+            })
+            .append_handler_register(inspector_handle_register)
+            .build();
+
+        assert!(evm.transact().unwrap().result.is_success());
+
+        std::mem::take(&mut evm.context.external)
+            .into_trace()
+            .unwrap()
+    }
+
+    #[test]
+    fn nested_calls_build_the_frame_tree() {
+        let root = trace_parent();
+
+        assert_eq!(root.typ, "CALL");
+        assert_eq!(root.from, CALLER);
+        assert_eq!(root.to, Some(PARENT));
+        assert_eq!(root.error, None);
+
+        let targets: Vec<_> = root.calls.iter().map(|frame| frame.to).collect();
+        assert_eq!(targets, [Some(LOGGER), Some(REVERTER)]);
+
+        for frame in &root.calls {
+            assert_eq!(frame.typ, "CALL");
+            assert_eq!(frame.from, PARENT);
+            assert_eq!(frame.value, Some(U256::ZERO));
+            assert!(frame.calls.is_empty());
+        }
+    }
+
+    #[test]
+    fn reverted_frame_keeps_the_reason() {
+        let root = trace_parent();
+
+        assert_eq!(root.calls[0].error, None);
+
+        let reverted = &root.calls[1];
+        assert_eq!(reverted.error.as_deref(), Some("execution reverted"));
+        assert_eq!(reverted.revert_reason.as_deref(), Some("nope"));
+        assert_eq!(reverted.output, Bytes::from(Revert { reason: "nope".into() }.abi_encode()));
+    }
+
+    #[test]
+    fn logs_are_positioned_between_subcalls() {
+        let root = trace_parent();
+
+        let logs: Vec<_> = root
+            .logs
+            .iter()
+            .map(|log| (log.topics[0], log.position))
+            .collect();
+        assert_eq!(logs, [(B256::with_last_byte(1), 0), (B256::with_last_byte(2), 2)]);
+        assert!(root.logs.iter().all(|log| log.address == PARENT));
+
+        let child = &root.calls[0].logs;
+        assert_eq!(child.len(), 1);
+        assert_eq!(
+            (child[0].address, child[0].topics[0], child[0].position),
+            (LOGGER, B256::with_last_byte(3), 0)
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use alloy::primitives::{Address, I256};
+use alloy_sol_types::SolCall;
+use anyhow::Result;
+use revm::primitives::{ExecutionResult, TransactTo, U256};
+use tracing::error;
+
+use crate::abi;
+use crate::evm::EVM;
+use crate::revert::revert_reason_or_output;
+
+#[derive(Debug, Clone)]
+pub struct LiquidationOutcome {
+    pub success: bool,
+    pub gas_used: u64,
+    /// Amount of collateral asset received by the owner.
+    pub collateral_received: U256,
+    /// Amount of debt asset spent by the owner.
+    pub debt_repaid: U256,
+    /// Collateral received minus debt repaid, valued in the Aave oracle base
+    /// currency (USD with 8 decimals on mainnet).
+    pub profit_base: I256,
+}
+
+pub trait AaveV3PoolContract {
+    fn health_factor(&mut self, pool: Address, user: Address) -> Result<U256>;
+
+    /// Liquidates `user` from the owner account and measures the outcome.
+    ///
+    /// The owner must already hold at least `debt_to_cover` of `debt_asset`.
+    /// The collateral is received as the underlying asset, not the aToken.
+    fn simulate_liquidation(
+        &mut self,
+        pool: Address,
+        collateral_asset: Address,
+        debt_asset: Address,
+        user: Address,
+        debt_to_cover: U256,
+    ) -> Result<LiquidationOutcome>;
+}
+
+fn value_in_base(evm: &mut EVM<'_>, oracle: Address, asset: Address, amount: U256) -> Result<I256> {
+    let encoded = abi::IAaveV3Oracle::getAssetPriceCall::new((asset,)).abi_encode();
+    let value = evm.static_call(oracle, encoded)?;
+    let price = abi::IAaveV3Oracle::getAssetPriceCall::abi_decode_returns(&value, false)?._0;
+
+    let encoded = abi::IERC20::decimalsCall::new(()).abi_encode();
+    let value = evm.static_call(asset, encoded)?;
+    let decimals = abi::IERC20::decimalsCall::abi_decode_returns(&value, false)?._0;
+
+    let value = amount * price / U256::from(10).pow(U256::from(decimals));
+
+    Ok(I256::try_from(value)?)
+}
+
+impl AaveV3PoolContract for EVM<'_> {
+    fn health_factor(&mut self, pool: Address, user: Address) -> Result<U256> {
+        let encoded = abi::IAaveV3Pool::getUserAccountDataCall::new((user,)).abi_encode();
+        let value = self.static_call(pool, encoded)?;
+
+        let result = abi::IAaveV3Pool::getUserAccountDataCall::abi_decode_returns(&value, false)?;
+
+        Ok(result.healthFactor)
+    }
+
+    fn simulate_liquidation(
+        &mut self,
+        pool: Address,
+        collateral_asset: Address,
+        debt_asset: Address,
+        user: Address,
+        debt_to_cover: U256,
+    ) -> Result<LiquidationOutcome> {
+        let owner = self.owner();
+
+        let collateral_before = self.get_token_balance(collateral_asset, owner)?.0;
+        let debt_before = self.get_token_balance(debt_asset, owner)?.0;
+
+        // Approve the pool to pull the debt asset.
+        self.approve_token(debt_asset, owner, pool, debt_to_cover)?;
+
+        let encoded = abi::IAaveV3Pool::liquidationCallCall::new((
+            collateral_asset,
+            debt_asset,
+            user,
+            debt_to_cover,
+            false,
+        ))
+        .abi_encode();
+
+        let tx_env = self.evm.tx_mut();
+        tx_env.transact_to = TransactTo::Call(pool);
+        tx_env.data = encoded.into();
+        tx_env.caller = owner;
+        tx_env.value = U256::ZERO;
+
+        let result = self.evm.transact_commit()?;
+        let gas_used = result.gas_used();
+
+        match result {
+            ExecutionResult::Halt { reason, gas_used } => {
+                error!("liquidation_call halted. gas_used={}, reason={:?}", gas_used, reason);
+            }
+            ExecutionResult::Revert { gas_used, output } => {
+                error!(
+                    "liquidation_call reverted. gas_used={}, reason={}",
+                    gas_used,
+                    revert_reason_or_output(&output)
+                );
+            }
+            ExecutionResult::Success { .. } => {
+                let collateral_after = self.get_token_balance(collateral_asset, owner)?.0;
+                let debt_after = self.get_token_balance(debt_asset, owner)?.0;
+
+                let collateral_received = collateral_after.saturating_sub(collateral_before);
+                let debt_repaid = debt_before.saturating_sub(debt_after);
+
+                let encoded = abi::IAaveV3Pool::ADDRESSES_PROVIDERCall::new(()).abi_encode();
+                let value = self.static_call(pool, encoded)?;
+                let provider =
+                    abi::IAaveV3Pool::ADDRESSES_PROVIDERCall::abi_decode_returns(&value, false)?._0;
+
+                let encoded =
+                    abi::IAaveV3AddressesProvider::getPriceOracleCall::new(()).abi_encode();
+                let value = self.static_call(provider, encoded)?;
+                let oracle = abi::IAaveV3AddressesProvider::getPriceOracleCall::abi_decode_returns(
+                    &value, false,
+                )?
+                ._0;
+
+                let profit_base =
+                    value_in_base(self, oracle, collateral_asset, collateral_received)?
+                        - value_in_base(self, oracle, debt_asset, debt_repaid)?;
+
+                return Ok(LiquidationOutcome {
+                    success: true,
+                    gas_used,
+                    collateral_received,
+                    debt_repaid,
+                    profit_base,
+                });
+            }
+        }
+
+        Ok(LiquidationOutcome {
+            success: false,
+            gas_used,
+            collateral_received: U256::ZERO,
+            debt_repaid: U256::ZERO,
+            profit_base: I256::ZERO,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+
+    /// Aave V3 Pool on mainnet.
+    const AAVE_V3_POOL: Address = address!("87870Bca3F3fD6335C3F4ce8392D69350B4fA4E2");
+
+    fn env_var<T: std::str::FromStr>(key: &str) -> T {
+        std::env::var(key)
+            .unwrap_or_else(|_| panic!("missing env. key={key}"))
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid env. key={key}"))
+    }
+
+    /// The position is read from the environment, it must be liquidatable
+    /// at `LIQUIDATION_BLOCK`:
+    ///
+    /// - `LIQUIDATION_BLOCK`
+    /// - `LIQUIDATION_USER`
+    /// - `LIQUIDATION_COLLATERAL_ASSET`
+    /// - `LIQUIDATION_DEBT_ASSET`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL and a liquidatable position"]
+    async fn simulate_liquidation_is_profitable() -> Result<()> {
+        let block_number: u64 = env_var("LIQUIDATION_BLOCK");
+        let user: Address = env_var("LIQUIDATION_USER");
+        let collateral_asset: Address = env_var("LIQUIDATION_COLLATERAL_ASSET");
+        let debt_asset: Address = env_var("LIQUIDATION_DEBT_ASSET");
+
+        let mut evm = EVM::from_env(block_number).await?;
+
+        let health_factor = evm.health_factor(AAVE_V3_POOL, user)?;
+        assert!(health_factor < U256::from(10_u64.pow(18)), "health_factor={health_factor}");
+
+        evm.fund_owner_token(debt_asset, U256::from(10_u128.pow(30)))?;
+
+        // `U256::MAX` covers as much debt as the close factor allows.
+        let outcome =
+            evm.simulate_liquidation(AAVE_V3_POOL, collateral_asset, debt_asset, user, U256::MAX)?;
+
+        assert!(outcome.success);
+        assert!(outcome.collateral_received > U256::ZERO);
+        assert!(outcome.debt_repaid > U256::ZERO);
+        // The liquidation bonus makes the collateral worth more than the debt.
+        assert!(outcome.profit_base > I256::ZERO, "profit_base={}", outcome.profit_base);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use alloy::primitives::{address, Address, Bytes, B256};
+use alloy_sol_types::SolCall;
+use anyhow::{anyhow, Result};
+use revm::primitives::U256;
+
+use crate::abi;
+use crate::evm::EVM;
+
+/// Balancer V2 Vault, same address on every chain.
+pub const BALANCER_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8");
+
+/// A Balancer pool id is the pool address followed by the specialization and
+/// a nonce.
+pub fn balancer_pool_address(pool_id: B256) -> Address {
+    Address::from_slice(&pool_id[..20])
+}
+
+pub trait BalancerPoolContract {
+    /// Returns the pool tokens and their balances held by the Vault.
+    fn pool_tokens(&mut self, pool_id: B256) -> Result<(Vec<Address>, Vec<U256>)>;
+
+    /// Amount of `token_out` received for `amount_in` of `token_in`.
+    ///
+    /// Uses the Vault's `queryBatchSwap`, which runs the swap and reverts
+    /// internally, so no state is committed.
+    fn query_swap(
+        &mut self,
+        pool_id: B256,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Result<U256>;
+}
+
+impl BalancerPoolContract for EVM<'_> {
+    fn pool_tokens(&mut self, pool_id: B256) -> Result<(Vec<Address>, Vec<U256>)> {
+        let encoded = abi::IBalancerVault::getPoolTokensCall::new((pool_id,)).abi_encode();
+        let value = self.static_call(BALANCER_VAULT, encoded)?;
+
+        let result = abi::IBalancerVault::getPoolTokensCall::abi_decode_returns(&value, false)?;
+
+        Ok((result.tokens, result.balances))
+    }
+
+    fn query_swap(
+        &mut self,
+        pool_id: B256,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+    ) -> Result<U256> {
+        let owner = self.owner();
+
+        let step = abi::IBalancerVault::BatchSwapStep {
+            poolId: pool_id,
+            assetInIndex: U256::ZERO,
+            assetOutIndex: U256::from(1),
+            amount: amount_in,
+            userData: Bytes::new(),
+        };
+        let funds = abi::IBalancerVault::FundManagement {
+            sender: owner,
+            fromInternalBalance: false,
+            recipient: owner,
+            toInternalBalance: false,
+        };
+
+        let encoded = abi::IBalancerVault::queryBatchSwapCall::new((
+            abi::IBalancerVault::SwapKind::GIVEN_IN,
+            vec![step],
+            vec![token_in, token_out],
+            funds,
+        ))
+        .abi_encode();
+        let value = self.static_call(BALANCER_VAULT, encoded)?;
+
+        let deltas =
+            abi::IBalancerVault::queryBatchSwapCall::abi_decode_returns(&value, false)?.assetDeltas;
+
+        // Deltas are from the Vault's point of view, the output is negative.
+        let delta_out = deltas
+            .get(1)
+            .ok_or_else(|| anyhow!("missing output delta. pool_id={}", pool_id))?;
+
+        Ok(U256::try_from(-*delta_out)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::b256;
+
+    use super::*;
+    use crate::evm::MAINNET_WETH;
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Balancer 80BAL/20WETH weighted pool.
+    const BAL_WETH_POOL_ID: B256 =
+        b256!("5c6ee304399dbdb9c8ef030ab642b10820db8f56000200000000000000000014");
+
+    const BAL: Address = address!("ba100000625a3754423978a60c9317c58a424e3D");
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn query_swap_matches_a_vault_swap() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        let owner = evm.owner();
+        let amount_in = U256::from(10_u64.pow(17));
+
+        assert_eq!(
+            balancer_pool_address(BAL_WETH_POOL_ID),
+            address!("5c6Ee304399DBdB9C8Ef030aB642B10820DB8F56")
+        );
+        let (tokens, balances) = evm.pool_tokens(BAL_WETH_POOL_ID)?;
+        assert_eq!(tokens, [BAL, MAINNET_WETH]);
+
+        let quoted = evm.query_swap(BAL_WETH_POOL_ID, MAINNET_WETH, BAL, amount_in)?;
+        assert!(quoted > U256::ZERO && quoted < balances[0]);
+
+        evm.wrap_eth(amount_in)?;
+        evm.approve_token(MAINNET_WETH, owner, BALANCER_VAULT, amount_in)?;
+
+        let encoded = abi::IBalancerVault::swapCall::new((
+            abi::IBalancerVault::SingleSwap {
+                poolId: BAL_WETH_POOL_ID,
+                kind: abi::IBalancerVault::SwapKind::GIVEN_IN,
+                assetIn: MAINNET_WETH,
+                assetOut: BAL,
+                amount: amount_in,
+                userData: Bytes::new(),
+            },
+            abi::IBalancerVault::FundManagement {
+                sender: owner,
+                fromInternalBalance: false,
+                recipient: owner,
+                toInternalBalance: false,
+            },
+            quoted,
+            U256::MAX,
+        ))
+        .abi_encode();
+        let output = evm.commit_call("swap", BALANCER_VAULT, encoded, U256::ZERO)?;
+
+        let amount_out = abi::IBalancerVault::swapCall::abi_decode_returns(&output, false)?;
+        assert_eq!(amount_out.amountCalculated, quoted);
+        assert_eq!(evm.get_token_balance(BAL, owner)?.0, quoted);
+
+        Ok(())
+    }
+}
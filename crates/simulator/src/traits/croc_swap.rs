@@ -0,0 +1,149 @@
+use alloy::primitives::Address;
+use alloy_sol_types::SolCall;
+use anyhow::{anyhow, Result};
+use revm::primitives::U256;
+
+use crate::abi;
+use crate::evm::EVM;
+
+pub trait CrocSwapDexContract {
+    /// Swaps on a CrocSwap (Ambient) pool from the owner account and returns
+    /// `(base_flow, quote_flow)`.
+    ///
+    /// Flows are from the pool's point of view: positive amounts are paid by
+    /// the owner, negative amounts are received. `qty` is denominated in base
+    /// if `in_base_qty` is set, in quote otherwise. The paid token is approved
+    /// to the dex, or sent as value when the base is native ETH.
+    #[allow(clippy::too_many_arguments)]
+    fn croc_swap(
+        &mut self,
+        dex: Address,
+        base: Address,
+        quote: Address,
+        pool_idx: U256,
+        is_buy: bool,
+        in_base_qty: bool,
+        qty: u128,
+        limit_price: u128,
+        min_out: u128,
+    ) -> Result<(i128, i128)>;
+}
+
+impl CrocSwapDexContract for EVM<'_> {
+    fn croc_swap(
+        &mut self,
+        dex: Address,
+        base: Address,
+        quote: Address,
+        pool_idx: U256,
+        is_buy: bool,
+        in_base_qty: bool,
+        qty: u128,
+        limit_price: u128,
+        min_out: u128,
+    ) -> Result<(i128, i128)> {
+        let owner = self.owner();
+
+        // A buy pays base for quote, a sell pays quote for base.
+        let token_in = if is_buy { base } else { quote };
+
+        let value = if token_in.is_zero() {
+            if !in_base_qty {
+                return Err(anyhow!("native base input requires qty in base. dex={}", dex));
+            }
+            U256::from(qty)
+        } else {
+            self.approve_token(token_in, owner, dex, U256::MAX)?;
+            U256::ZERO
+        };
+
+        let encoded = abi::CrocSwapDex::swapCall::new((
+            base,
+            quote,
+            pool_idx,
+            is_buy,
+            in_base_qty,
+            qty,
+            0,
+            limit_price,
+            min_out,
+            0,
+        ))
+        .abi_encode();
+
+        let output = self.commit_call("croc swap", dex, encoded, value)?;
+
+        let result = abi::CrocSwapDex::swapCall::abi_decode_returns(&output, false)?;
+
+        Ok((result.baseFlow, result.quoteFlow))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Ambient (CrocSwap) dex on mainnet.
+    const CROC_SWAP_DEX: Address = address!("AaAaAAAaA24eEeb8d57D431224f73832bC34f688");
+
+    /// Standard Ambient pool index.
+    const POOL_IDX: u64 = 420;
+
+    /// `CurveMath.MAX_SQRT_PRICE`, no price limit for a buy.
+    const MAX_SQRT_PRICE: u128 = 21267430153580247136652501917186561137;
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn buying_usdc_with_native_eth() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        let owner = evm.owner();
+        let qty = 10_u128.pow(17);
+
+        let (base_flow, quote_flow) = evm.croc_swap(
+            CROC_SWAP_DEX,
+            Address::ZERO,
+            USDC,
+            U256::from(POOL_IDX),
+            true,
+            true,
+            qty,
+            MAX_SQRT_PRICE,
+            0,
+        )?;
+
+        assert_eq!(base_flow, qty as i128);
+        assert!(quote_flow < 0, "quote_flow={quote_flow}");
+        assert_eq!(evm.get_token_balance(USDC, owner)?.0, U256::from(quote_flow.unsigned_abs()));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn native_eth_must_be_paid_in_base_qty() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+
+        let result = evm.croc_swap(
+            CROC_SWAP_DEX,
+            Address::ZERO,
+            USDC,
+            U256::from(POOL_IDX),
+            true,
+            false,
+            1_000_000,
+            MAX_SQRT_PRICE,
+            0,
+        );
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use alloy::primitives::Address;
+use alloy_sol_types::SolCall;
+use anyhow::Result;
+use revm::primitives::U256;
+
+use crate::abi;
+use crate::evm::EVM;
+
+pub trait CurveV2PoolContract {
+    /// Amount of coin `j` received for `dx` of coin `i`.
+    fn get_dy(&mut self, pool: Address, i: U256, j: U256, dx: U256) -> Result<U256>;
+
+    /// Swaps `dx` of coin `i` for coin `j` from the owner account.
+    ///
+    /// The owner must already hold `dx` of coin `i`, the pool is approved for
+    /// exactly that amount. Returns the balance change of coin `j` measured on
+    /// the owner.
+    fn exchange(&mut self, pool: Address, i: U256, j: U256, dx: U256, min_dy: U256)
+        -> Result<U256>;
+}
+
+fn coin(evm: &mut EVM<'_>, pool: Address, index: U256) -> Result<Address> {
+    let encoded = abi::ICurveV2Pool::coinsCall::new((index,)).abi_encode();
+    let value = evm.static_call(pool, encoded)?;
+
+    Ok(abi::ICurveV2Pool::coinsCall::abi_decode_returns(&value, false)?._0)
+}
+
+impl CurveV2PoolContract for EVM<'_> {
+    fn get_dy(&mut self, pool: Address, i: U256, j: U256, dx: U256) -> Result<U256> {
+        let encoded = abi::ICurveV2Pool::get_dyCall::new((i, j, dx)).abi_encode();
+        let value = self.static_call(pool, encoded)?;
+
+        Ok(abi::ICurveV2Pool::get_dyCall::abi_decode_returns(&value, false)?._0)
+    }
+
+    fn exchange(
+        &mut self,
+        pool: Address,
+        i: U256,
+        j: U256,
+        dx: U256,
+        min_dy: U256,
+    ) -> Result<U256> {
+        let owner = self.owner();
+
+        let token_in = coin(self, pool, i)?;
+        let token_out = coin(self, pool, j)?;
+
+        self.approve_token(token_in, owner, pool, dx)?;
+
+        let balance_before = self.get_token_balance(token_out, owner)?.0;
+
+        let encoded = abi::ICurveV2Pool::exchangeCall::new((i, j, dx, min_dy)).abi_encode();
+
+        self.commit_call("exchange", pool, encoded, U256::ZERO)?;
+
+        let balance_after = self.get_token_balance(token_out, owner)?.0;
+
+        Ok(balance_after.saturating_sub(balance_before))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+    use crate::evm::MAINNET_WETH;
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Curve tricrypto2 on mainnet, coins are USDT, WBTC and WETH.
+    const TRICRYPTO2: Address = address!("D51a44d3FaE010294C616388b506AcdA1bfAAE46");
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn exchange_pays_out_get_dy() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        let (usdt, weth) = (U256::ZERO, U256::from(2));
+        let dx = U256::from(10_u64.pow(17));
+
+        assert_eq!(coin(&mut evm, TRICRYPTO2, weth)?, MAINNET_WETH);
+        evm.wrap_eth(dx)?;
+
+        let dy = evm.get_dy(TRICRYPTO2, weth, usdt, dx)?;
+        assert!(dy > U256::ZERO);
+        assert_eq!(evm.exchange(TRICRYPTO2, weth, usdt, dx, dy)?, dy);
+
+        Ok(())
+    }
+}
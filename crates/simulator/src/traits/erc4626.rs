@@ -0,0 +1,160 @@
+use alloy::primitives::{Address, I256};
+use alloy_sol_types::SolCall;
+use anyhow::{anyhow, Result};
+use revm::primitives::U256;
+
+use crate::abi;
+use crate::evm::EVM;
+use crate::traits::UniswapV3PoolContract;
+use crate::uniswap_v3_math::quote_exact_input_single;
+
+#[derive(Debug, Clone)]
+pub struct VaultArbitrageQuote {
+    /// `true` when assets are deposited into the vault and the shares sold on
+    /// the pool, `false` when shares are bought on the pool and redeemed.
+    pub deposit: bool,
+    pub amount_in: U256,
+    /// Shares received from the vault or the pool.
+    pub shares: U256,
+    /// Assets received from the pool or the vault.
+    pub amount_out: U256,
+    pub profit: I256,
+}
+
+pub trait ERC4626VaultContract {
+    fn asset(&mut self, vault: Address) -> Result<Address>;
+
+    fn preview_deposit(&mut self, vault: Address, assets: U256) -> Result<U256>;
+
+    fn preview_redeem(&mut self, vault: Address, shares: U256) -> Result<U256>;
+
+    fn convert_to_shares(&mut self, vault: Address, assets: U256) -> Result<U256>;
+
+    fn convert_to_assets(&mut self, vault: Address, shares: U256) -> Result<U256>;
+
+    /// Quotes an arbitrage between the vault exchange rate and a V3 pool
+    /// trading the vault shares against the underlying asset.
+    ///
+    /// With `deposit`, `amount_in` assets are deposited and the shares sold on
+    /// the pool. Otherwise `amount_in` assets buy shares on the pool, which are
+    /// then redeemed. Nothing is committed: the vault side uses the `preview*`
+    /// methods and the pool side the offline V3 quoter.
+    fn vault_arbitrage(
+        &mut self,
+        pool: Address,
+        vault: Address,
+        deposit: bool,
+        amount_in: U256,
+    ) -> Result<VaultArbitrageQuote>;
+}
+
+impl ERC4626VaultContract for EVM<'_> {
+    fn asset(&mut self, vault: Address) -> Result<Address> {
+        let encoded = abi::IERC4626::assetCall::new(()).abi_encode();
+        let value = self.static_call(vault, encoded)?;
+
+        Ok(abi::IERC4626::assetCall::abi_decode_returns(&value, false)?._0)
+    }
+
+    fn preview_deposit(&mut self, vault: Address, assets: U256) -> Result<U256> {
+        let encoded = abi::IERC4626::previewDepositCall::new((assets,)).abi_encode();
+        let value = self.static_call(vault, encoded)?;
+
+        Ok(abi::IERC4626::previewDepositCall::abi_decode_returns(&value, false)?._0)
+    }
+
+    fn preview_redeem(&mut self, vault: Address, shares: U256) -> Result<U256> {
+        let encoded = abi::IERC4626::previewRedeemCall::new((shares,)).abi_encode();
+        let value = self.static_call(vault, encoded)?;
+
+        Ok(abi::IERC4626::previewRedeemCall::abi_decode_returns(&value, false)?._0)
+    }
+
+    fn convert_to_shares(&mut self, vault: Address, assets: U256) -> Result<U256> {
+        let encoded = abi::IERC4626::convertToSharesCall::new((assets,)).abi_encode();
+        let value = self.static_call(vault, encoded)?;
+
+        Ok(abi::IERC4626::convertToSharesCall::abi_decode_returns(&value, false)?._0)
+    }
+
+    fn convert_to_assets(&mut self, vault: Address, shares: U256) -> Result<U256> {
+        let encoded = abi::IERC4626::convertToAssetsCall::new((shares,)).abi_encode();
+        let value = self.static_call(vault, encoded)?;
+
+        Ok(abi::IERC4626::convertToAssetsCall::abi_decode_returns(&value, false)?._0)
+    }
+
+    fn vault_arbitrage(
+        &mut self,
+        pool: Address,
+        vault: Address,
+        deposit: bool,
+        amount_in: U256,
+    ) -> Result<VaultArbitrageQuote> {
+        let asset = self.asset(vault)?;
+        let (token0, token1) = self.pool_tokens(pool)?;
+
+        let shares_is_token0 = if token0 == vault && token1 == asset {
+            true
+        } else if token0 == asset && token1 == vault {
+            false
+        } else {
+            return Err(anyhow!(
+                "pool does not trade the vault against its asset. pool={}, vault={}",
+                pool,
+                vault
+            ));
+        };
+
+        let (shares, amount_out) = if deposit {
+            let shares = self.preview_deposit(vault, amount_in)?;
+            let amount_out = quote_exact_input_single(self, pool, shares_is_token0, shares)?;
+            (shares, amount_out)
+        } else {
+            let shares = quote_exact_input_single(self, pool, !shares_is_token0, amount_in)?;
+            let amount_out = self.preview_redeem(vault, shares)?;
+            (shares, amount_out)
+        };
+
+        let profit = I256::try_from(amount_out)? - I256::try_from(amount_in)?;
+
+        Ok(VaultArbitrageQuote { deposit, amount_in, shares, amount_out, profit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Maker's Savings Dai vault.
+    const SDAI: Address = address!("83F20F44975D03b1b09e64809B757c47f942BEeA");
+
+    const DAI: Address = address!("6B175474E89094C44Da98b954EedeAC495271d0F");
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn previews_follow_the_exchange_rate() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        let assets = U256::from(1_000) * U256::from(10_u64.pow(18));
+
+        assert_eq!(evm.asset(SDAI)?, DAI);
+
+        // sDAI charges no fee, so previews equal the conversions.
+        let shares = evm.preview_deposit(SDAI, assets)?;
+        assert_eq!(shares, evm.convert_to_shares(SDAI, assets)?);
+        // The DSR has accrued since launch, a share is worth more than a DAI.
+        assert!(shares < assets);
+
+        let redeemed = evm.preview_redeem(SDAI, shares)?;
+        assert_eq!(redeemed, evm.convert_to_assets(SDAI, shares)?);
+        // Both conversions round down.
+        assert!(redeemed <= assets && assets - redeemed <= U256::from(2));
+
+        Ok(())
+    }
+}
@@ -1,5 +1,17 @@
+pub mod aave_v3;
+pub mod balancer;
+pub mod croc_swap;
+pub mod curve_v2;
+pub mod erc4626;
 pub mod simulator;
+pub mod uniswap_v2;
 pub mod uniswap_v3;
 
+pub use aave_v3::AaveV3PoolContract;
+pub use balancer::BalancerPoolContract;
+pub use croc_swap::CrocSwapDexContract;
+pub use curve_v2::CurveV2PoolContract;
+pub use erc4626::ERC4626VaultContract;
 pub use simulator::SimulatorContract;
-pub use uniswap_v3::UniswapV3PoolContract;
+pub use uniswap_v2::UniswapV2PairContract;
+pub use uniswap_v3::{TickInfo, UniswapV3PoolContract};
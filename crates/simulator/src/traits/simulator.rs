@@ -1,44 +1,332 @@
-use alloy::primitives::Address;
+use alloy::primitives::{Address, Bytes, I256};
 use alloy_sol_types::SolCall;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use revm::primitives::{ExecutionResult, TransactTo, U256};
 use tracing::error;
 
 use crate::abi;
 use crate::evm::EVM;
+use crate::revert::{decode_revert, revert_reason_or_output};
+use crate::types::SimOutcome;
+
+/// A `SimOutcome` with the reason the call failed.
+#[derive(Debug, Clone)]
+pub struct FlashswapOutcome {
+    pub outcome: SimOutcome,
+    /// Decoded `Error(string)` reason, or the halt reason.
+    pub revert_reason: Option<String>,
+}
 
 pub trait SimulatorContract {
-    fn flashswap_lst_arbitrage(&mut self, pool: Address, zfo: bool, amount_in: U256) -> Result<()>;
+    /// Calls the simulator from the owner with arbitrary calldata, for custom
+    /// helpers injected with `EVMBuilder::simulator_bytecode` or
+    /// `EVM::set_simulator_address`. State changes are kept only if `commit`
+    /// is set, a revert or halt is reported in the `SimOutcome`.
+    fn call_simulator(&mut self, calldata: Bytes, commit: bool) -> Result<SimOutcome>;
+
+    /// Runs and commits the arbitrage. A reverted or halted call is not an
+    /// error, check `SimOutcome::success`.
+    fn flashswap_lst_arbitrage(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<SimOutcome>;
+
+    /// Same as `flashswap_lst_arbitrage` without committing, to check whether
+    /// the arbitrage would succeed while leaving the EVM untouched.
+    fn flashswap_lst_arbitrage_preview(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<SimOutcome>;
+
+    /// Same as `flashswap_lst_arbitrage`, but a reverted or halted call is
+    /// returned as an error.
+    fn flashswap_lst_arbitrage_checked(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<SimOutcome>;
+
+    /// Same as `flashswap_lst_arbitrage`, but returns why the call failed
+    /// instead of only logging it.
+    fn flashswap_lst_arbitrage_verbose(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<FlashswapOutcome>;
+
+    /// Same as `flashswap_lst_arbitrage`, but the call fails unless the
+    /// simulator gains at least `min_out` WETH, like a slippage check would on
+    /// chain. A failed call leaves the state untouched and is reported with
+    /// `SimOutcome::success` set to `false`.
+    ///
+    /// The check runs around the call since the deployed simulator takes no
+    /// min out.
+    fn flashswap_lst_arbitrage_with_min_out(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+        min_out: U256,
+    ) -> Result<SimOutcome>;
+
+    /// Backtests the arbitrage by repinning to each block and returns the WETH
+    /// profit of the simulator per block.
+    ///
+    /// The EVM is repinned to its original block afterwards, also when a block
+    /// fails, which wipes any state modified before the call.
+    fn simulate_at_multiple_blocks(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+        blocks: &[u64],
+    ) -> Result<Vec<(u64, I256)>>;
 }
 
-impl SimulatorContract for EVM<'_> {
-    fn flashswap_lst_arbitrage(&mut self, pool: Address, zfo: bool, amount_in: U256) -> Result<()> {
-        let owner = self.owner();
-        let simulator = self.simulator();
+fn transact_flashswap(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    zfo: bool,
+    amount_in: U256,
+    commit: bool,
+) -> Result<ExecutionResult> {
+    let owner = evm.owner();
+    let simulator = evm.require_simulator()?;
 
-        let encoded =
-            abi::Simulator::flashswapLstArbitrageCall::new((pool, zfo, amount_in)).abi_encode();
+    let encoded =
+        abi::Simulator::flashswapLstArbitrageCall::new((pool, zfo, amount_in)).abi_encode();
 
-        let evm = &mut self.evm;
+    let tx_env = evm.evm.tx_mut();
+    tx_env.transact_to = TransactTo::Call(simulator);
+    tx_env.data = encoded.into();
+    tx_env.caller = owner;
+    tx_env.value = U256::ZERO;
+
+    evm.transact_tx_env(commit)
+}
 
-        let tx_env = evm.tx_mut();
-        tx_env.transact_to = TransactTo::Call(simulator);
-        tx_env.data = encoded.into();
-        tx_env.caller = owner;
-        tx_env.value = U256::ZERO;
+impl SimulatorContract for EVM<'_> {
+    fn call_simulator(&mut self, calldata: Bytes, commit: bool) -> Result<SimOutcome> {
+        let simulator = self.require_simulator()?;
+        let owner = self.owner();
 
-        let result = evm.transact_commit()?;
+        self.call(simulator, calldata, owner, U256::ZERO, commit)
+    }
 
-        match result {
+    fn flashswap_lst_arbitrage(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<SimOutcome> {
+        let result = transact_flashswap(self, pool, zfo, amount_in, true)?;
+
+        match &result {
             ExecutionResult::Halt { reason, gas_used } => {
-                error!("transfer_token halted. gas_used={}, reason={:?}", gas_used, reason);
+                error!(
+                    "flashswap_lst_arbitrage halted. gas_used={}, reason={:?}",
+                    gas_used, reason
+                );
             }
             ExecutionResult::Revert { gas_used, output } => {
-                error!("transfer_token reverted. gas_used={}, output={}", gas_used, output);
+                error!(
+                    "flashswap_lst_arbitrage reverted. gas_used={}, reason={}",
+                    gas_used,
+                    revert_reason_or_output(output)
+                );
             }
             _ => {}
         }
 
+        Ok(result.into())
+    }
+
+    fn flashswap_lst_arbitrage_preview(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<SimOutcome> {
+        Ok(transact_flashswap(self, pool, zfo, amount_in, false)?.into())
+    }
+
+    fn flashswap_lst_arbitrage_with_min_out(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+        min_out: U256,
+    ) -> Result<SimOutcome> {
+        let weth = self.weth();
+        let simulator = self.require_simulator()?;
+
+        let snapshot_id = self.snapshot();
+
+        let result = (|| {
+            let balance_before = self.get_token_balance(weth, simulator)?.0;
+            let outcome = self.flashswap_lst_arbitrage(pool, zfo, amount_in)?;
+            let balance_after = self.get_token_balance(weth, simulator)?.0;
+
+            Ok::<_, anyhow::Error>((outcome, balance_after.saturating_sub(balance_before)))
+        })();
+
+        let (mut outcome, amount_out) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.revert(snapshot_id)?;
+                return Err(e);
+            }
+        };
+
+        if outcome.success && amount_out < min_out {
+            error!(
+                "flashswap_lst_arbitrage below min out. amount_out={}, min_out={}",
+                amount_out, min_out
+            );
+            outcome.success = false;
+            outcome.output = Bytes::new();
+        }
+
+        if outcome.success {
+            self.discard_snapshot(snapshot_id)?;
+        } else {
+            self.revert(snapshot_id)?;
+        }
+
+        Ok(outcome)
+    }
+
+    fn flashswap_lst_arbitrage_checked(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<SimOutcome> {
+        let result = transact_flashswap(self, pool, zfo, amount_in, true)?;
+
+        match result {
+            ExecutionResult::Halt { reason, gas_used } => Err(anyhow!(
+                "flashswap_lst_arbitrage halted. gas_used={}, reason={:?}",
+                gas_used,
+                reason
+            )),
+            ExecutionResult::Revert { gas_used, output } => Err(anyhow!(
+                "flashswap_lst_arbitrage reverted. gas_used={}, reason={}",
+                gas_used,
+                revert_reason_or_output(&output)
+            )),
+            result => Ok(result.into()),
+        }
+    }
+
+    fn flashswap_lst_arbitrage_verbose(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+    ) -> Result<FlashswapOutcome> {
+        let result = transact_flashswap(self, pool, zfo, amount_in, true)?;
+
+        let revert_reason = match &result {
+            ExecutionResult::Success { .. } => None,
+            ExecutionResult::Revert { output, .. } => decode_revert(output),
+            ExecutionResult::Halt { reason, .. } => Some(format!("{:?}", reason)),
+        };
+
+        Ok(FlashswapOutcome { outcome: result.into(), revert_reason })
+    }
+
+    fn simulate_at_multiple_blocks(
+        &mut self,
+        pool: Address,
+        zfo: bool,
+        amount_in: U256,
+        blocks: &[u64],
+    ) -> Result<Vec<(u64, I256)>> {
+        let original_block = self.block_number();
+        let weth = self.weth();
+        let simulator = self.require_simulator()?;
+
+        let profits = blocks
+            .iter()
+            .map(|&block_number| -> Result<(u64, I256)> {
+                self.repin(block_number)?;
+
+                let balance_before = self.get_token_balance(weth, simulator)?.0;
+                let flashswap = self.flashswap_lst_arbitrage_verbose(pool, zfo, amount_in)?;
+                let balance_after = self.get_token_balance(weth, simulator)?.0;
+
+                if !flashswap.outcome.success {
+                    error!(
+                        "flashswap_lst_arbitrage failed. block_number={}, reason={:?}",
+                        block_number, flashswap.revert_reason
+                    );
+                }
+
+                let profit = I256::try_from(balance_after)? - I256::try_from(balance_before)?;
+                Ok((block_number, profit))
+            })
+            .collect::<Result<Vec<_>>>();
+
+        // Even if a block failed, so the EVM isn't left pinned to it.
+        let repinned = self.repin(original_block);
+
+        let profits = profits?;
+        repinned?;
+
+        Ok(profits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+    use crate::traits::UniswapV3PoolContract;
+
+    /// Block and pool of the `lst-mev` example.
+    const BLOCK: u64 = 18732930;
+    const POOL: Address = address!("DeBead39628F93905dfc3E88003af40bf11189b0");
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn simulate_at_multiple_blocks_reports_each_block() -> Result<()> {
+        let mut evm = EVM::from_env(BLOCK).await?;
+        let zfo = evm.zero_for_one(POOL, evm.weth())?;
+
+        // A day apart, the pool price has moved.
+        let blocks = [BLOCK, BLOCK - 7200];
+        let profits =
+            evm.simulate_at_multiple_blocks(POOL, zfo, U256::from(10_u64.pow(18)), &blocks)?;
+
+        assert_eq!(profits.iter().map(|(block, _)| *block).collect::<Vec<_>>(), blocks);
+        assert_ne!(profits[0].1, profits[1].1);
+        assert_eq!(evm.block_number(), BLOCK);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn simulate_at_multiple_blocks_repins_on_error() -> Result<()> {
+        let mut evm = EVM::from_env(BLOCK).await?;
+        let zfo = evm.zero_for_one(POOL, evm.weth())?;
+
+        // Not mined yet, nothing can be fetched at it.
+        let blocks = [BLOCK - 1, u64::MAX];
+        let result =
+            evm.simulate_at_multiple_blocks(POOL, zfo, U256::from(10_u64.pow(18)), &blocks);
+
+        assert!(result.is_err());
+        assert_eq!(evm.block_number(), BLOCK);
+
         Ok(())
     }
 }
@@ -0,0 +1,149 @@
+use alloy::primitives::{Address, Bytes};
+use alloy_sol_types::SolCall;
+use anyhow::{anyhow, Result};
+use revm::primitives::U256;
+
+use crate::abi;
+use crate::evm::EVM;
+use crate::uniswap_v2_math::{get_amount_out, V2_FEE_BPS};
+
+pub trait UniswapV2PairContract {
+    /// Returns `(reserve0, reserve1)` of the pair.
+    fn get_reserves(&mut self, pair: Address) -> Result<(U256, U256)>;
+
+    /// Output of swapping `amount_in` of `token_in` on the pair, computed from
+    /// the reserves with the 0.3% fee. Nothing is executed.
+    fn quote_v2(&mut self, pair: Address, token_in: Address, amount_in: U256) -> Result<U256>;
+
+    /// Sends `amount_in` of `token_in` from the owner to the pair and swaps it
+    /// for the other token, paid out to `recipient`.
+    ///
+    /// The output amount is computed from the reserves with the 0.3% fee, and
+    /// the returned value is the balance change measured on `recipient`.
+    fn swap_v2_exact_in(
+        &mut self,
+        pair: Address,
+        token_in: Address,
+        amount_in: U256,
+        recipient: Address,
+    ) -> Result<U256>;
+}
+
+/// Returns whether `token_in` is token0 of the pair, and the other token.
+fn pair_direction(evm: &mut EVM<'_>, pair: Address, token_in: Address) -> Result<(bool, Address)> {
+    let encoded = abi::IUniswapV2Pair::token0Call::new(()).abi_encode();
+    let value = evm.static_call(pair, encoded)?;
+    let token0 = abi::IUniswapV2Pair::token0Call::abi_decode_returns(&value, false)?._0;
+
+    let encoded = abi::IUniswapV2Pair::token1Call::new(()).abi_encode();
+    let value = evm.static_call(pair, encoded)?;
+    let token1 = abi::IUniswapV2Pair::token1Call::abi_decode_returns(&value, false)?._0;
+
+    if token_in == token0 {
+        Ok((true, token1))
+    } else if token_in == token1 {
+        Ok((false, token0))
+    } else {
+        Err(anyhow!("token not in pair. pair={}, token={}", pair, token_in))
+    }
+}
+
+impl UniswapV2PairContract for EVM<'_> {
+    fn get_reserves(&mut self, pair: Address) -> Result<(U256, U256)> {
+        let encoded = abi::IUniswapV2Pair::getReservesCall::new(()).abi_encode();
+        let value = self.static_call(pair, encoded)?;
+
+        let result = abi::IUniswapV2Pair::getReservesCall::abi_decode_returns(&value, false)?;
+
+        Ok((U256::from(result.reserve0), U256::from(result.reserve1)))
+    }
+
+    fn quote_v2(&mut self, pair: Address, token_in: Address, amount_in: U256) -> Result<U256> {
+        let (zero_for_one, _) = pair_direction(self, pair, token_in)?;
+
+        let (reserve0, reserve1) = self.get_reserves(pair)?;
+        if reserve0.is_zero() || reserve1.is_zero() {
+            return Err(anyhow!("pair has no reserves. pair={}", pair));
+        }
+
+        let (reserve_in, reserve_out) =
+            if zero_for_one { (reserve0, reserve1) } else { (reserve1, reserve0) };
+
+        Ok(get_amount_out(amount_in, reserve_in, reserve_out, V2_FEE_BPS))
+    }
+
+    fn swap_v2_exact_in(
+        &mut self,
+        pair: Address,
+        token_in: Address,
+        amount_in: U256,
+        recipient: Address,
+    ) -> Result<U256> {
+        let (zero_for_one, token_out) = pair_direction(self, pair, token_in)?;
+        let amount_out = self.quote_v2(pair, token_in, amount_in)?;
+
+        let (amount0_out, amount1_out) =
+            if zero_for_one { (U256::ZERO, amount_out) } else { (amount_out, U256::ZERO) };
+
+        let balance_before = self.get_token_balance(token_out, recipient)?.0;
+
+        let encoded = abi::IERC20::transferCall::new((pair, amount_in)).abi_encode();
+        self.commit_call("transfer", token_in, encoded, U256::ZERO)?;
+
+        let encoded =
+            abi::IUniswapV2Pair::swapCall::new((amount0_out, amount1_out, recipient, Bytes::new()))
+                .abi_encode();
+        self.commit_call("swap", pair, encoded, U256::ZERO)?;
+
+        let balance_after = self.get_token_balance(token_out, recipient)?.0;
+
+        Ok(balance_after.saturating_sub(balance_before))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+    use crate::evm::MAINNET_WETH;
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Uniswap V2 USDC/WETH, USDC is token0.
+    const USDC_WETH_PAIR: Address = address!("B4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc");
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn swap_pays_out_the_reserves_quote() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+        let owner = evm.owner();
+        let amount_in = U256::from(10_u64.pow(17));
+
+        evm.wrap_eth(amount_in)?;
+
+        let quoted = evm.quote_v2(USDC_WETH_PAIR, MAINNET_WETH, amount_in)?;
+        assert!(quoted > U256::ZERO);
+
+        assert_eq!(evm.swap_v2_exact_in(USDC_WETH_PAIR, MAINNET_WETH, amount_in, owner)?, quoted);
+        assert_eq!(evm.get_token_balance(USDC, owner)?.0, quoted);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn quote_rejects_a_token_outside_the_pair() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+
+        assert!(evm
+            .quote_v2(USDC_WETH_PAIR, Address::ZERO, U256::from(1))
+            .is_err());
+        assert!(evm.quote_v2(USDC_WETH_PAIR, USDC, U256::from(1_000_000))? > U256::ZERO);
+
+        Ok(())
+    }
+}
@@ -1,67 +1,184 @@
+use alloy::primitives::aliases::I24;
 use alloy::primitives::Address;
 use alloy_sol_types::SolCall;
 use anyhow::{anyhow, Result};
-use revm::primitives::{ExecutionResult, Output, TransactTo, U256};
+use revm::primitives::U256;
 
 use crate::abi;
 use crate::evm::EVM;
+use crate::uniswap_v3_math::next_initialized_tick_within_one_word;
+
+/// Decoded `ticks(int24)` of a V3 pool.
+#[derive(Debug, Clone)]
+pub struct TickInfo {
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+    pub fee_growth_outside0_x128: U256,
+    pub fee_growth_outside1_x128: U256,
+    pub tick_cumulative_outside: i64,
+    pub seconds_per_liquidity_outside_x128: U256,
+    pub seconds_outside: u32,
+    pub initialized: bool,
+}
 
 pub trait UniswapV3PoolContract {
     fn token0(&mut self, contract_address: Address) -> Result<Address>;
 
     fn token1(&mut self, contract_address: Address) -> Result<Address>;
+
+    /// `(token0, token1)`, read once per pool and cached on the EVM since
+    /// they never change.
+    fn pool_tokens(&mut self, contract_address: Address) -> Result<(Address, Address)>;
+
+    /// Whether swapping `token_in` on the pool goes from token0 to token1.
+    /// Fails if `token_in` isn't one of the pool's tokens.
+    fn zero_for_one(&mut self, contract_address: Address, token_in: Address) -> Result<bool>;
+
+    /// Returns `(sqrtPriceX96, tick)`.
+    fn slot0(&mut self, contract_address: Address) -> Result<(U256, i32)>;
+
+    fn liquidity(&mut self, contract_address: Address) -> Result<u128>;
+
+    fn fee(&mut self, contract_address: Address) -> Result<u32>;
+
+    fn tick_spacing(&mut self, contract_address: Address) -> Result<i32>;
+
+    fn tick_liquidity_net(&mut self, contract_address: Address, tick: i32) -> Result<i128>;
+
+    fn tick_bitmap(&mut self, contract_address: Address, word_position: i16) -> Result<U256>;
+
+    fn tick_info(&mut self, contract_address: Address, tick: i32) -> Result<TickInfo>;
+
+    /// Next initialized tick at or below (`lte`) or above `from_tick`,
+    /// searching a single bitmap word. Returns the word boundary and
+    /// `false` when the word has no initialized tick in that direction.
+    fn next_initialized_tick(
+        &mut self,
+        contract_address: Address,
+        from_tick: i32,
+        lte: bool,
+    ) -> Result<(i32, bool)>;
 }
 
 impl UniswapV3PoolContract for EVM<'_> {
     fn token0(&mut self, contract_address: Address) -> Result<Address> {
-        let owner = self.owner();
+        Ok(self.pool_tokens(contract_address)?.0)
+    }
+
+    fn token1(&mut self, contract_address: Address) -> Result<Address> {
+        Ok(self.pool_tokens(contract_address)?.1)
+    }
+
+    fn pool_tokens(&mut self, contract_address: Address) -> Result<(Address, Address)> {
+        if let Some(tokens) = self.pool_tokens_cache().get(&contract_address) {
+            return Ok(*tokens);
+        }
 
         let encoded = abi::IUniswapV3Pool::token0Call::new(()).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
+        let token0 = abi::IUniswapV3Pool::token0Call::abi_decode_returns(&value, false)?._0;
+
+        let encoded = abi::IUniswapV3Pool::token1Call::new(()).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
+        let token1 = abi::IUniswapV3Pool::token1Call::abi_decode_returns(&value, false)?._0;
+
+        self.pool_tokens_cache()
+            .insert(contract_address, (token0, token1));
 
-        let evm = &mut self.evm;
+        Ok((token0, token1))
+    }
+
+    fn zero_for_one(&mut self, contract_address: Address, token_in: Address) -> Result<bool> {
+        let (token0, token1) = self.pool_tokens(contract_address)?;
+
+        if token_in == token0 {
+            Ok(true)
+        } else if token_in == token1 {
+            Ok(false)
+        } else {
+            Err(anyhow!("token not in pool. pool={}, token={}", contract_address, token_in))
+        }
+    }
+
+    fn slot0(&mut self, contract_address: Address) -> Result<(U256, i32)> {
+        let encoded = abi::IUniswapV3Pool::slot0Call::new(()).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
 
-        let tx_env = evm.tx_mut();
-        tx_env.transact_to = TransactTo::Call(contract_address);
-        tx_env.data = encoded.into();
-        tx_env.caller = owner;
-        tx_env.value = U256::ZERO;
+        let result = abi::IUniswapV3Pool::slot0Call::abi_decode_returns(&value, false)?;
 
-        let ref_tx = evm.transact()?;
-        let result = ref_tx.result;
+        Ok((U256::from(result.sqrtPriceX96), i32::try_from(result.tick)?))
+    }
 
-        let value = match result {
-            ExecutionResult::Success { output: Output::Call(value), .. } => Ok(value),
-            _ => Err(anyhow!("failed to get token0. pool={}", contract_address)),
-        }?;
+    fn liquidity(&mut self, contract_address: Address) -> Result<u128> {
+        let encoded = abi::IUniswapV3Pool::liquidityCall::new(()).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
 
-        let result = abi::IUniswapV3Pool::token0Call::abi_decode_returns(&value, false)?;
+        let result = abi::IUniswapV3Pool::liquidityCall::abi_decode_returns(&value, false)?;
 
         Ok(result._0)
     }
 
-    fn token1(&mut self, contract_address: Address) -> Result<Address> {
-        let owner = self.owner();
+    fn fee(&mut self, contract_address: Address) -> Result<u32> {
+        let encoded = abi::IUniswapV3Pool::feeCall::new(()).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
 
-        let encoded = abi::IUniswapV3Pool::token1Call::new(()).abi_encode();
+        let result = abi::IUniswapV3Pool::feeCall::abi_decode_returns(&value, false)?;
 
-        let evm = &mut self.evm;
+        Ok(result._0.to::<u32>())
+    }
 
-        let tx_env = evm.tx_mut();
-        tx_env.transact_to = TransactTo::Call(contract_address);
-        tx_env.data = encoded.into();
-        tx_env.caller = owner;
-        tx_env.value = U256::ZERO;
+    fn tick_spacing(&mut self, contract_address: Address) -> Result<i32> {
+        let encoded = abi::IUniswapV3Pool::tickSpacingCall::new(()).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
 
-        let ref_tx = evm.transact()?;
-        let result = ref_tx.result;
+        let result = abi::IUniswapV3Pool::tickSpacingCall::abi_decode_returns(&value, false)?;
 
-        let value = match result {
-            ExecutionResult::Success { output: Output::Call(value), .. } => Ok(value),
-            _ => Err(anyhow!("failed to get token0. pool={}", contract_address)),
-        }?;
+        Ok(i32::try_from(result._0)?)
+    }
 
-        let result = abi::IUniswapV3Pool::token1Call::abi_decode_returns(&value, false)?;
+    fn tick_liquidity_net(&mut self, contract_address: Address, tick: i32) -> Result<i128> {
+        let encoded = abi::IUniswapV3Pool::ticksCall::new((I24::try_from(tick)?,)).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
+
+        let result = abi::IUniswapV3Pool::ticksCall::abi_decode_returns(&value, false)?;
+
+        Ok(result.liquidityNet)
+    }
+
+    fn tick_bitmap(&mut self, contract_address: Address, word_position: i16) -> Result<U256> {
+        let encoded = abi::IUniswapV3Pool::tickBitmapCall::new((word_position,)).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
+
+        let result = abi::IUniswapV3Pool::tickBitmapCall::abi_decode_returns(&value, false)?;
 
         Ok(result._0)
     }
+
+    fn tick_info(&mut self, contract_address: Address, tick: i32) -> Result<TickInfo> {
+        let encoded = abi::IUniswapV3Pool::ticksCall::new((I24::try_from(tick)?,)).abi_encode();
+        let value = self.static_call(contract_address, encoded)?;
+
+        let result = abi::IUniswapV3Pool::ticksCall::abi_decode_returns(&value, false)?;
+
+        Ok(TickInfo {
+            liquidity_gross: result.liquidityGross,
+            liquidity_net: result.liquidityNet,
+            fee_growth_outside0_x128: result.feeGrowthOutside0X128,
+            fee_growth_outside1_x128: result.feeGrowthOutside1X128,
+            tick_cumulative_outside: i64::try_from(result.tickCumulativeOutside)?,
+            seconds_per_liquidity_outside_x128: U256::from(result.secondsPerLiquidityOutsideX128),
+            seconds_outside: result.secondsOutside,
+            initialized: result.initialized,
+        })
+    }
+
+    fn next_initialized_tick(
+        &mut self,
+        contract_address: Address,
+        from_tick: i32,
+        lte: bool,
+    ) -> Result<(i32, bool)> {
+        let tick_spacing = self.tick_spacing(contract_address)?;
+        next_initialized_tick_within_one_word(self, contract_address, from_tick, tick_spacing, lte)
+    }
 }
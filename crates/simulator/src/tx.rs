@@ -0,0 +1,29 @@
+use alloy::consensus::Transaction;
+use alloy::primitives::{Address, U256};
+use revm::primitives::TxEnv;
+
+/// Sets every field of `tx_env` from a signed transaction sent by `caller`.
+///
+/// The nonce is left unset so transactions can be replayed out of their
+/// original context without failing revm's nonce check.
+pub fn fill_tx_env<T: Transaction>(tx_env: &mut TxEnv, caller: Address, tx: &T) {
+    tx_env.caller = caller;
+    tx_env.transact_to = tx.kind();
+    tx_env.value = tx.value();
+    tx_env.data = tx.input().clone();
+    tx_env.gas_limit = tx.gas_limit();
+    tx_env.gas_price = U256::from(tx.max_fee_per_gas());
+    tx_env.gas_priority_fee = tx.max_priority_fee_per_gas().map(U256::from);
+    tx_env.nonce = None;
+    tx_env.chain_id = tx.chain_id();
+    tx_env.access_list = tx
+        .access_list()
+        .map(|access_list| access_list.0.clone())
+        .unwrap_or_default();
+    tx_env.blob_hashes = tx
+        .blob_versioned_hashes()
+        .map(|hashes| hashes.to_vec())
+        .unwrap_or_default();
+    tx_env.max_fee_per_blob_gas = tx.max_fee_per_blob_gas().map(U256::from);
+    tx_env.authorization_list = None;
+}
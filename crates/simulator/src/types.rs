@@ -0,0 +1,91 @@
+use alloy::primitives::{Address, Bytes, B256, I256, U256};
+use revm::primitives::ExecutionResult;
+
+/// Result of a committed call, with the revert data kept for the caller.
+#[derive(Debug, Clone)]
+pub struct SimOutcome {
+    pub success: bool,
+    pub gas_used: u64,
+    /// Return data on success, revert data on revert and empty on halt.
+    pub output: Bytes,
+}
+
+impl From<ExecutionResult> for SimOutcome {
+    fn from(result: ExecutionResult) -> Self {
+        let success = result.is_success();
+        let gas_used = result.gas_used();
+        let output = result.into_output().unwrap_or_default();
+
+        Self { success, gas_used, output }
+    }
+}
+
+/// Gas settings applied to every transaction sent by the EVM helpers.
+///
+/// The default leaves the gas price at zero, so calls are free. Setting a fee
+/// charges `gas_used * effective_gas_price` to the caller's balance.
+#[derive(Debug, Clone, Default)]
+pub struct TxOptions {
+    /// Defaults to 30M gas when a fee is set, unlimited otherwise.
+    pub gas_limit: Option<u64>,
+    pub max_fee_per_gas: Option<u128>,
+    /// Only with `max_fee_per_gas`, which caps it.
+    pub max_priority_fee_per_gas: Option<u128>,
+}
+
+/// Manual block env values. `None` keeps the value from the pinned block's
+/// header.
+#[derive(Debug, Clone, Default)]
+pub struct BlockOverrides {
+    pub timestamp: Option<u64>,
+    pub basefee: Option<u64>,
+    pub gas_limit: Option<u64>,
+    pub coinbase: Option<Address>,
+    pub difficulty: Option<U256>,
+    pub prevrandao: Option<B256>,
+}
+
+/// Result of one transaction of a bundle, mirrors an `eth_callBundle` entry.
+#[derive(Debug, Clone)]
+pub struct BundleTxResult {
+    pub tx_hash: B256,
+    pub from: Address,
+    /// `None` for contract creations.
+    pub to: Option<Address>,
+    pub outcome: SimOutcome,
+    /// Effective gas price paid by the transaction.
+    pub gas_price: U256,
+    /// Priority fees paid to the coinbase, the base fee is burned.
+    pub gas_fees: U256,
+    /// Coinbase balance change, priority fees and direct transfers.
+    pub coinbase_diff: U256,
+    /// Direct transfers to the coinbase, `coinbase_diff - gas_fees`.
+    pub eth_sent_to_coinbase: U256,
+}
+
+/// Outcome of `EVM::call_bundle`, mirrors the `eth_callBundle` response.
+#[derive(Debug, Clone, Default)]
+pub struct BundleSimResult {
+    pub results: Vec<BundleTxResult>,
+    pub total_gas_used: u64,
+    /// Total miner payment of the bundle.
+    pub coinbase_diff: U256,
+    pub gas_fees: U256,
+    pub eth_sent_to_coinbase: U256,
+    /// `coinbase_diff / total_gas_used`, what builders rank bundles by.
+    pub bundle_gas_price: U256,
+}
+
+/// Outcome of a frontrun, victim, backrun sequence.
+#[derive(Debug, Clone)]
+pub struct SandwichResult {
+    pub frontrun: SimOutcome,
+    pub victim: SimOutcome,
+    pub backrun: SimOutcome,
+    /// `false` when the victim reverted, usually on its slippage check.
+    pub victim_success: bool,
+    /// WETH balance change of the frontrun caller.
+    pub weth_delta: I256,
+    /// ETH balance change of the frontrun caller, including gas paid.
+    pub eth_delta: I256,
+}
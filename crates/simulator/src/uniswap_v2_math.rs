@@ -0,0 +1,153 @@
+//! Offline Uniswap V2 constant-product quoter, mirroring `UniswapV2Library`.
+
+use alloy::primitives::U256;
+
+use crate::math::mul_div;
+
+/// Fee of Uniswap V2 pairs and most of its forks, in basis points.
+pub const V2_FEE_BPS: u32 = 30;
+
+const BPS_DENOMINATOR: u32 = 10_000;
+
+/// Mirrors `UniswapV2Library.getAmountOut` with a configurable fee
+/// (`fee_bps` = 30 for 0.3%).
+///
+/// Returns zero for empty reserves, a fee of 100% or more, or on overflow.
+pub fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> U256 {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return U256::ZERO;
+    }
+    if fee_bps >= BPS_DENOMINATOR {
+        return U256::ZERO;
+    }
+
+    let Some(amount_in_with_fee) = amount_in.checked_mul(U256::from(BPS_DENOMINATOR - fee_bps))
+    else {
+        return U256::ZERO;
+    };
+    let Some(denominator) = reserve_in
+        .checked_mul(U256::from(BPS_DENOMINATOR))
+        .and_then(|scaled| scaled.checked_add(amount_in_with_fee))
+    else {
+        return U256::ZERO;
+    };
+
+    mul_div(amount_in_with_fee, reserve_out, denominator).unwrap_or_default()
+}
+
+/// Mirrors `UniswapV2Library.getAmountIn`: the input needed to receive
+/// `amount_out`, rounded up.
+///
+/// Returns `U256::MAX` when `amount_out` can't be bought, i.e. it drains the
+/// reserve, and zero for empty reserves or a fee of 100% or more.
+pub fn get_amount_in(amount_out: U256, reserve_in: U256, reserve_out: U256, fee_bps: u32) -> U256 {
+    if amount_out.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return U256::ZERO;
+    }
+    if fee_bps >= BPS_DENOMINATOR {
+        return U256::ZERO;
+    }
+    if amount_out >= reserve_out {
+        return U256::MAX;
+    }
+
+    let Some(numerator) = reserve_in.checked_mul(U256::from(BPS_DENOMINATOR)) else {
+        return U256::MAX;
+    };
+    let denominator = (reserve_out - amount_out) * U256::from(BPS_DENOMINATOR - fee_bps);
+
+    mul_div(numerator, amount_out, denominator)
+        .and_then(|amount_in| amount_in.checked_add(U256::from(1)))
+        .unwrap_or(U256::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETH: u64 = 1_000_000_000_000_000_000;
+
+    fn eth(amount: u64) -> U256 {
+        U256::from(amount) * U256::from(ETH)
+    }
+
+    /// `(amount_in, reserve_in, reserve_out, amount_out)` in ETH, the swap test
+    /// cases of the `UniswapV2Pair` spec. Asking for one wei more reverts.
+    fn swap_cases() -> Vec<(U256, U256, U256, U256)> {
+        [
+            (1, 5, 10, "1662497915624478906"),
+            (1, 10, 5, "453305446940074565"),
+            (2, 5, 10, "2851015155847869602"),
+            (2, 10, 5, "831248957812239453"),
+            (1, 10, 10, "906610893880149131"),
+            (1, 100, 100, "987158034397061298"),
+            (1, 1000, 1000, "996006981039903216"),
+        ]
+        .into_iter()
+        .map(|(amount_in, reserve_in, reserve_out, amount_out)| {
+            (eth(amount_in), eth(reserve_in), eth(reserve_out), amount_out.parse().unwrap())
+        })
+        .collect()
+    }
+
+    #[test]
+    fn amount_out_matches_the_pair_spec() {
+        for (amount_in, reserve_in, reserve_out, amount_out) in swap_cases() {
+            assert_eq!(get_amount_out(amount_in, reserve_in, reserve_out, V2_FEE_BPS), amount_out);
+        }
+    }
+
+    #[test]
+    fn amount_in_buys_the_amount_out() {
+        for (amount_in, reserve_in, reserve_out, amount_out) in swap_cases() {
+            let needed = get_amount_in(amount_out, reserve_in, reserve_out, V2_FEE_BPS);
+
+            assert!(needed <= amount_in, "needed={needed}, amount_in={amount_in}");
+            assert!(get_amount_out(needed, reserve_in, reserve_out, V2_FEE_BPS) >= amount_out);
+            // Rounded up, one wei less isn't enough.
+            assert!(
+                get_amount_out(needed - U256::from(1), reserve_in, reserve_out, V2_FEE_BPS)
+                    < amount_out
+            );
+        }
+    }
+
+    #[test]
+    fn lower_fee_gives_more_out() {
+        let with_fee = get_amount_out(eth(1), eth(10), eth(10), V2_FEE_BPS);
+        let without_fee = get_amount_out(eth(1), eth(10), eth(10), 0);
+
+        assert!(without_fee > with_fee);
+        // 10 * 1 / 11 without a fee.
+        assert_eq!(without_fee, eth(10) / U256::from(11));
+    }
+
+    #[test]
+    fn empty_reserves_and_full_fee_quote_zero() {
+        assert_eq!(get_amount_out(U256::ZERO, eth(1), eth(1), V2_FEE_BPS), U256::ZERO);
+        assert_eq!(get_amount_out(eth(1), U256::ZERO, eth(1), V2_FEE_BPS), U256::ZERO);
+        assert_eq!(get_amount_out(eth(1), eth(1), U256::ZERO, V2_FEE_BPS), U256::ZERO);
+        assert_eq!(get_amount_out(eth(1), eth(1), eth(1), 10_000), U256::ZERO);
+
+        assert_eq!(get_amount_in(U256::ZERO, eth(1), eth(1), V2_FEE_BPS), U256::ZERO);
+        assert_eq!(get_amount_in(eth(1), U256::ZERO, eth(1), V2_FEE_BPS), U256::ZERO);
+        assert_eq!(get_amount_in(U256::from(1), eth(1), eth(1), 10_000), U256::ZERO);
+    }
+
+    #[test]
+    fn draining_the_reserve_needs_max() {
+        assert_eq!(get_amount_in(eth(10), eth(10), eth(10), V2_FEE_BPS), U256::MAX);
+        assert_eq!(get_amount_in(eth(11), eth(10), eth(10), V2_FEE_BPS), U256::MAX);
+    }
+
+    #[test]
+    fn overflow_does_not_panic() {
+        let huge = U256::MAX / U256::from(2);
+
+        assert_eq!(get_amount_out(U256::MAX, eth(1), eth(1), V2_FEE_BPS), U256::ZERO);
+        assert_eq!(get_amount_out(eth(1), U256::MAX, eth(1), V2_FEE_BPS), U256::ZERO);
+        // The intermediate product doesn't fit in 256 bits, the quote does.
+        assert!(get_amount_out(eth(1), eth(1), huge, V2_FEE_BPS) > U256::ZERO);
+        assert_eq!(get_amount_in(eth(1), U256::MAX, eth(2), V2_FEE_BPS), U256::MAX);
+    }
+}
@@ -0,0 +1,494 @@
+//! Offline Uniswap V3 quoter. Replays the pool's swap loop in Rust, reading
+//! `slot0`, liquidity and tick data from the fork as it goes, so no state is
+//! committed.
+
+use alloy::primitives::{Address, U256};
+use anyhow::{anyhow, Result};
+
+use crate::evm::EVM;
+use crate::math::{
+    get_amount0_delta, get_amount0_delta_rounding_up, get_amount1_delta,
+    get_amount1_delta_rounding_up, get_next_sqrt_price_from_input, mul_div, mul_div_rounding_up,
+    FEE_DENOMINATOR,
+};
+use crate::traits::UniswapV3PoolContract;
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = 887272;
+
+/// `getSqrtRatioAtTick(MIN_TICK)`
+pub const MIN_SQRT_RATIO: U256 = U256::from_limbs([4295128739, 0, 0, 0]);
+/// `getSqrtRatioAtTick(MAX_TICK)`
+pub const MAX_SQRT_RATIO: U256 =
+    U256::from_limbs([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+
+/// `1 / sqrt(1.0001)^(2^i)` as Q128.128, for bit `i` of the absolute tick.
+const TICK_RATIOS: [u128; 20] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e213a,
+    0xfff2e50f5f656932ef12357cf3c7fdcc,
+    0xffe5caca7e10e4e61c3624eaa0941cd0,
+    0xffcb9843d60f6159c9db58835c926644,
+    0xff973b41fa98c081472e6896dfb254c0,
+    0xff2ea16466c96a3843ec78b326b52861,
+    0xfe5dee046a99a2a811c461f1969c3053,
+    0xfcbe86c7900a88aedcffc83b479aa3a4,
+    0xf987a7253ac413176f2b074cf7815e54,
+    0xf3392b0822b70005940c7a398e4b70f3,
+    0xe7159475a2c29b7443b29c7fa6e889d9,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e5,
+    0x70d869a156d2a1b890bb3df62baf32f7,
+    0x31be135f97d08fd981231505542fcfa6,
+    0x9aa508b5b7a84e1c677de54f3e99bc9,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe98,
+    0x48a170391f7dc42444e8fa2,
+];
+
+/// Mirrors `TickMath.getSqrtRatioAtTick`.
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return Err(anyhow!("tick out of range. tick={}", tick));
+    }
+
+    let abs_tick = tick.unsigned_abs();
+
+    let mut ratio =
+        if abs_tick & 1 != 0 { U256::from(TICK_RATIOS[0]) } else { U256::from(1) << 128 };
+
+    for (i, tick_ratio) in TICK_RATIOS.iter().enumerate().skip(1) {
+        if abs_tick & (1 << i) != 0 {
+            ratio = (ratio * U256::from(*tick_ratio)) >> 128;
+        }
+    }
+
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Q128.128 to Q64.96, rounding up.
+    let round_up = if ratio % (U256::from(1) << 32) == U256::ZERO { 0 } else { 1 };
+
+    Ok((ratio >> 32) + U256::from(round_up))
+}
+
+/// Greatest tick whose sqrt ratio is less than or equal to `sqrt_price_x96`.
+///
+/// Equivalent to `TickMath.getTickAtSqrtRatio`, implemented as a binary search
+/// over `get_sqrt_ratio_at_tick`.
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32> {
+    if sqrt_price_x96 < MIN_SQRT_RATIO || sqrt_price_x96 >= MAX_SQRT_RATIO {
+        return Err(anyhow!("sqrt price out of range. sqrt_price_x96={}", sqrt_price_x96));
+    }
+
+    let mut lo = MIN_TICK;
+    let mut hi = MAX_TICK;
+
+    while lo < hi {
+        let mid = lo + (hi - lo + 1) / 2;
+        if get_sqrt_ratio_at_tick(mid)? <= sqrt_price_x96 {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    Ok(lo)
+}
+
+#[derive(Debug, Clone)]
+pub struct SwapStep {
+    pub sqrt_price_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// Mirrors `SwapMath.computeSwapStep` for exact input swaps.
+pub fn compute_swap_step(
+    sqrt_price_current_x96: U256,
+    sqrt_price_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: U256,
+    fee: u32,
+) -> Result<SwapStep> {
+    let overflow = || anyhow!("overflow in swap step");
+
+    let zero_for_one = sqrt_price_current_x96 >= sqrt_price_target_x96;
+
+    let amount_remaining_less_fee =
+        mul_div(amount_remaining, U256::from(FEE_DENOMINATOR - fee), U256::from(FEE_DENOMINATOR))
+            .ok_or_else(overflow)?;
+
+    let mut amount_in = if zero_for_one {
+        get_amount0_delta_rounding_up(sqrt_price_target_x96, sqrt_price_current_x96, liquidity)
+    } else {
+        get_amount1_delta_rounding_up(sqrt_price_current_x96, sqrt_price_target_x96, liquidity)
+    }
+    .ok_or_else(overflow)?;
+
+    let sqrt_price_next_x96 = if amount_remaining_less_fee >= amount_in {
+        sqrt_price_target_x96
+    } else {
+        get_next_sqrt_price_from_input(
+            sqrt_price_current_x96,
+            liquidity,
+            amount_remaining_less_fee,
+            zero_for_one,
+        )
+        .ok_or_else(overflow)?
+    };
+
+    let max = sqrt_price_next_x96 == sqrt_price_target_x96;
+
+    let amount_out = if zero_for_one {
+        if !max {
+            amount_in = get_amount0_delta_rounding_up(
+                sqrt_price_next_x96,
+                sqrt_price_current_x96,
+                liquidity,
+            )
+            .ok_or_else(overflow)?;
+        }
+        get_amount1_delta(sqrt_price_next_x96, sqrt_price_current_x96, liquidity)
+    } else {
+        if !max {
+            amount_in = get_amount1_delta_rounding_up(
+                sqrt_price_current_x96,
+                sqrt_price_next_x96,
+                liquidity,
+            )
+            .ok_or_else(overflow)?;
+        }
+        get_amount0_delta(sqrt_price_current_x96, sqrt_price_next_x96, liquidity)
+    }
+    .ok_or_else(overflow)?;
+
+    let fee_amount = if !max {
+        // The target was not reached, the rest of the input is taken as fee.
+        amount_remaining - amount_in
+    } else {
+        mul_div_rounding_up(amount_in, U256::from(fee), U256::from(FEE_DENOMINATOR - fee))
+            .ok_or_else(overflow)?
+    };
+
+    Ok(SwapStep { sqrt_price_next_x96, amount_in, amount_out, fee_amount })
+}
+
+/// Mirrors `TickBitmap.nextInitializedTickWithinOneWord`, reading the bitmap
+/// word from the pool. Returns the next tick and whether it is initialized.
+pub fn next_initialized_tick_within_one_word(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    tick: i32,
+    tick_spacing: i32,
+    lte: bool,
+) -> Result<(i32, bool)> {
+    let (word_position, _) = bitmap_position(tick, tick_spacing, lte);
+    let word = evm.tick_bitmap(pool, word_position)?;
+
+    Ok(next_initialized_tick_in_word(word, tick, tick_spacing, lte))
+}
+
+/// Compresses `tick` by `tick_spacing`, rounding towards negative infinity.
+fn compress(tick: i32, tick_spacing: i32) -> i32 {
+    let mut compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed -= 1;
+    }
+    compressed
+}
+
+/// Bitmap word and bit searched from `tick`. Searching to the right starts
+/// at the tick itself, to the left at the next one.
+fn bitmap_position(tick: i32, tick_spacing: i32, lte: bool) -> (i16, usize) {
+    let compressed = compress(tick, tick_spacing) + if lte { 0 } else { 1 };
+
+    ((compressed >> 8) as i16, compressed.rem_euclid(256) as usize)
+}
+
+/// The search of `next_initialized_tick_within_one_word` on an already read
+/// bitmap word.
+fn next_initialized_tick_in_word(
+    word: U256,
+    tick: i32,
+    tick_spacing: i32,
+    lte: bool,
+) -> (i32, bool) {
+    let (_, bit_position) = bitmap_position(tick, tick_spacing, lte);
+    let compressed = compress(tick, tick_spacing);
+
+    if lte {
+        // All the bits at or to the right of the current bit.
+        let mask =
+            (U256::from(1) << bit_position) - U256::from(1) + (U256::from(1) << bit_position);
+        let masked = word & mask;
+
+        let initialized = !masked.is_zero();
+        let next = if initialized {
+            let most_significant_bit = 255 - masked.leading_zeros() as i32;
+            (compressed - (bit_position as i32 - most_significant_bit)) * tick_spacing
+        } else {
+            (compressed - bit_position as i32) * tick_spacing
+        };
+
+        (next, initialized)
+    } else {
+        let compressed = compressed + 1;
+
+        // All the bits at or to the left of the current bit.
+        let mask = !((U256::from(1) << bit_position) - U256::from(1));
+        let masked = word & mask;
+
+        let initialized = !masked.is_zero();
+        let next = if initialized {
+            let least_significant_bit = masked.trailing_zeros() as i32;
+            (compressed + (least_significant_bit - bit_position as i32)) * tick_spacing
+        } else {
+            (compressed + (255 - bit_position as i32)) * tick_spacing
+        };
+
+        (next, initialized)
+    }
+}
+
+/// Quotes an exact input swap on a V3 pool without executing `swap`.
+///
+/// Tick data is pulled lazily from the fork, so only the bitmap words and
+/// ticks the swap actually crosses are fetched. The swap runs until the input
+/// is used up or the price hits the min/max sqrt ratio.
+pub fn quote_exact_input_single(
+    evm: &mut EVM<'_>,
+    pool: Address,
+    zero_for_one: bool,
+    amount_in: U256,
+) -> Result<U256> {
+    let (mut sqrt_price_x96, mut tick) = evm.slot0(pool)?;
+    let mut liquidity = evm.liquidity(pool)?;
+    let tick_spacing = evm.tick_spacing(pool)?;
+    let fee = evm.fee(pool)?;
+
+    let sqrt_price_limit_x96 =
+        if zero_for_one { MIN_SQRT_RATIO + U256::from(1) } else { MAX_SQRT_RATIO - U256::from(1) };
+
+    let mut amount_remaining = amount_in;
+    let mut amount_out = U256::ZERO;
+
+    while !amount_remaining.is_zero() && sqrt_price_x96 != sqrt_price_limit_x96 {
+        let (tick_next, initialized) =
+            next_initialized_tick_within_one_word(evm, pool, tick, tick_spacing, zero_for_one)?;
+        let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+
+        let sqrt_price_next_tick_x96 = get_sqrt_ratio_at_tick(tick_next)?;
+
+        let sqrt_price_target_x96 = if zero_for_one {
+            sqrt_price_next_tick_x96.max(sqrt_price_limit_x96)
+        } else {
+            sqrt_price_next_tick_x96.min(sqrt_price_limit_x96)
+        };
+
+        let step = compute_swap_step(
+            sqrt_price_x96,
+            sqrt_price_target_x96,
+            liquidity,
+            amount_remaining,
+            fee,
+        )?;
+
+        sqrt_price_x96 = step.sqrt_price_next_x96;
+        amount_remaining -= step.amount_in + step.fee_amount;
+        amount_out += step.amount_out;
+
+        if sqrt_price_x96 == sqrt_price_next_tick_x96 {
+            if initialized {
+                let mut liquidity_net = evm.tick_liquidity_net(pool, tick_next)?;
+                if zero_for_one {
+                    liquidity_net = -liquidity_net;
+                }
+
+                liquidity = liquidity
+                    .checked_add_signed(liquidity_net)
+                    .ok_or_else(|| anyhow!("invalid liquidity net. tick={}", tick_next))?;
+            }
+
+            tick = if zero_for_one { tick_next - 1 } else { tick_next };
+        } else {
+            tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+        }
+    }
+
+    Ok(amount_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+    use alloy::primitives::aliases::{U160, U24};
+    use alloy::sol;
+    use alloy::sol_types::SolCall;
+
+    use super::*;
+    use crate::evm::MAINNET_WETH;
+    use crate::math::Q96;
+
+    /// Initialized ticks of the `TickBitmap` spec, with a tick spacing of 1.
+    const INITIALIZED_TICKS: [i32; 9] = [-200, -55, -4, 70, 78, 84, 139, 240, 535];
+
+    fn bitmap_word(word_position: i16) -> U256 {
+        INITIALIZED_TICKS
+            .iter()
+            .filter(|tick| (*tick >> 8) as i16 == word_position)
+            .fold(U256::ZERO, |word, tick| word | U256::from(1) << tick.rem_euclid(256))
+    }
+
+    fn next_tick(tick: i32, lte: bool) -> (i32, bool) {
+        let (word_position, _) = bitmap_position(tick, 1, lte);
+        next_initialized_tick_in_word(bitmap_word(word_position), tick, 1, lte)
+    }
+
+    #[test]
+    fn sqrt_ratio_at_tick_matches_tick_math() -> Result<()> {
+        let max_sqrt_ratio: U256 = "1461446703485210103287273052203988822378723970342".parse()?;
+
+        assert_eq!(MIN_SQRT_RATIO, U256::from(4295128739_u64));
+        assert_eq!(MAX_SQRT_RATIO, max_sqrt_ratio);
+
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK)?, MIN_SQRT_RATIO);
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK)?, MAX_SQRT_RATIO);
+        assert_eq!(get_sqrt_ratio_at_tick(0)?, Q96);
+
+        assert!(get_sqrt_ratio_at_tick(MIN_TICK - 1).is_err());
+        assert!(get_sqrt_ratio_at_tick(MAX_TICK + 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tick_at_sqrt_ratio_matches_tick_math() -> Result<()> {
+        assert_eq!(get_tick_at_sqrt_ratio(MIN_SQRT_RATIO)?, MIN_TICK);
+        assert_eq!(get_tick_at_sqrt_ratio(MAX_SQRT_RATIO - U256::from(1))?, MAX_TICK - 1);
+        assert_eq!(get_tick_at_sqrt_ratio(Q96)?, 0);
+
+        assert!(get_tick_at_sqrt_ratio(MIN_SQRT_RATIO - U256::from(1)).is_err());
+        assert!(get_tick_at_sqrt_ratio(MAX_SQRT_RATIO).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tick_at_sqrt_ratio_inverts_sqrt_ratio_at_tick() -> Result<()> {
+        for tick in [MIN_TICK + 1, -100_000, -60, -1, 1, 60, 100_000, MAX_TICK - 1] {
+            let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick)?;
+
+            assert_eq!(get_tick_at_sqrt_ratio(sqrt_price_x96)?, tick);
+            assert_eq!(get_tick_at_sqrt_ratio(sqrt_price_x96 - U256::from(1))?, tick - 1);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn compress_rounds_towards_negative_infinity() {
+        assert_eq!(compress(59, 60), 0);
+        assert_eq!(compress(60, 60), 1);
+        assert_eq!(compress(-1, 60), -1);
+        assert_eq!(compress(-60, 60), -1);
+        assert_eq!(compress(-61, 60), -2);
+    }
+
+    #[test]
+    fn next_initialized_tick_to_the_left() {
+        assert_eq!(next_tick(78, false), (84, true));
+        assert_eq!(next_tick(77, false), (78, true));
+        assert_eq!(next_tick(-55, false), (-4, true));
+        assert_eq!(next_tick(-56, false), (-55, true));
+        assert_eq!(next_tick(-257, false), (-200, true));
+        assert_eq!(next_tick(255, false), (511, false));
+        assert_eq!(next_tick(508, false), (511, false));
+        assert_eq!(next_tick(511, false), (535, true));
+    }
+
+    #[test]
+    fn next_initialized_tick_to_the_right() {
+        assert_eq!(next_tick(78, true), (78, true));
+        assert_eq!(next_tick(79, true), (78, true));
+        assert_eq!(next_tick(72, true), (70, true));
+        assert_eq!(next_tick(-55, true), (-55, true));
+        assert_eq!(next_tick(256, true), (256, false));
+        assert_eq!(next_tick(258, true), (256, false));
+        assert_eq!(next_tick(1023, true), (768, false));
+        assert_eq!(next_tick(-257, true), (-512, false));
+    }
+
+    #[test]
+    fn next_initialized_tick_scales_by_tick_spacing() {
+        // Tick 120 is initialized, tick -60 compresses to bit 255 of word -1.
+        let word = U256::from(1) << 2;
+
+        assert_eq!(next_initialized_tick_in_word(word, 60, 60, false), (120, true));
+        assert_eq!(next_initialized_tick_in_word(word, 179, 60, true), (120, true));
+        assert_eq!(next_initialized_tick_in_word(U256::ZERO, -1, 60, true), (-256 * 60, false));
+        assert_eq!(bitmap_position(-1, 60, true), (-1, 255));
+    }
+
+    /// First Cancun block is 19426587.
+    const CANCUN_BLOCK: u64 = 20_000_000;
+
+    /// Uniswap V3 USDC/WETH 0.05%, USDC is token0.
+    const USDC_WETH_POOL: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+
+    /// Uniswap V3 QuoterV2 on mainnet.
+    const QUOTER_V2: Address = address!("61fFE014bA17989E743c5F6cB21bF9697530B21e");
+
+    const USDC: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+
+    sol! {
+        contract IQuoterV2 {
+            struct QuoteExactInputSingleParams {
+                address tokenIn;
+                address tokenOut;
+                uint256 amountIn;
+                uint24 fee;
+                uint160 sqrtPriceLimitX96;
+            }
+
+            function quoteExactInputSingle(QuoteExactInputSingleParams memory params)
+                external
+                returns (
+                    uint256 amountOut,
+                    uint160 sqrtPriceX96After,
+                    uint32 initializedTicksCrossed,
+                    uint256 gasEstimate
+                );
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs RPC_HTTPS_URL"]
+    async fn quote_matches_quoter_v2_across_ticks() -> Result<()> {
+        let mut evm = EVM::from_env(CANCUN_BLOCK).await?;
+
+        let amount_in = U256::from(2_000) * U256::from(10).pow(U256::from(18));
+
+        let encoded =
+            IQuoterV2::quoteExactInputSingleCall::new((IQuoterV2::QuoteExactInputSingleParams {
+                tokenIn: MAINNET_WETH,
+                tokenOut: USDC,
+                amountIn: amount_in,
+                fee: U24::from(500),
+                sqrtPriceLimitX96: U160::ZERO,
+            },))
+            .abi_encode();
+        let value = evm.static_call(QUOTER_V2, encoded)?;
+        let quoted = IQuoterV2::quoteExactInputSingleCall::abi_decode_returns(&value, false)?;
+
+        assert!(quoted.initializedTicksCrossed > 1, "the swap must cross ticks");
+        assert_eq!(
+            quote_exact_input_single(&mut evm, USDC_WETH_POOL, false, amount_in)?,
+            quoted.amountOut
+        );
+
+        Ok(())
+    }
+}